@@ -48,8 +48,8 @@ pub async fn copilot_auth_token() -> Result<String> {
 pub async fn copilot_completion<R: Runtime>(
     path: SafePathBuf,
     pos: usize,
-    tab_width: u32,
-    use_tabs: bool,
+    tab_width: Option<u32>,
+    use_tabs: Option<bool>,
     app_handle: AppHandle<R>,
 ) -> Result<request::GetCompletionsResult> {
     let service = app_handle.state::<CopilotLspService<R>>();
@@ -6,7 +6,10 @@ use tauri::{AppHandle, Manager, Runtime};
 use tokio::sync::Mutex;
 
 use crate::{
-    editor::editor_state::{is_buffer, Delete, Document, EditorState, Insert, Language},
+    editor::{
+        editor_state::{is_buffer, Document, EditorState, Language, Transaction},
+        project_settings::{ProjectSettingsStore, DEFAULT_TAB_WIDTH},
+    },
     lsp::{
         registry::{LanguageServerId, LspRegistry},
         server::LspServer,
@@ -95,15 +98,26 @@ impl<R: Runtime> CopilotLspService<R> {
         let lsp_service = self.app_handle.state::<LspService<R>>();
 
         let maybe_doc = editor_state.get_document(path).await.ok();
+        if maybe_doc.as_ref().is_some_and(|d| d.large_file) {
+            debug!("Copilot - skip large file (path={:?})", path);
+            return Ok(());
+        }
+        if maybe_doc.as_ref().is_some_and(|d| self.is_disabled(d)) {
+            debug!("Copilot - disabled in project settings (path={:?})", path);
+            return Ok(());
+        }
+
         let worktree_path = maybe_doc.clone().and_then(|d| d.worktree_path);
         let language_server_id = Self::language_server_id(worktree_path);
 
         match lsp_registry
-            .register_language_server(&language_server_id)
+            .register_language_server(&language_server_id, None)
             .await?
         {
             (server, true) => {
-                let result = lsp_service.initialize(&server, &language_server_id).await?;
+                let result = lsp_service
+                    .initialize(&server, &language_server_id, None)
+                    .await?;
                 let _ = lsp_registry
                     .insert_language_server_config(&language_server_id, result)
                     .await;
@@ -124,28 +138,34 @@ impl<R: Runtime> CopilotLspService<R> {
         Ok(())
     }
 
-    pub async fn insert_document(&self, doc: &Document, data: &Insert) -> anyhow::Result<()> {
-        if !*self.enabled.lock().await {
+    pub async fn change_document(&self, tx: &Transaction) -> anyhow::Result<()> {
+        if !*self.enabled.lock().await || tx.doc.large_file || self.is_disabled(&tx.doc) {
+            return Ok(());
+        }
+
+        let lsp_service = self.app_handle.state::<LspService<R>>();
+        let language_server_id = Self::language_server_id(tx.doc.worktree_path.clone());
+        lsp_service.change_document(&language_server_id, tx).await
+    }
+
+    pub async fn close_document(&self, doc: &Document) -> anyhow::Result<()> {
+        if !*self.enabled.lock().await || doc.large_file || self.is_disabled(doc) {
             return Ok(());
         }
 
         let lsp_service = self.app_handle.state::<LspService<R>>();
         let language_server_id = Self::language_server_id(doc.worktree_path.clone());
-        lsp_service
-            .insert_document(&language_server_id, doc, data)
-            .await
+        lsp_service.close_document(&language_server_id, doc).await
     }
 
-    pub async fn delete_document(&self, doc: &Document, data: &Delete) -> anyhow::Result<()> {
-        if !*self.enabled.lock().await {
+    pub async fn update_document(&self, doc: &Document) -> anyhow::Result<()> {
+        if !*self.enabled.lock().await || doc.large_file || self.is_disabled(doc) {
             return Ok(());
         }
 
         let lsp_service = self.app_handle.state::<LspService<R>>();
         let language_server_id = Self::language_server_id(doc.worktree_path.clone());
-        lsp_service
-            .delete_document(&language_server_id, doc, data)
-            .await
+        lsp_service.update_document(&language_server_id, doc).await
     }
 
     pub async fn check_status(&self, server: &LspServer) -> anyhow::Result<request::SignInStatus> {
@@ -192,14 +212,28 @@ impl<R: Runtime> CopilotLspService<R> {
         &self,
         path: &Path,
         pos: usize,
-        tab_width: u32,
-        use_tabs: bool,
+        tab_width: Option<u32>,
+        use_tabs: Option<bool>,
     ) -> anyhow::Result<request::GetCompletionsResult> {
         let editor_state = self.app_handle.state::<EditorState>();
         let lsp_registry = self.app_handle.state::<LspRegistry>();
         let lsp_service = self.app_handle.state::<LspService<R>>();
 
         let doc = editor_state.get_document(path).await?;
+        let settings = self
+            .app_handle
+            .state::<ProjectSettingsStore>()
+            .resolve(&doc);
+        if !settings.copilot {
+            return Err(anyhow!("Copilot is disabled in project settings"));
+        }
+
+        // Tab settings of the project or EditorConfig win over the given ones
+        let tab_width = settings
+            .tab_width
+            .or(tab_width)
+            .unwrap_or(DEFAULT_TAB_WIDTH);
+        let use_tabs = settings.use_tabs.or(use_tabs).unwrap_or(false);
         let language_server_id = Self::language_server_id(doc.worktree_path.clone());
 
         lsp_service
@@ -248,6 +282,11 @@ impl<R: Runtime> CopilotLspService<R> {
         Ok(response)
     }
 
+    fn is_disabled(&self, doc: &Document) -> bool {
+        let project_settings = self.app_handle.state::<ProjectSettingsStore>();
+        !project_settings.resolve(doc).copilot
+    }
+
     fn language_server_id(worktree_path: Option<PathBuf>) -> LanguageServerId {
         match worktree_path {
             Some(path) => LanguageServerId(path.clone(), Language("copilot".to_string())),
@@ -1,12 +1,26 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use encoding_rs::Encoding;
+use serde::Serialize;
 use tauri::{path::SafePathBuf, Manager, Runtime};
+use tracing::error;
 
 use crate::{
     copilot::lsp_service::CopilotLspService,
     editor::editor_state::{Document, EditorState},
+    git::{command::refresh_status, repo::git_dir},
     lsp::service::LspService,
 };
 
-use super::editor_state::{Delete, Insert, UpdateDocument};
+use super::editor_state::{
+    is_buffer, Change, Delete, EditError, Insert, Lines, Transaction, UndoResult, UpdateDocument,
+};
+use super::file_format::FileFormat;
+use super::history::LocalHistory;
+use super::journal::{Journal, JournalEntry};
+use super::project_settings::{ProjectSettingsStore, DEFAULT_TAB_WIDTH};
+use super::search::{SearchMatch, SearchOptions};
 
 #[tauri::command]
 pub async fn get_document<R: Runtime>(
@@ -25,10 +39,27 @@ pub async fn read_text<R: Runtime>(
 ) -> tauri::Result<String> {
     let state = app_handle.state::<EditorState>();
     let doc = state.get_document(path.as_ref()).await?;
+    if doc.large_file {
+        return Err(anyhow!("Large files can only be read with read_lines").into());
+    }
+
     let text = doc.text.to_string();
     Ok(text)
 }
 
+#[tauri::command]
+pub async fn read_lines<R: Runtime>(
+    path: SafePathBuf,
+    start: usize,
+    count: usize,
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<Lines> {
+    let state = app_handle.state::<EditorState>();
+    state.get_document(path.as_ref()).await?;
+    let lines = state.read_lines(path.as_ref(), start, count)?;
+    Ok(lines)
+}
+
 #[tauri::command]
 pub async fn replace_text<R: Runtime>(
     path: SafePathBuf,
@@ -44,58 +75,291 @@ pub async fn replace_text<R: Runtime>(
 #[tauri::command]
 pub async fn insert_text<R: Runtime>(
     path: SafePathBuf,
+    version: i32,
     data: Insert,
     app_handle: tauri::AppHandle<R>,
-) -> tauri::Result<()> {
+) -> Result<(), EditError> {
     let state = app_handle.state::<EditorState>();
+    let tx = state.insert_text(path.as_ref(), version, &data)?;
+    notify_change(&app_handle, &tx).await;
 
-    state.insert_text(path.as_ref(), &data)?;
+    Ok(())
+}
 
-    let doc = state.get_document(path.as_ref()).await?;
-    let lsp_service = app_handle.state::<LspService<R>>();
+#[tauri::command]
+pub async fn delete_text<R: Runtime>(
+    path: SafePathBuf,
+    version: i32,
+    data: Delete,
+    app_handle: tauri::AppHandle<R>,
+) -> Result<(), EditError> {
+    let state = app_handle.state::<EditorState>();
+    let tx = state.delete_text(path.as_ref(), version, &data)?;
+    notify_change(&app_handle, &tx).await;
 
-    if let Some(language_server_id) = doc.get_language_server_id() {
-        let _ = lsp_service
-            .insert_document(&language_server_id, &doc, &data)
-            .await;
-    }
+    Ok(())
+}
 
-    let copilot_service = app_handle.state::<CopilotLspService<R>>();
-    let _ = copilot_service.insert_document(&doc, &data).await;
+#[tauri::command]
+pub async fn apply_changes<R: Runtime>(
+    path: SafePathBuf,
+    version: i32,
+    changes: Vec<Change>,
+    // Pages of large files send offsets from their first line
+    line: Option<usize>,
+    app_handle: tauri::AppHandle<R>,
+) -> Result<Document, EditError> {
+    let state = app_handle.state::<EditorState>();
+    let tx = state.apply_line_changes(path.as_ref(), version, line.unwrap_or(0), &changes)?;
+    notify_change(&app_handle, &tx).await;
 
-    Ok(())
+    Ok(tx.doc)
 }
 
 #[tauri::command]
-pub async fn delete_text<R: Runtime>(
+pub async fn search_document<R: Runtime>(
     path: SafePathBuf,
-    data: Delete,
+    query: String,
+    options: SearchOptions,
     app_handle: tauri::AppHandle<R>,
-) -> tauri::Result<()> {
+) -> tauri::Result<Vec<SearchMatch>> {
     let state = app_handle.state::<EditorState>();
+    state.get_document(path.as_ref()).await?;
+    let matches = state.search_document(path.as_ref(), &query, &options)?;
+    Ok(matches)
+}
 
-    let doc = state.get_document(path.as_ref()).await?;
-    let lsp_service = app_handle.state::<LspService<R>>();
-    if let Some(language_server_id) = doc.get_language_server_id() {
-        let _ = lsp_service
-            .delete_document(&language_server_id, &doc, &data)
-            .await;
+#[tauri::command]
+pub async fn replace_all<R: Runtime>(
+    path: SafePathBuf,
+    version: i32,
+    query: String,
+    replacement: String,
+    options: SearchOptions,
+    app_handle: tauri::AppHandle<R>,
+) -> Result<Document, EditError> {
+    let state = app_handle.state::<EditorState>();
+    let tx = state.replace_all(path.as_ref(), version, &query, &replacement, &options)?;
+    notify_change(&app_handle, &tx).await;
+
+    Ok(tx.doc)
+}
+
+#[tauri::command]
+pub async fn undo<R: Runtime>(
+    path: SafePathBuf,
+    app_handle: tauri::AppHandle<R>,
+) -> Result<Option<UndoResult>, EditError> {
+    let state = app_handle.state::<EditorState>();
+    let Some((result, txs)) = state.undo(path.as_ref())? else {
+        return Ok(None);
+    };
+
+    for tx in &txs {
+        notify_change(&app_handle, tx).await;
+    }
+    Ok(Some(result))
+}
+
+#[tauri::command]
+pub async fn redo<R: Runtime>(
+    path: SafePathBuf,
+    app_handle: tauri::AppHandle<R>,
+) -> Result<Option<UndoResult>, EditError> {
+    let state = app_handle.state::<EditorState>();
+    let Some((result, txs)) = state.redo(path.as_ref())? else {
+        return Ok(None);
+    };
+
+    for tx in &txs {
+        notify_change(&app_handle, tx).await;
     }
+    Ok(Some(result))
+}
 
-    let copilot_service = app_handle.state::<CopilotLspService<R>>();
-    let _ = copilot_service.delete_document(&doc, &data).await;
+#[tauri::command]
+pub async fn set_file_format<R: Runtime>(
+    path: SafePathBuf,
+    format: FileFormat,
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<Document> {
+    let state = app_handle.state::<EditorState>();
+    let doc = state.set_file_format(path.as_ref(), format)?;
+    Ok(doc)
+}
 
-    state.delete_text(path.as_ref(), &data)?;
+#[tauri::command]
+pub async fn reopen_with_encoding<R: Runtime>(
+    path: SafePathBuf,
+    encoding: String,
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<Document> {
+    let encoding = Encoding::for_label(encoding.as_bytes())
+        .ok_or(anyhow!("Unknown encoding: {}", encoding))?;
+    let state = app_handle.state::<EditorState>();
+    let doc = state.reopen_with_encoding(path.as_ref(), encoding).await?;
+    Ok(doc)
+}
 
+#[tauri::command]
+pub async fn list_recoverable_documents<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<Vec<JournalEntry>> {
+    let journal = app_handle.state::<Journal>();
+    Ok(journal.list_recoverable_documents())
+}
+
+// Replaces the text of the document with its recoverable entry
+#[tauri::command]
+pub async fn restore_document<R: Runtime>(
+    path: SafePathBuf,
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<Document> {
+    let journal = app_handle.state::<Journal>();
+    let state = app_handle.state::<EditorState>();
+    let doc = journal.restore(&state, path.as_ref()).await?;
+    // Reaches the language servers and open editors like a change on disk
+    let _ = state.changed_doc_tx.send(doc.clone()).await;
+    Ok(doc)
+}
+
+#[tauri::command]
+pub async fn discard_recoverable_document<R: Runtime>(
+    path: SafePathBuf,
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<()> {
+    let journal = app_handle.state::<Journal>();
+    journal.discard(path.as_ref())?;
     Ok(())
 }
 
+#[tauri::command]
+pub async fn list_dirty_documents<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<Vec<Document>> {
+    let state = app_handle.state::<EditorState>();
+    Ok(state.list_dirty_documents())
+}
+
+// Edits made before saving, for the frontend to apply to its editor
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WriteResult {
+    // Each batch is against the text after the previous one
+    pub edits: Vec<Vec<Change>>,
+    pub version: i32,
+}
+
+// Writes the document. Only explicit saves by the user edit the text before,
+// autosaves write it as it is.
 #[tauri::command]
 pub async fn write_file<R: Runtime>(
     path: SafePathBuf,
+    explicit: Option<bool>,
     app_handle: tauri::AppHandle<R>,
-) -> tauri::Result<()> {
+) -> tauri::Result<WriteResult> {
     let state = app_handle.state::<EditorState>();
+    let mut edits = Vec::new();
+    let explicit = explicit.unwrap_or(false);
+    if explicit {
+        format_before_save(&app_handle, path.as_ref(), &mut edits).await;
+        trim_before_save(&app_handle, path.as_ref(), &mut edits).await;
+    }
     state.write_document(path.as_ref())?;
-    Ok(())
+    if let Err(e) = app_handle.state::<Journal>().remove(path.as_ref()) {
+        error!("Could not remove journal entry: {:?}", e);
+    }
+
+    let doc = state.get_document(path.as_ref()).await?;
+    if !doc.large_file && !is_buffer(&doc.path) {
+        let history = app_handle.state::<LocalHistory>();
+        if let Err(e) = history.snapshot_saved(&doc.path, &doc.text, doc.content_hash, explicit) {
+            error!("Could not create history snapshot: {:?}", e);
+        }
+    }
+
+    if let Some(worktree) = doc.worktree_path.filter(|w| git_dir(w).is_some()) {
+        refresh_status(&app_handle, worktree);
+    }
+
+    Ok(WriteResult {
+        edits,
+        version: doc.version,
+    })
+}
+
+// Formats the document with the language server if enabled in the project
+// settings. Failed edits do not prevent the save.
+async fn format_before_save<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    path: &Path,
+    edits: &mut Vec<Vec<Change>>,
+) {
+    let state = app_handle.state::<EditorState>();
+    let project_settings = app_handle.state::<ProjectSettingsStore>();
+    let lsp_service = app_handle.state::<LspService<R>>();
+
+    let Ok(doc) = state.get_document(path).await else {
+        return;
+    };
+    let settings = project_settings.resolve(&doc);
+    if doc.large_file || !settings.format_on_save {
+        return;
+    }
+
+    let tab_width = settings.tab_width.unwrap_or(DEFAULT_TAB_WIDTH);
+    let use_tabs = settings.use_tabs.unwrap_or(false);
+    match lsp_service.format_document(&doc, tab_width, use_tabs).await {
+        Ok(changes) => apply_before_save(app_handle, &doc, changes, edits).await,
+        Err(e) => error!("Could not format document (path={:?}): {:?}", path, e),
+    }
+}
+
+// Removes trailing whitespace if set in the EditorConfig
+async fn trim_before_save<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    path: &Path,
+    edits: &mut Vec<Vec<Change>>,
+) {
+    let state = app_handle.state::<EditorState>();
+    let Ok(doc) = state.get_document(path).await else {
+        return;
+    };
+    if doc.large_file {
+        return;
+    }
+
+    let changes = doc.editor_config.trim_changes(&doc.text);
+    apply_before_save(app_handle, &doc, changes, edits).await;
+}
+
+async fn apply_before_save<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    doc: &Document,
+    changes: Vec<Change>,
+    edits: &mut Vec<Vec<Change>>,
+) {
+    if changes.is_empty() {
+        return;
+    }
+
+    let state = app_handle.state::<EditorState>();
+    match state.apply_changes(&doc.path, doc.version, &changes) {
+        Ok(tx) => {
+            notify_change(app_handle, &tx).await;
+            edits.push(changes);
+        }
+        Err(e) => error!("Could not edit before save (path={:?}): {:?}", doc.path, e),
+    }
+}
+
+// Sends the ranges of an applied transaction to the language servers
+pub async fn notify_change<R: Runtime>(app_handle: &tauri::AppHandle<R>, tx: &Transaction) {
+    let lsp_service = app_handle.state::<LspService<R>>();
+    if let Some(language_server_id) = tx.doc.get_language_server_id() {
+        let _ = lsp_service.change_document(&language_server_id, tx).await;
+    }
+
+    let copilot_service = app_handle.state::<CopilotLspService<R>>();
+    let _ = copilot_service.change_document(tx).await;
 }
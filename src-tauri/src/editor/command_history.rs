@@ -0,0 +1,90 @@
+use anyhow::anyhow;
+use tauri::{path::SafePathBuf, Manager, Runtime};
+
+use crate::settings::command::save_settings;
+
+use super::editor_state::{Document, EditorState, UpdateDocument};
+use super::history::{LocalHistory, Retention, Snapshot};
+
+#[tauri::command]
+pub async fn list_history<R: Runtime>(
+    path: SafePathBuf,
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<Vec<Snapshot>> {
+    let history = app_handle.state::<LocalHistory>();
+    let snapshots = history.list(path.as_ref())?;
+    Ok(snapshots)
+}
+
+#[tauri::command]
+pub async fn read_history<R: Runtime>(
+    path: SafePathBuf,
+    id: u64,
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<String> {
+    let history = app_handle.state::<LocalHistory>();
+    let text = history.read(path.as_ref(), id)?;
+    Ok(text)
+}
+
+#[tauri::command]
+pub async fn diff_history<R: Runtime>(
+    path: SafePathBuf,
+    id: u64,
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<String> {
+    let state = app_handle.state::<EditorState>();
+    let history = app_handle.state::<LocalHistory>();
+    let doc = state.get_document(path.as_ref()).await?;
+    let diff = history.diff(path.as_ref(), id, &doc.text)?;
+    Ok(diff)
+}
+
+// Replaces the text with the snapshot as an unsaved edit. Open editors get it
+// like a change on disk, so later edits don't conflict with it.
+#[tauri::command]
+pub async fn restore_history<R: Runtime>(
+    path: SafePathBuf,
+    id: u64,
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<Document> {
+    let state = app_handle.state::<EditorState>();
+    let history = app_handle.state::<LocalHistory>();
+
+    let text = history.read(path.as_ref(), id)?;
+    let doc = state.get_document(path.as_ref()).await?;
+    let data = UpdateDocument {
+        text,
+        language: doc.language,
+    };
+    state.replace_text(path.as_ref(), &data)?;
+
+    let doc = state
+        .documents
+        .read()
+        .unwrap()
+        .get(path.as_ref())
+        .cloned()
+        .ok_or(anyhow!("No doc"))?;
+    let _ = state.changed_doc_tx.send(doc.clone()).await;
+    Ok(doc)
+}
+
+#[tauri::command]
+pub async fn get_history_retention<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<Retention> {
+    let history = app_handle.state::<LocalHistory>();
+    Ok(history.retention())
+}
+
+// Stored in the app settings, which apply it to the history
+#[tauri::command]
+pub async fn set_history_retention<R: Runtime>(
+    retention: Retention,
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<()> {
+    let patch = serde_json::json!({ "history": retention });
+    save_settings(&app_handle, patch, None).await?;
+    Ok(())
+}
@@ -0,0 +1,16 @@
+use tauri::{path::SafePathBuf, Manager, Runtime};
+
+use super::editor_state::EditorState;
+use super::project_settings::{ProjectSettings, ProjectSettingsStore};
+
+// The effective settings of the document at `path`
+#[tauri::command]
+pub async fn get_project_settings<R: Runtime>(
+    path: SafePathBuf,
+    app_handle: tauri::AppHandle<R>,
+) -> tauri::Result<ProjectSettings> {
+    let state = app_handle.state::<EditorState>();
+    let store = app_handle.state::<ProjectSettingsStore>();
+    let doc = state.get_document(path.as_ref()).await?;
+    Ok(store.resolve(&doc))
+}
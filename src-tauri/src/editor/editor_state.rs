@@ -1,18 +1,31 @@
 use anyhow::anyhow;
 use async_channel::{unbounded, Receiver, Sender};
+use async_lsp::lsp_types::Range;
+use encoding_rs::Encoding;
 use tracing::{debug, info};
 use ropey::Rope;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs::{self, File};
-use std::io::BufWriter;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::path::{Path, PathBuf};
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
 use std::time::SystemTime;
 
+use crate::fs::write::write_atomic;
+use crate::git::repo::git_dir;
+use crate::lsp::service::OffsetEncoding;
+use crate::lsp::util::pos_to_lsp_pos;
+
+use super::editorconfig::EditorConfig;
+use super::file_format::FileFormat;
+use super::large_file::LargeFile;
 use super::pathutil::to_relative_path;
+use super::search::{self, build_regex, SearchMatch, SearchOptions};
+use super::undo::UndoHistory;
 
 #[derive(Debug, Clone, PartialEq, Hash, Eq, Serialize, Deserialize)]
 pub struct Language(pub String);
@@ -25,8 +38,24 @@ pub struct Document {
     #[serde(skip)]
     pub text: Rope,
     pub language: Option<Language>,
+    pub format: FileFormat,
+    // Time of the last change to the text
     pub last_modified: SystemTime,
+    // Modification time of the file when it was last loaded or written
+    pub disk_modified: Option<SystemTime>,
     pub version: i32,
+    // Version of the text that was last loaded from or written to disk
+    pub saved_version: i32,
+    // Hash of the text and format that was last loaded from or written to disk
+    #[serde(skip)]
+    pub content_hash: u64,
+    pub is_dirty: bool,
+    // Changed on disk while it had unsaved edits
+    pub conflicted: bool,
+    // Too big to be sent as a whole to the frontend or to language servers
+    pub large_file: bool,
+    #[serde(default)]
+    pub editor_config: EditorConfig,
 }
 
 impl Document {
@@ -37,13 +66,30 @@ impl Document {
     pub fn get_relative_path(&self) -> PathBuf {
         to_relative_path(&self.path, self.worktree_path.as_ref()).unwrap_or(self.path.clone())
     }
+
+    // Marks the current text as the one on disk
+    fn set_saved(&mut self, disk_modified: Option<SystemTime>) {
+        self.disk_modified = disk_modified;
+        self.saved_version = self.version;
+        if !self.large_file {
+            self.content_hash = hash_content(&self.text, &self.format);
+        }
+        self.is_dirty = false;
+        self.conflicted = false;
+    }
+
+    // Edits that restore the saved text make the document clean again. Hashing
+    // large files on every edit is too slow, so any edit makes them dirty.
+    fn update_dirty(&mut self) {
+        self.is_dirty = self.version != self.saved_version
+            && (self.large_file || hash_content(&self.text, &self.format) != self.content_hash);
+    }
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Insert {
     pub from_a: usize,
-    pub to_b: usize,
     pub text: String,
 }
 
@@ -54,6 +100,73 @@ pub struct Delete {
     pub to_a: usize,
 }
 
+// Replaces the UTF-16 range `from..to` of the document before the transaction
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Change {
+    pub from: usize,
+    pub to: usize,
+    pub text: String,
+}
+
+impl From<&Insert> for Change {
+    fn from(data: &Insert) -> Self {
+        Change {
+            from: data.from_a,
+            to: data.from_a,
+            text: data.text.clone(),
+        }
+    }
+}
+
+impl From<&Delete> for Change {
+    fn from(data: &Delete) -> Self {
+        Change {
+            from: data.from_a,
+            to: data.to_a,
+            text: "".to_string(),
+        }
+    }
+}
+
+// A change with its range in the text before it was applied
+#[derive(Clone, Debug, PartialEq)]
+pub struct AppliedChange {
+    pub utf8: Range,
+    pub utf16: Range,
+    pub utf32: Range,
+    pub text: String,
+}
+
+impl AppliedChange {
+    pub fn range(&self, offset_encoding: OffsetEncoding) -> Range {
+        match offset_encoding {
+            OffsetEncoding::Utf8 => self.utf8,
+            OffsetEncoding::Utf16 => self.utf16,
+            OffsetEncoding::Utf32 => self.utf32,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Transaction {
+    // The document after all changes were applied
+    pub doc: Document,
+    // Ordered from the end of the document to the start, so every range stays
+    // valid when the changes are applied one after another
+    pub changes: Vec<AppliedChange>,
+    // Changes that revert the transaction on the new text
+    pub inverse: Vec<Change>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UndoResult {
+    pub document: Document,
+    // Changes of each applied transaction, to be applied one after another
+    pub steps: Vec<Vec<Change>>,
+}
+
 #[derive(Clone, Debug, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UpdateDocument {
@@ -61,20 +174,135 @@ pub struct UpdateDocument {
     pub language: Option<Language>,
 }
 
+pub const DOCUMENT_CHANGED_EXTERNALLY: &str = "document-changed-externally";
+
+// Payload of DOCUMENT_CHANGED_EXTERNALLY. Large files are sent without their
+// text, the frontend reads the lines it shows.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangedDocument {
+    #[serde(flatten)]
+    pub document: Document,
+    pub text: Option<String>,
+}
+
+impl From<Document> for ChangedDocument {
+    fn from(document: Document) -> Self {
+        let text = (!document.large_file).then(|| document.text.to_string());
+        Self { document, text }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ExternalChange {
+    Reloaded(Document),
+    Conflict(Document),
+    Deleted(Document),
+}
+
+// Error returned to the frontend by the edit commands
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum EditError {
+    // The changes were made against another version, e.g. before the file
+    // was reloaded from disk
+    #[serde(rename_all = "camelCase")]
+    VersionConflict {
+        path: PathBuf,
+        version: i32,
+        current_version: i32,
+        text: String,
+    },
+    Other {
+        message: String,
+    },
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::VersionConflict {
+                path,
+                version,
+                current_version,
+                ..
+            } => write!(
+                f,
+                "Version conflict (path={:?}, version={}, current={})",
+                path, version, current_version
+            ),
+            EditError::Other { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+impl From<anyhow::Error> for EditError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<EditError>() {
+            Ok(e) => e,
+            Err(e) => EditError::Other {
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+// Files above this size are opened in large file mode
+pub const LARGE_FILE_SIZE: u64 = 50 * 1024 * 1024;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lines {
+    pub start: usize,
+    pub lines: Vec<String>,
+    pub total: usize,
+}
+
+pub const ROOT_MARKERS: [&str; 3] = [".tinywrite", "package.json", "Cargo.toml"];
+
 pub struct EditorState {
     pub documents: RwLock<HashMap<PathBuf, Document>>,
+    undo_history: Mutex<HashMap<PathBuf, UndoHistory>>,
+    // Line indexes of the open large files, whose text is not in memory
+    large_files: RwLock<HashMap<PathBuf, LargeFile>>,
+    large_file_size: u64,
+    // Files that mark the root of projects without git
+    pub root_markers: RwLock<Vec<String>>,
     pub open_doc_tx: Sender<PathBuf>,
     pub open_doc_rx: Receiver<PathBuf>,
+    // Documents that are no longer open under their path
+    pub closed_doc_tx: Sender<Document>,
+    pub closed_doc_rx: Receiver<Document>,
+    pub changed_doc_tx: Sender<Document>,
+    pub changed_doc_rx: Receiver<Document>,
+}
+
+impl Default for EditorState {
+    fn default() -> Self {
+        Self::with_large_file_size(LARGE_FILE_SIZE)
+    }
 }
 
 impl EditorState {
-    pub fn new() -> Self {
+    pub fn with_large_file_size(large_file_size: u64) -> Self {
         let (open_doc_tx, open_doc_rx) = unbounded();
+        let (closed_doc_tx, closed_doc_rx) = unbounded();
+        let (changed_doc_tx, changed_doc_rx) = unbounded();
 
         Self {
             documents: RwLock::new(HashMap::new()),
+            undo_history: Mutex::new(HashMap::new()),
+            large_files: RwLock::new(HashMap::new()),
+            large_file_size,
+            root_markers: RwLock::new(ROOT_MARKERS.iter().map(|m| m.to_string()).collect()),
             open_doc_tx,
             open_doc_rx,
+            closed_doc_tx,
+            closed_doc_rx,
+            changed_doc_tx,
+            changed_doc_rx,
         }
     }
 
@@ -88,45 +316,66 @@ impl EditorState {
             File::create(path)?;
         }
 
-        let last_modified = if is_buffer {
-            SystemTime::now()
+        let disk_modified = if is_buffer {
+            None
         } else {
-            fs::metadata(path)?.modified()?
+            Some(fs::metadata(path)?.modified()?)
         };
 
         let mut new_doc = false;
+        let mut changed_doc = None;
 
         match self.documents.write().unwrap().entry(path.to_path_buf()) {
             Entry::Occupied(doc) => {
-                let doc = doc.into_mut();
-                if !is_buffer && last_modified > doc.last_modified {
-                    debug!("Update file contents (path={:?})", path);
-                    let file = File::open(path)?;
-                    let text = ropey::Rope::from_reader(file)?;
-                    doc.text = text;
-                    doc.last_modified = last_modified;
-                    doc.version += 1
+                if let Some(ExternalChange::Reloaded(doc)) =
+                    self.reload(doc.into_mut(), disk_modified)?
+                {
+                    self.clear_undo(path);
+                    changed_doc = Some(doc);
                 }
             }
             Entry::Vacant(map) => {
                 let language = Self::get_language(path);
-                let worktree_path = Self::get_worktree_path(path);
+                let worktree_path = self.get_worktree_path(path);
+                let large_file = !is_buffer && fs::metadata(path)?.len() > self.large_file_size;
+                if large_file {
+                    info!("Open large file (path={:?})", path);
+                }
 
-                let text = if is_buffer {
-                    ropey::Rope::new()
+                let (text, mut format) = if is_buffer {
+                    (ropey::Rope::new(), FileFormat::default())
+                } else if large_file {
+                    (ropey::Rope::new(), self.open_large_file(path, None)?)
+                } else {
+                    read_file(path, None)?
+                };
+                // Applied once, so that a format chosen by the user is kept
+                let editor_config = if is_buffer {
+                    EditorConfig::default()
                 } else {
-                    let file = File::open(path)?;
-                    ropey::Rope::from_reader(file)?
+                    EditorConfig::resolve(path, worktree_path.as_deref())
                 };
+                if !large_file {
+                    editor_config.apply_format(&mut format, &text);
+                }
 
-                let doc = Document {
+                let mut doc = Document {
                     path: path.to_path_buf(),
                     text,
                     worktree_path,
                     language,
+                    format,
                     last_modified: SystemTime::now(),
+                    disk_modified,
                     version: 0,
+                    saved_version: 0,
+                    content_hash: 0,
+                    is_dirty: false,
+                    conflicted: false,
+                    large_file,
+                    editor_config,
                 };
+                doc.set_saved(disk_modified);
 
                 map.insert(doc);
                 new_doc = true;
@@ -137,6 +386,10 @@ impl EditorState {
             self.open_doc_tx.send(path.to_path_buf()).await?;
         }
 
+        if let Some(doc) = changed_doc {
+            self.changed_doc_tx.send(doc).await?;
+        }
+
         let doc = self
             .documents
             .read()
@@ -147,55 +400,583 @@ impl EditorState {
         Ok(doc)
     }
 
-    pub fn insert_text(&self, path: &Path, data: &Insert) -> anyhow::Result<()> {
-        let mut docs = self.documents.write().unwrap();
+    pub fn insert_text(
+        &self,
+        path: &Path,
+        version: i32,
+        data: &Insert,
+    ) -> anyhow::Result<Transaction> {
+        self.edit(path, version, 0, &[data.into()])
+    }
+
+    pub fn delete_text(
+        &self,
+        path: &Path,
+        version: i32,
+        data: &Delete,
+    ) -> anyhow::Result<Transaction> {
+        self.edit(path, version, 0, &[data.into()])
+    }
 
+    // Applies a batch of changes made against `version` as one edit
+    pub fn apply_changes(
+        &self,
+        path: &Path,
+        version: i32,
+        changes: &[Change],
+    ) -> anyhow::Result<Transaction> {
+        self.edit(path, version, 0, changes)
+    }
+
+    // Like `apply_changes` with offsets from the start of `line`, so that a
+    // page of a large file is edited without its whole text
+    pub fn apply_line_changes(
+        &self,
+        path: &Path,
+        version: i32,
+        line: usize,
+        changes: &[Change],
+    ) -> anyhow::Result<Transaction> {
+        self.edit(path, version, line, changes)
+    }
+
+    pub fn replace_text(&self, path: &Path, data: &UpdateDocument) -> anyhow::Result<()> {
+        let mut docs = self.documents.write().unwrap();
         let doc = docs.get_mut(path).ok_or(anyhow!("No doc"))?;
-        let from = doc.text.utf16_cu_to_char(data.from_a);
 
-        doc.text.insert(from, &data.text);
+        if doc.large_file {
+            let lines = data.text.split('\n').map(str::to_string).collect();
+            let mut large_files = self.large_files.write().unwrap();
+            let large_file = large_files.get_mut(path).ok_or(anyhow!("No large file"))?;
+            large_file.replace_lines(0..large_file.total(), lines);
+            doc.language = data.language.clone();
+            doc.last_modified = SystemTime::now();
+            doc.version += 1;
+            doc.update_dirty();
+            return Ok(());
+        }
+
+        let inverse = Change {
+            from: 0,
+            to: data.text.encode_utf16().count(),
+            text: doc.text.to_string(),
+        };
+        self.record_undo(path, vec![inverse]);
+
+        doc.text = Rope::from_str(&data.text);
+        doc.language = data.language.clone();
         doc.last_modified = SystemTime::now();
         doc.version += 1;
+        doc.update_dirty();
 
         Ok(())
     }
 
-    pub fn delete_text(&self, path: &Path, data: &Delete) -> anyhow::Result<()> {
+    // Returns a range of lines without line breaks
+    pub fn read_lines(&self, path: &Path, start: usize, count: usize) -> anyhow::Result<Lines> {
+        let docs = self.documents.read().unwrap();
+        let doc = docs.get(path).ok_or(anyhow!("No doc"))?;
+        if doc.large_file {
+            if let Some(large_file) = self.large_files.read().unwrap().get(path) {
+                return large_file.read_lines(start, count);
+            }
+        }
+
+        let total = doc.text.len_lines();
+        let start = start.min(total);
+        let end = start.saturating_add(count).min(total);
+        let lines = doc
+            .text
+            .lines_at(start)
+            .take(end - start)
+            .map(|line| {
+                let mut line = line.to_string();
+                if line.ends_with('\n') {
+                    line.pop();
+                }
+                line
+            })
+            .collect();
+
+        Ok(Lines {
+            start,
+            lines,
+            total,
+        })
+    }
+
+    pub fn write_document(&self, path: &Path) -> anyhow::Result<()> {
         let mut docs = self.documents.write().unwrap();
-        let doc = docs.get_mut(path).ok_or(anyhow!("No doc"))?;
+        let doc = docs.get_mut(path).ok_or(anyhow!("Document not found"))?;
+
+        if doc.large_file {
+            info!("Write large file (path={:?})", doc.path);
+            {
+                let large_files = self.large_files.read().unwrap();
+                let large_file = large_files.get(path).ok_or(anyhow!("No large file"))?;
+                write_atomic(&doc.path, |w| {
+                    doc.format.write_chunks(w, |f| large_file.for_each_chunk(f))
+                })?;
+            }
+            // The written file has all edits, its lines are indexed again
+            self.open_large_file(&doc.path, Some(doc.format.encoding))?;
+        } else {
+            info!("Write rope to file (path={:?})", doc.path);
+            write_atomic(&doc.path, |w| doc.format.write(&doc.text, w))?;
+        }
 
-        let from = doc.text.utf16_cu_to_char(data.from_a);
-        let to = doc.text.utf16_cu_to_char(data.to_a);
+        // Don't reload our own write as an external change
+        let disk_modified = fs::metadata(&doc.path)?.modified()?;
+        doc.set_saved(Some(disk_modified));
+        Ok(())
+    }
 
-        doc.text.remove(from..to);
+    // Changes how the document is written on the next save
+    pub fn set_file_format(&self, path: &Path, format: FileFormat) -> anyhow::Result<Document> {
+        let mut docs = self.documents.write().unwrap();
+        let doc = docs.get_mut(path).ok_or(anyhow!("No doc"))?;
+
+        doc.format = format;
         doc.last_modified = SystemTime::now();
         doc.version += 1;
+        doc.update_dirty();
 
-        Ok(())
+        Ok(doc.clone())
     }
 
-    pub fn replace_text(&self, path: &Path, data: &UpdateDocument) -> anyhow::Result<()> {
+    // Reads the file again with the given encoding, unsaved changes are discarded
+    pub async fn reopen_with_encoding(
+        &self,
+        path: &Path,
+        encoding: &'static Encoding,
+    ) -> anyhow::Result<Document> {
+        self.get_document(path).await?;
+
+        let doc = {
+            let mut docs = self.documents.write().unwrap();
+            let doc = docs.get_mut(path).ok_or(anyhow!("No doc"))?;
+            let (text, format) = if doc.large_file {
+                (Rope::new(), self.open_large_file(path, Some(encoding))?)
+            } else {
+                read_file(path, Some(encoding))?
+            };
+            let disk_modified = fs::metadata(path)?.modified()?;
+
+            doc.text = text;
+            doc.format = format;
+            doc.last_modified = SystemTime::now();
+            doc.version += 1;
+            doc.set_saved(Some(disk_modified));
+            doc.clone()
+        };
+
+        self.clear_undo(path);
+        self.changed_doc_tx.send(doc.clone()).await?;
+        Ok(doc)
+    }
+
+    pub fn list_dirty_documents(&self) -> Vec<Document> {
+        self.documents
+            .read()
+            .unwrap()
+            .values()
+            .filter(|doc| doc.is_dirty)
+            .cloned()
+            .collect()
+    }
+
+    // Reloads a document whose file changed on disk, unless it has unsaved edits
+    pub fn sync_document(&self, path: &Path) -> anyhow::Result<Option<ExternalChange>> {
+        let mut docs = self.documents.write().unwrap();
+        let Some(doc) = docs.get_mut(path) else {
+            return Ok(None);
+        };
+
+        if !fs::exists(path)? {
+            return Ok(Some(ExternalChange::Deleted(doc.clone())));
+        }
+
+        let disk_modified = fs::metadata(path)?.modified()?;
+        let change = self.reload(doc, Some(disk_modified))?;
+        if let Some(ExternalChange::Reloaded(_)) = change {
+            self.clear_undo(path);
+        }
+
+        Ok(change)
+    }
+
+    pub fn search_document(
+        &self,
+        path: &Path,
+        query: &str,
+        options: &SearchOptions,
+    ) -> anyhow::Result<Vec<SearchMatch>> {
+        let docs = self.documents.read().unwrap();
+        let doc = docs.get(path).ok_or(anyhow!("No doc"))?;
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let regex = build_regex(query, options)?;
+        Ok(search::search(&doc.text, &regex))
+    }
+
+    // Replaces all matches in one transaction
+    pub fn replace_all(
+        &self,
+        path: &Path,
+        version: i32,
+        query: &str,
+        replacement: &str,
+        options: &SearchOptions,
+    ) -> anyhow::Result<Transaction> {
+        let changes = {
+            let docs = self.documents.read().unwrap();
+            let doc = docs.get(path).ok_or(anyhow!("No doc"))?;
+            if query.is_empty() {
+                Vec::new()
+            } else {
+                let regex = build_regex(query, options)?;
+                search::replace(&doc.text, &regex, replacement, options.regex)
+            }
+        };
+
+        self.edit(path, version, 0, &changes)
+    }
+
+    pub fn undo(&self, path: &Path) -> anyhow::Result<Option<(UndoResult, Vec<Transaction>)>> {
+        self.revert(path, true)
+    }
+
+    pub fn redo(&self, path: &Path) -> anyhow::Result<Option<(UndoResult, Vec<Transaction>)>> {
+        self.revert(path, false)
+    }
+
+    // Moves an open document to its new path and returns it before and after
+    // the move. Fails if another document is open at the new path.
+    pub fn rename_document(
+        &self,
+        from: &Path,
+        to: &Path,
+    ) -> anyhow::Result<Option<(Document, Document)>> {
+        let mut docs = self.documents.write().unwrap();
+        if !docs.contains_key(from) {
+            return Ok(None);
+        }
+        if docs.contains_key(to) {
+            return Err(anyhow!(
+                "Document already open (from={:?}, to={:?})",
+                from,
+                to
+            ));
+        }
+
+        let Some(old) = docs.remove(from) else {
+            return Ok(None);
+        };
+        let mut doc = old.clone();
+        doc.path = to.to_path_buf();
+        doc.language = Self::get_language(to);
+        doc.worktree_path = self.get_worktree_path(to);
+        doc.editor_config = EditorConfig::resolve(to, doc.worktree_path.as_deref());
+        docs.insert(to.to_path_buf(), doc.clone());
+
+        let mut large_files = self.large_files.write().unwrap();
+        if let Some(mut large_file) = large_files.remove(from) {
+            large_file.rename(to);
+            large_files.insert(to.to_path_buf(), large_file);
+        }
+
+        let mut undo_history = self.undo_history.lock().unwrap();
+        if let Some(history) = undo_history.remove(from) {
+            undo_history.insert(to.to_path_buf(), history);
+        }
+        Ok(Some((old, doc)))
+    }
+
+    fn edit(
+        &self,
+        path: &Path,
+        version: i32,
+        line: usize,
+        changes: &[Change],
+    ) -> anyhow::Result<Transaction> {
         let mut docs = self.documents.write().unwrap();
         let doc = docs.get_mut(path).ok_or(anyhow!("No doc"))?;
 
-        doc.text = Rope::from_str(&data.text);
-        doc.language = data.language.clone();
+        if version != doc.version {
+            return Err(EditError::VersionConflict {
+                path: path.to_path_buf(),
+                version,
+                current_version: doc.version,
+                text: doc.text.to_string(),
+            }
+            .into());
+        }
+
+        if doc.large_file {
+            return self.edit_large_file(doc, line, changes);
+        }
+
+        let changes = if line == 0 {
+            Cow::Borrowed(changes)
+        } else {
+            let line = doc.text.line_to_char(line.min(doc.text.len_lines()));
+            let offset = doc.text.char_to_utf16_cu(line);
+            let shifted = changes.iter().map(|c| Change {
+                from: c.from + offset,
+                to: c.to + offset,
+                text: c.text.clone(),
+            });
+            Cow::Owned(shifted.collect())
+        };
+
+        let tx = Self::apply(doc, &changes)?;
+        self.record_undo(path, tx.inverse.clone());
+        Ok(tx)
+    }
+
+    // Applies the changes to the lines they touch, which are then replaced in
+    // the large file. Offsets are relative to the start of `line`. Undo of
+    // large files is left to the editor.
+    fn edit_large_file(
+        &self,
+        doc: &mut Document,
+        line: usize,
+        changes: &[Change],
+    ) -> anyhow::Result<Transaction> {
+        let mut large_files = self.large_files.write().unwrap();
+        let large_file = large_files
+            .get_mut(&doc.path)
+            .ok_or(anyhow!("No large file"))?;
+
+        let line = line.min(large_file.total());
+        let len = changes.iter().map(|c| c.to.max(c.from)).max().unwrap_or(0);
+        let (text, count) = large_file.read_text(line, len)?;
+
+        let mut edited = doc.clone();
+        edited.text = Rope::from_str(&text);
+        let mut tx = Self::apply(&mut edited, changes)?;
+        let text = std::mem::take(&mut edited.text).to_string();
+        let lines = if count == 0 && text.is_empty() {
+            Vec::new()
+        } else {
+            text.split('\n').map(str::to_string).collect()
+        };
+        large_file.replace_lines(line..line + count, lines);
+        *doc = edited;
+
+        for change in &mut tx.changes {
+            for range in [&mut change.utf8, &mut change.utf16, &mut change.utf32] {
+                range.start.line += line as u32;
+                range.end.line += line as u32;
+            }
+        }
+        tx.doc = doc.clone();
+        Ok(tx)
+    }
+
+    // Applies the last undo or redo group and moves its inverse to the
+    // opposite stack. The group is applied to a copy, so that a failed step
+    // leaves the document as it was. Such a group is dropped.
+    fn revert(
+        &self,
+        path: &Path,
+        undo: bool,
+    ) -> anyhow::Result<Option<(UndoResult, Vec<Transaction>)>> {
+        let mut docs = self.documents.write().unwrap();
+        let doc = docs.get_mut(path).ok_or(anyhow!("No doc"))?;
+        let mut undo_history = self.undo_history.lock().unwrap();
+        let history = undo_history.entry(path.to_path_buf()).or_default();
+
+        let steps = if undo {
+            history.pop_undo()
+        } else {
+            history.pop_redo()
+        };
+        let Some(steps) = steps else {
+            return Ok(None);
+        };
+
+        let mut reverted = doc.clone();
+        let mut txs = Vec::with_capacity(steps.len());
+        for step in &steps {
+            txs.push(Self::apply(&mut reverted, step)?);
+        }
+        *doc = reverted;
+
+        let inverse = txs.iter().map(|tx| tx.inverse.clone()).collect();
+        if undo {
+            history.push_redo(inverse);
+        } else {
+            history.push_undo(inverse);
+        }
+
+        debug!(
+            "Revert document (path={:?}, undo={}, steps={})",
+            path,
+            undo,
+            steps.len()
+        );
+        let result = UndoResult {
+            document: doc.clone(),
+            steps,
+        };
+        Ok(Some((result, txs)))
+    }
+
+    // Indexes the lines of a large file and returns its format
+    fn open_large_file(
+        &self,
+        path: &Path,
+        encoding: Option<&'static Encoding>,
+    ) -> anyhow::Result<FileFormat> {
+        let (large_file, format) = LargeFile::open(path, encoding)
+            .map_err(|e| anyhow!("Could not read file (path={:?}): {}", path, e))?;
+        self.large_files
+            .write()
+            .unwrap()
+            .insert(path.to_path_buf(), large_file);
+        Ok(format)
+    }
+
+    fn record_undo(&self, path: &Path, inverse: Vec<Change>) {
+        self.undo_history
+            .lock()
+            .unwrap()
+            .entry(path.to_path_buf())
+            .or_default()
+            .record(inverse);
+    }
+
+    fn clear_undo(&self, path: &Path) {
+        self.undo_history.lock().unwrap().remove(path);
+    }
+
+    // Replaces the text with a newer version from disk, or flags a conflict
+    // if the document has unsaved edits
+    fn reload(
+        &self,
+        doc: &mut Document,
+        disk_modified: Option<SystemTime>,
+    ) -> anyhow::Result<Option<ExternalChange>> {
+        match (disk_modified, doc.disk_modified) {
+            (Some(disk_modified), Some(prev)) if disk_modified > prev => {}
+            _ => return Ok(None),
+        }
+
+        // The line index of large files is renewed, unless they have edits
+        if doc.large_file {
+            if doc.is_dirty {
+                return Ok(Some(Self::conflict(doc, disk_modified)));
+            }
+
+            debug!("Reload large file (path={:?})", doc.path);
+            doc.format = self.open_large_file(&doc.path, Some(doc.format.encoding))?;
+            doc.last_modified = SystemTime::now();
+            doc.version += 1;
+            doc.set_saved(disk_modified);
+            return Ok(Some(ExternalChange::Reloaded(doc.clone())));
+        }
+
+        let (text, mut format) = read_file(&doc.path, Some(doc.format.encoding))?;
+        doc.editor_config.apply_format(&mut format, &text);
+        if text == doc.text && format == doc.format {
+            doc.set_saved(disk_modified);
+            return Ok(None);
+        }
+
+        if doc.is_dirty {
+            return Ok(Some(Self::conflict(doc, disk_modified)));
+        }
+
+        debug!("Reload document (path={:?})", doc.path);
+        doc.text = text;
+        doc.format = format;
         doc.last_modified = SystemTime::now();
         doc.version += 1;
+        doc.set_saved(disk_modified);
+        Ok(Some(ExternalChange::Reloaded(doc.clone())))
+    }
 
-        Ok(())
+    fn conflict(doc: &mut Document, disk_modified: Option<SystemTime>) -> ExternalChange {
+        debug!(
+            "Document changed on disk with unsaved edits (path={:?})",
+            doc.path
+        );
+        doc.disk_modified = disk_modified;
+        doc.conflicted = true;
+        ExternalChange::Conflict(doc.clone())
     }
 
-    pub fn write_document(&self, path: &Path) -> anyhow::Result<()> {
-        let docs = self.documents.read().unwrap();
-        let doc = docs
-            .get(&path.to_path_buf())
-            .ok_or(anyhow!("Document not found"))?;
+    // Bumps the version once for the whole batch
+    fn apply(doc: &mut Document, changes: &[Change]) -> anyhow::Result<Transaction> {
+        // Apply from the end so that earlier offsets are not shifted. Deletions
+        // go before insertions at the same offset, and inserts at the same
+        // offset keep their order.
+        let mut sorted: Vec<(usize, &Change)> = changes.iter().enumerate().collect();
+        sorted.sort_by(|(i, a), (j, b)| b.from.cmp(&a.from).then(b.to.cmp(&a.to)).then(j.cmp(i)));
+
+        let mut end = doc.text.len_utf16_cu();
+        for (_, change) in &sorted {
+            if change.from > change.to || change.to > end {
+                return Err(anyhow!(
+                    "Invalid change (from={}, to={}, path={:?})",
+                    change.from,
+                    change.to,
+                    doc.path
+                ));
+            }
+            end = change.from;
+        }
 
-        info!("Write rope to file (path={:?})", doc.path);
-        doc.text
-            .write_to(BufWriter::new(File::create(&doc.path)?))?;
-        Ok(())
+        let mut applied = Vec::with_capacity(sorted.len());
+        let mut removed = Vec::with_capacity(sorted.len());
+        for (_, change) in &sorted {
+            let range = |offset_encoding| {
+                Range::new(
+                    pos_to_lsp_pos(&doc.text, change.from, offset_encoding),
+                    pos_to_lsp_pos(&doc.text, change.to, offset_encoding),
+                )
+            };
+
+            applied.push(AppliedChange {
+                utf8: range(OffsetEncoding::Utf8),
+                utf16: range(OffsetEncoding::Utf16),
+                utf32: range(OffsetEncoding::Utf32),
+                text: change.text.clone(),
+            });
+
+            let from = doc.text.utf16_cu_to_char(change.from);
+            let to = doc.text.utf16_cu_to_char(change.to);
+            removed.push(doc.text.slice(from..to).to_string());
+            doc.text.remove(from..to);
+            doc.text.insert(from, &change.text);
+        }
+
+        // Shift the ranges by the length difference of all changes before them
+        let mut inverse = Vec::with_capacity(sorted.len());
+        let mut shift: isize = 0;
+        for ((_, change), text) in sorted.iter().zip(removed).rev() {
+            let len = change.text.encode_utf16().count();
+            let from = change.from.saturating_add_signed(shift);
+            inverse.push(Change {
+                from,
+                to: from + len,
+                text,
+            });
+            shift += len as isize - (change.to - change.from) as isize;
+        }
+
+        doc.last_modified = SystemTime::now();
+        doc.version += 1;
+        doc.update_dirty();
+
+        Ok(Transaction {
+            doc: doc.clone(),
+            changes: applied,
+            inverse,
+        })
     }
 
     fn get_language<P: AsRef<Path>>(path: P) -> Option<Language> {
@@ -206,19 +987,22 @@ impl EditorState {
         }
     }
 
-    fn get_worktree_path<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+    // The closest git worktree, or for non-git projects the closest dir with
+    // one of the root markers
+    pub fn get_worktree_path<P: AsRef<Path>>(&self, path: P) -> Option<PathBuf> {
         let mut path = path.as_ref().canonicalize().ok()?;
         if !path.is_dir() {
             path = path.parent().map(|p| p.to_path_buf())?;
         }
 
-        let git_config: PathBuf = path.join(".git").join("config");
-        if git_config.exists() {
-            Some(path.to_path_buf())
-        } else {
-            let parent = path.parent()?;
-            Self::get_worktree_path(parent)
+        if let Some(worktree) = path.ancestors().find(|dir| git_dir(dir).is_some()) {
+            return Some(worktree.to_path_buf());
         }
+
+        let root_markers = self.root_markers.read().unwrap();
+        path.ancestors()
+            .find(|dir| root_markers.iter().any(|m| is_root_marker(dir, m)))
+            .map(|dir| dir.to_path_buf())
     }
 }
 
@@ -226,6 +1010,36 @@ pub fn is_buffer(path: &Path) -> bool {
     path.starts_with("buffer://")
 }
 
+// A Cargo.toml only marks the root of a cargo workspace, not its members
+fn is_root_marker(dir: &Path, marker: &str) -> bool {
+    let path = dir.join(marker);
+    if marker == "Cargo.toml" {
+        fs::read_to_string(path).is_ok_and(|c| c.contains("[workspace]"))
+    } else {
+        path.exists()
+    }
+}
+
+// Reads a file, decodes it and normalizes its line endings
+fn read_file(
+    path: &Path,
+    encoding: Option<&'static Encoding>,
+) -> anyhow::Result<(Rope, FileFormat)> {
+    let bytes = fs::read(path)?;
+    let (format, text) = FileFormat::decode(&bytes, encoding)
+        .map_err(|e| anyhow!("Could not read file (path={:?}): {}", path, e))?;
+    Ok((Rope::from_str(&text), format))
+}
+
+pub fn hash_content(text: &Rope, format: &FileFormat) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    format.hash(&mut hasher);
+    for chunk in text.chunks() {
+        hasher.write(chunk.as_bytes());
+    }
+    hasher.finish()
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::File;
@@ -234,11 +1048,18 @@ mod tests {
     use std::time::SystemTime;
     use std::{thread, time};
 
+    use async_lsp::lsp_types::{Position, Range};
     use ropey::Rope;
     use serial_test::serial;
 
-    use crate::editor::editor_state::{EditorState, Insert, UpdateDocument};
-    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+    use crate::editor::editor_state::{
+        Change, ChangedDocument, Delete, EditError, EditorState, ExternalChange, Insert,
+        UpdateDocument,
+    };
+    use crate::editor::file_format::{FileFormat, LineEnding};
+    use crate::editor::search::SearchOptions;
+    use crate::editor::testutil::{create_test_workspace, doc_version, get_test_dir};
+    use crate::lsp::service::OffsetEncoding;
 
     use super::{Document, Language};
 
@@ -249,19 +1070,36 @@ mod tests {
 
         let path = get_test_dir().join("src").join("index.ts");
 
-        let doc = Document {
+        let mut doc = Document {
             path: path.clone(),
             worktree_path: Some(get_test_dir()),
             language: Some(Language("javascript".to_string())),
+            format: Default::default(),
             text: Rope::new(),
             last_modified: SystemTime::now(),
+            disk_modified: None,
             version: 0,
+            saved_version: 0,
+            content_hash: 0,
+            is_dirty: false,
+            conflicted: false,
+            large_file: false,
+            editor_config: Default::default(),
         };
 
         assert_eq!(
             "./src/index.ts".to_string(),
             doc.get_relative_path().to_string_lossy().to_string()
         );
+
+        doc.text = Rope::from_str("a");
+        let changed = serde_json::to_value(ChangedDocument::from(doc.clone())).unwrap();
+        assert_eq!(changed["text"], "a");
+        assert_eq!(changed["version"], 0);
+
+        doc.large_file = true;
+        let changed = serde_json::to_value(ChangedDocument::from(doc)).unwrap();
+        assert!(changed["text"].is_null());
     }
 
     #[tokio::test]
@@ -271,7 +1109,7 @@ mod tests {
 
         let path = get_test_dir();
 
-        let editor_state = EditorState::new();
+        let editor_state = EditorState::default();
         let doc = editor_state.get_document(path.as_ref()).await;
 
         assert!(doc.is_err());
@@ -282,7 +1120,7 @@ mod tests {
     async fn test_buffer() {
         create_test_workspace(true);
 
-        let editor_state = EditorState::new();
+        let editor_state = EditorState::default();
         let path = Path::new("buffer://123");
         let doc = editor_state.get_document(path.as_ref()).await.unwrap();
 
@@ -314,7 +1152,7 @@ mod tests {
 
         let path = get_test_dir().join("README.md");
 
-        let editor_state = EditorState::new();
+        let editor_state = EditorState::default();
 
         let v0_doc = editor_state.get_document(path.as_ref()).await.unwrap();
         assert_eq!(v0_doc.path, path.to_path_buf());
@@ -323,9 +1161,9 @@ mod tests {
         editor_state
             .insert_text(
                 path.as_ref(),
+                doc_version(&editor_state, path.as_ref()),
                 &Insert {
                     from_a: 0,
-                    to_b: 5,
                     text: "test".to_string(),
                 },
             )
@@ -338,6 +1176,7 @@ mod tests {
         assert_eq!(get_again_doc.text, v1_doc.text);
         assert_eq!(get_again_doc.last_modified, v1_doc.last_modified);
 
+        editor_state.write_document(path.as_ref()).unwrap();
         thread::sleep(time::Duration::from_millis(10));
         let mut file = File::create(&path).unwrap();
         file.write_all(b"updated").unwrap();
@@ -354,7 +1193,7 @@ mod tests {
 
         let path = get_test_dir().join("README.md");
 
-        let editor_state = EditorState::new();
+        let editor_state = EditorState::default();
         let doc = editor_state.get_document(path.as_ref()).await.unwrap();
 
         assert_eq!(doc.text.to_string(), "".to_string());
@@ -362,9 +1201,9 @@ mod tests {
         editor_state
             .insert_text(
                 path.as_ref(),
+                doc_version(&editor_state, path.as_ref()),
                 &Insert {
                     from_a: 0,
-                    to_b: 5,
                     text: "🧜‍♂️".to_string(),
                 },
             )
@@ -373,9 +1212,9 @@ mod tests {
         editor_state
             .insert_text(
                 path.as_ref(),
+                doc_version(&editor_state, path.as_ref()),
                 &Insert {
                     from_a: 5,
-                    to_b: 6,
                     text: "1".to_string(),
                 },
             )
@@ -384,4 +1223,665 @@ mod tests {
         let doc = editor_state.get_document(path.as_ref()).await.unwrap();
         assert_eq!(doc.text.to_string(), "🧜‍♂️1".to_string());
     }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_apply_changes() {
+        create_test_workspace(true);
+
+        let path = get_test_dir().join("README.md");
+        std::fs::write(&path, "a😀b\nc").unwrap();
+
+        let editor_state = EditorState::default();
+        editor_state.get_document(path.as_ref()).await.unwrap();
+
+        let change = |from, to, text: &str| Change {
+            from,
+            to,
+            text: text.to_string(),
+        };
+
+        // Replace "b", insert twice at the start and delete "c"
+        let tx = editor_state
+            .apply_changes(
+                path.as_ref(),
+                0,
+                &[
+                    change(0, 0, "1"),
+                    change(0, 0, "2\n"),
+                    change(3, 4, "B"),
+                    change(5, 6, ""),
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(tx.doc.text.to_string(), "12\na😀B\n");
+        assert_eq!(tx.doc.version, 1);
+        assert_eq!(
+            tx.changes
+                .iter()
+                .map(|c| c.text.as_str())
+                .collect::<Vec<_>>(),
+            vec!["", "B", "2\n", "1"]
+        );
+
+        let replace = &tx.changes[1];
+        assert_eq!(
+            replace.range(OffsetEncoding::Utf8),
+            Range::new(Position::new(0, 5), Position::new(0, 6))
+        );
+        assert_eq!(
+            replace.range(OffsetEncoding::Utf16),
+            Range::new(Position::new(0, 3), Position::new(0, 4))
+        );
+        assert_eq!(
+            replace.range(OffsetEncoding::Utf32),
+            Range::new(Position::new(0, 2), Position::new(0, 3))
+        );
+        assert_eq!(
+            tx.changes[0].utf16,
+            Range::new(Position::new(1, 0), Position::new(1, 1))
+        );
+
+        // Overlapping and out of bounds changes are rejected without editing
+        let overlap =
+            editor_state.apply_changes(path.as_ref(), 1, &[change(0, 2, "x"), change(1, 3, "y")]);
+        assert!(overlap.is_err());
+        let out_of_bounds = editor_state.apply_changes(path.as_ref(), 1, &[change(8, 20, "")]);
+        assert!(out_of_bounds.is_err());
+
+        // Changes made against an older version are rejected
+        let stale = editor_state.apply_changes(path.as_ref(), 0, &[change(0, 0, "x")]);
+        assert!(stale.is_err());
+
+        let doc = editor_state.get_document(path.as_ref()).await.unwrap();
+        assert_eq!(doc.text.to_string(), "12\na😀B\n");
+        assert_eq!(doc.version, 1);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_version_conflict() {
+        create_test_workspace(true);
+
+        let path = get_test_dir().join("README.md");
+        std::fs::write(&path, "a").unwrap();
+
+        let editor_state = EditorState::default();
+        editor_state.get_document(path.as_ref()).await.unwrap();
+
+        let change = Change {
+            from: 0,
+            to: 0,
+            text: "b".to_string(),
+        };
+        let tx = editor_state
+            .apply_changes(path.as_ref(), 0, std::slice::from_ref(&change))
+            .unwrap();
+        assert_eq!(tx.doc.version, 1);
+
+        // Writing the document is not an external change
+        editor_state.write_document(path.as_ref()).unwrap();
+        let doc = editor_state.get_document(path.as_ref()).await.unwrap();
+        assert_eq!(doc.version, 1);
+        assert!(editor_state.changed_doc_rx.try_recv().is_err());
+
+        thread::sleep(time::Duration::from_millis(10));
+        std::fs::write(&path, "changed").unwrap();
+
+        let doc = editor_state.get_document(path.as_ref()).await.unwrap();
+        assert_eq!(doc.version, 2);
+        let changed = editor_state.changed_doc_rx.try_recv().unwrap();
+        assert_eq!(changed.version, 2);
+        assert_eq!(changed.text.to_string(), "changed");
+
+        let err = editor_state
+            .apply_changes(path.as_ref(), 1, &[change])
+            .unwrap_err();
+        match EditError::from(err) {
+            EditError::VersionConflict {
+                version,
+                current_version,
+                text,
+                ..
+            } => {
+                assert_eq!(version, 1);
+                assert_eq!(current_version, 2);
+                assert_eq!(text, "changed");
+            }
+            e => panic!("Expected version conflict, got {:?}", e),
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_sync_document() {
+        create_test_workspace(true);
+
+        let path = get_test_dir().join("README.md");
+        std::fs::write(&path, "a").unwrap();
+
+        let editor_state = EditorState::default();
+        editor_state.get_document(path.as_ref()).await.unwrap();
+        assert!(editor_state.sync_document(path.as_ref()).unwrap().is_none());
+
+        // Clean documents are reloaded
+        thread::sleep(time::Duration::from_millis(10));
+        std::fs::write(&path, "b").unwrap();
+        match editor_state.sync_document(path.as_ref()).unwrap() {
+            Some(ExternalChange::Reloaded(doc)) => {
+                assert_eq!(doc.text.to_string(), "b");
+                assert_eq!(doc.version, 1);
+                assert_eq!(doc.saved_version, 1);
+            }
+            change => panic!("Expected reload, got {:?}", change),
+        }
+
+        // Unsaved edits are kept and flagged
+        editor_state
+            .insert_text(
+                path.as_ref(),
+                doc_version(&editor_state, path.as_ref()),
+                &Insert {
+                    from_a: 0,
+                    text: "x".to_string(),
+                },
+            )
+            .unwrap();
+        thread::sleep(time::Duration::from_millis(10));
+        std::fs::write(&path, "c").unwrap();
+        match editor_state.sync_document(path.as_ref()).unwrap() {
+            Some(ExternalChange::Conflict(doc)) => {
+                assert_eq!(doc.text.to_string(), "xb");
+                assert!(doc.conflicted);
+            }
+            change => panic!("Expected conflict, got {:?}", change),
+        }
+        let doc = editor_state.get_document(path.as_ref()).await.unwrap();
+        assert_eq!(doc.text.to_string(), "xb");
+
+        // Saving resolves the conflict
+        editor_state.write_document(path.as_ref()).unwrap();
+        let doc = editor_state.get_document(path.as_ref()).await.unwrap();
+        assert!(!doc.conflicted);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            editor_state.sync_document(path.as_ref()).unwrap(),
+            Some(ExternalChange::Deleted(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_dirty() {
+        create_test_workspace(true);
+
+        let path = get_test_dir().join("README.md");
+        std::fs::write(&path, "a").unwrap();
+
+        let editor_state = EditorState::default();
+        let doc = editor_state.get_document(path.as_ref()).await.unwrap();
+        assert!(!doc.is_dirty);
+        assert_eq!(
+            doc.disk_modified,
+            Some(std::fs::metadata(&path).unwrap().modified().unwrap())
+        );
+
+        let insert = Insert {
+            from_a: 1,
+            text: "b".to_string(),
+        };
+        let tx = editor_state
+            .insert_text(
+                path.as_ref(),
+                doc_version(&editor_state, path.as_ref()),
+                &insert,
+            )
+            .unwrap();
+        assert!(tx.doc.is_dirty);
+        assert_eq!(tx.doc.saved_version, 0);
+        assert_eq!(tx.doc.disk_modified, doc.disk_modified);
+        assert_eq!(editor_state.list_dirty_documents().len(), 1);
+
+        // Undoing the edit restores the saved text
+        let delete = Delete { from_a: 1, to_a: 2 };
+        let tx = editor_state
+            .delete_text(
+                path.as_ref(),
+                doc_version(&editor_state, path.as_ref()),
+                &delete,
+            )
+            .unwrap();
+        assert!(!tx.doc.is_dirty);
+        assert!(editor_state.list_dirty_documents().is_empty());
+
+        editor_state
+            .insert_text(
+                path.as_ref(),
+                doc_version(&editor_state, path.as_ref()),
+                &insert,
+            )
+            .unwrap();
+        editor_state.write_document(path.as_ref()).unwrap();
+        let doc = editor_state.get_document(path.as_ref()).await.unwrap();
+        assert!(!doc.is_dirty);
+        assert_eq!(doc.saved_version, 3);
+        assert!(editor_state.list_dirty_documents().is_empty());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_file_format() {
+        create_test_workspace(true);
+
+        let path = get_test_dir().join("README.md");
+        std::fs::write(&path, "\u{feff}a\r\nb\r\n").unwrap();
+
+        let editor_state = EditorState::default();
+        let doc = editor_state.get_document(path.as_ref()).await.unwrap();
+        assert_eq!(doc.text.to_string(), "a\nb");
+        assert_eq!(doc.format.line_ending, LineEnding::Crlf);
+        assert!(doc.format.bom);
+        assert!(doc.format.final_newline);
+
+        let insert = Insert {
+            from_a: 3,
+            text: "\nc".to_string(),
+        };
+        editor_state
+            .insert_text(
+                path.as_ref(),
+                doc_version(&editor_state, path.as_ref()),
+                &insert,
+            )
+            .unwrap();
+        editor_state.write_document(path.as_ref()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "\u{feff}a\r\nb\r\nc\r\n"
+        );
+
+        let format = FileFormat {
+            line_ending: LineEnding::Lf,
+            bom: false,
+            final_newline: true,
+            ..FileFormat::default()
+        };
+        let doc = editor_state.set_file_format(path.as_ref(), format).unwrap();
+        assert!(doc.is_dirty);
+        editor_state.write_document(path.as_ref()).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\nc\n");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_encoding() {
+        create_test_workspace(true);
+
+        let path = get_test_dir().join("README.md");
+        std::fs::write(&path, b"caf\xe9 cr\xe8me br\xfbl\xe9e\n").unwrap();
+
+        let editor_state = EditorState::default();
+        let doc = editor_state.get_document(path.as_ref()).await.unwrap();
+        assert_eq!(doc.text.to_string(), "café crème brûlée");
+        assert_eq!(doc.format.encoding, encoding_rs::WINDOWS_1252);
+
+        let insert = Insert {
+            from_a: 0,
+            text: "à ".to_string(),
+        };
+        editor_state
+            .insert_text(
+                path.as_ref(),
+                doc_version(&editor_state, path.as_ref()),
+                &insert,
+            )
+            .unwrap();
+        editor_state.write_document(path.as_ref()).unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"\xe0 caf\xe9 cr\xe8me br\xfbl\xe9e\n"
+        );
+
+        let doc = editor_state
+            .reopen_with_encoding(path.as_ref(), encoding_rs::ISO_8859_7)
+            .await
+            .unwrap();
+        assert_eq!(doc.format.encoding, encoding_rs::ISO_8859_7);
+        assert_eq!(doc.text.to_string(), "ΰ cafι crθme brϋlιe");
+        assert!(!doc.is_dirty);
+        assert_eq!(editor_state.changed_doc_rx.recv().await.unwrap().version, 2);
+
+        let binary = get_test_dir().join("image.png");
+        std::fs::write(&binary, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        let err = editor_state
+            .get_document(binary.as_ref())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Binary"));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_large_file() {
+        create_test_workspace(true);
+
+        let path = get_test_dir().join("README.md");
+        let contents: String = (0..100).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(&path, &contents).unwrap();
+
+        let editor_state = EditorState::with_large_file_size(100);
+        let doc = editor_state.get_document(path.as_ref()).await.unwrap();
+        assert!(doc.large_file);
+        assert!(doc.get_language_server_id().is_none());
+
+        let lines = editor_state.read_lines(path.as_ref(), 98, 10).unwrap();
+        assert_eq!(lines.start, 98);
+        assert_eq!(lines.lines, vec!["line 98", "line 99"]);
+        assert_eq!(lines.total, 100);
+
+        let lines = editor_state.read_lines(path.as_ref(), 200, 10).unwrap();
+        assert_eq!(lines.start, 100);
+        assert!(lines.lines.is_empty());
+
+        let version = doc_version(&editor_state, path.as_ref());
+        let insert = Insert {
+            from_a: 0,
+            text: "x".to_string(),
+        };
+        editor_state
+            .insert_text(path.as_ref(), version, &insert)
+            .unwrap();
+        let delete = Delete { from_a: 0, to_a: 1 };
+        editor_state
+            .delete_text(path.as_ref(), version + 1, &delete)
+            .unwrap();
+
+        // Any edit makes large files dirty
+        let doc = editor_state.get_document(path.as_ref()).await.unwrap();
+        assert!(doc.is_dirty);
+        editor_state.write_document(path.as_ref()).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), contents);
+
+        // Pages are edited with offsets from their first line
+        let contents: String = (0..3000).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(&path, &contents).unwrap();
+        let editor_state = EditorState::with_large_file_size(100);
+        editor_state.get_document(path.as_ref()).await.unwrap();
+        let changes = [
+            Change {
+                from: 0,
+                to: 4,
+                text: "LINE".to_string(),
+            },
+            Change {
+                from: 9,
+                to: 9,
+                text: "\nnew".to_string(),
+            },
+        ];
+        let tx = editor_state
+            .apply_line_changes(path.as_ref(), 0, 2000, &changes)
+            .unwrap();
+        assert_eq!(tx.changes[0].utf16.start, Position::new(2000, 9));
+        assert!(tx.doc.text.len_chars() == 0);
+        let delete = Change {
+            from: 0,
+            to: 8,
+            text: "".to_string(),
+        };
+        editor_state
+            .apply_line_changes(path.as_ref(), 1, 10, &[delete])
+            .unwrap();
+
+        let lines = editor_state.read_lines(path.as_ref(), 1998, 4).unwrap();
+        assert_eq!(
+            lines.lines,
+            vec!["line 1999", "LINE 2000", "new", "line 2001"]
+        );
+        assert_eq!(lines.total, 3000);
+
+        // Saving streams the file with the edited lines
+        editor_state.write_document(path.as_ref()).unwrap();
+        let expected = contents
+            .replace("line 10\n", "")
+            .replace("line 2000\n", "LINE 2000\nnew\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+        let doc = editor_state.get_document(path.as_ref()).await.unwrap();
+        assert!(!doc.is_dirty);
+        let lines = editor_state.read_lines(path.as_ref(), 1999, 2).unwrap();
+        assert_eq!(lines.lines, vec!["LINE 2000", "new"]);
+
+        // External changes renew the line index
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(&path, "a\nb\n").unwrap();
+        editor_state.sync_document(path.as_ref()).unwrap();
+        let lines = editor_state.read_lines(path.as_ref(), 0, 10).unwrap();
+        assert_eq!(lines.lines, vec!["a", "b"]);
+
+        // or conflict with unsaved edits
+        let version = doc_version(&editor_state, path.as_ref());
+        let change = Change {
+            from: 0,
+            to: 1,
+            text: "A".to_string(),
+        };
+        editor_state
+            .apply_changes(path.as_ref(), version, &[change])
+            .unwrap();
+        assert_eq!(
+            editor_state.read_lines(path.as_ref(), 0, 10).unwrap().lines,
+            vec!["A", "b"]
+        );
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(&path, "c\n").unwrap();
+        assert!(matches!(
+            editor_state.sync_document(path.as_ref()).unwrap(),
+            Some(ExternalChange::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_undo_redo() {
+        create_test_workspace(true);
+
+        let path = get_test_dir().join("README.md");
+        std::fs::write(&path, "a😀b\ncd").unwrap();
+
+        let editor_state = EditorState::default();
+        editor_state.get_document(&path).await.unwrap();
+
+        // A batch with inserts at the same offset and a multi-byte deletion
+        let changes = vec![
+            Change {
+                from: 0,
+                to: 0,
+                text: "x".to_string(),
+            },
+            Change {
+                from: 0,
+                to: 0,
+                text: "y".to_string(),
+            },
+            Change {
+                from: 1,
+                to: 3,
+                text: "é".to_string(),
+            },
+            Change {
+                from: 5,
+                to: 6,
+                text: "".to_string(),
+            },
+        ];
+        let tx = editor_state.apply_changes(&path, 0, &changes).unwrap();
+        assert_eq!(tx.doc.text.to_string(), "xyaéb\nd");
+
+        let (result, txs) = editor_state.undo(&path).unwrap().unwrap();
+        assert_eq!(result.document.text.to_string(), "a😀b\ncd");
+        assert_eq!(result.document.version, 2);
+        assert_eq!(txs.len(), 1);
+        assert_eq!(result.steps, vec![tx.inverse.clone()]);
+        assert!(!result.document.is_dirty);
+
+        let (result, _) = editor_state.redo(&path).unwrap().unwrap();
+        assert_eq!(result.document.text.to_string(), "xyaéb\nd");
+        assert!(editor_state.redo(&path).unwrap().is_none());
+
+        // Edits in quick succession are undone together
+        let insert = Insert {
+            from_a: 0,
+            text: "1".to_string(),
+        };
+        editor_state
+            .insert_text(&path, doc_version(&editor_state, &path), &insert)
+            .unwrap();
+        let delete = Delete { from_a: 0, to_a: 2 };
+        editor_state
+            .delete_text(&path, doc_version(&editor_state, &path), &delete)
+            .unwrap();
+
+        let (result, txs) = editor_state.undo(&path).unwrap().unwrap();
+        assert_eq!(txs.len(), 2);
+        assert_eq!(result.document.text.to_string(), "xyaéb\nd");
+
+        let (result, _) = editor_state.redo(&path).unwrap().unwrap();
+        assert_eq!(result.document.text.to_string(), "yaéb\nd");
+
+        // Replacing the text is undoable and a new edit drops the redo stack
+        let update = UpdateDocument {
+            text: "new".to_string(),
+            language: None,
+        };
+        editor_state.replace_text(&path, &update).unwrap();
+        let (result, _) = editor_state.undo(&path).unwrap().unwrap();
+        assert_eq!(result.document.text.to_string(), "yaéb\nd");
+        editor_state
+            .insert_text(&path, doc_version(&editor_state, &path), &insert)
+            .unwrap();
+        assert!(editor_state.redo(&path).unwrap().is_none());
+
+        // A group with an invalid step is not applied partly
+        let doc = editor_state.get_document(&path).await.unwrap();
+        let valid = Change {
+            from: 0,
+            to: 0,
+            text: "z".to_string(),
+        };
+        let invalid = Change {
+            from: 0,
+            to: 100,
+            text: String::new(),
+        };
+        editor_state.record_undo(&path, vec![invalid]);
+        editor_state.record_undo(&path, vec![valid]);
+        assert!(editor_state.undo(&path).is_err());
+        let reverted = editor_state.get_document(&path).await.unwrap();
+        assert_eq!(reverted.text, doc.text);
+        assert_eq!(reverted.version, doc.version);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_replace_all() {
+        create_test_workspace(true);
+
+        let path = get_test_dir().join("README.md");
+        std::fs::write(&path, "foo 😀 foo\nfoo").unwrap();
+
+        let editor_state = EditorState::default();
+        editor_state.get_document(&path).await.unwrap();
+
+        let options = SearchOptions::default();
+        let matches = editor_state
+            .search_document(&path, "foo", &options)
+            .unwrap();
+        assert_eq!(matches.len(), 3);
+        assert_eq!((matches[1].from, matches[1].to), (7, 10));
+
+        let tx = editor_state
+            .replace_all(&path, 0, "foo", "barbaz", &options)
+            .unwrap();
+        assert_eq!(tx.doc.text.to_string(), "barbaz 😀 barbaz\nbarbaz");
+        assert_eq!(tx.doc.version, 1);
+        assert_eq!(tx.changes.len(), 3);
+
+        // Undone as a whole
+        let (result, _) = editor_state.undo(&path).unwrap().unwrap();
+        assert_eq!(result.document.text.to_string(), "foo 😀 foo\nfoo");
+
+        let err = editor_state
+            .replace_all(&path, 1, "foo", "bar", &options)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast::<EditError>().unwrap(),
+            EditError::VersionConflict { .. }
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn test_worktree_path() {
+        create_test_workspace(false);
+        let dir = get_test_dir();
+        let src = dir.join("src");
+        let main = src.join("main.rs");
+        let editor_state = EditorState::default();
+        assert_eq!(editor_state.get_worktree_path(&main), None);
+
+        // Only cargo workspaces are roots
+        std::fs::write(src.join("Cargo.toml"), "[package]\n").unwrap();
+        assert_eq!(editor_state.get_worktree_path(&main), None);
+        std::fs::write(dir.join("Cargo.toml"), "[workspace]\n").unwrap();
+        assert_eq!(editor_state.get_worktree_path(&main), Some(dir.clone()));
+
+        std::fs::write(src.join("package.json"), "{}").unwrap();
+        assert_eq!(editor_state.get_worktree_path(&main), Some(src.clone()));
+
+        // Git repos win over markers
+        std::fs::create_dir_all(dir.join("repo")).unwrap();
+        std::fs::write(dir.join("repo").join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        std::fs::write(src.join(".git"), "gitdir: ../repo\n").unwrap();
+        assert_eq!(editor_state.get_worktree_path(&main), Some(src.clone()));
+        std::fs::remove_file(src.join(".git")).unwrap();
+
+        *editor_state.root_markers.write().unwrap() = vec![".tinywrite".to_string()];
+        assert_eq!(editor_state.get_worktree_path(&main), None);
+        std::fs::create_dir(dir.join(".tinywrite")).unwrap();
+        assert_eq!(editor_state.get_worktree_path(&src), Some(dir));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_write_editorconfig() {
+        create_test_workspace(true);
+        let dir = get_test_dir();
+        let config = "[*.md]\nend_of_line = crlf\ninsert_final_newline = false";
+        std::fs::write(dir.join(".editorconfig"), config).unwrap();
+        let path = dir.join("README.md");
+        std::fs::write(&path, "a\nb\n").unwrap();
+
+        let editor_state = EditorState::default();
+        let doc = editor_state.get_document(&path).await.unwrap();
+        assert_eq!(doc.editor_config.end_of_line, Some(LineEnding::Crlf));
+        assert_eq!(doc.format.line_ending, LineEnding::Crlf);
+        assert!(!doc.is_dirty);
+
+        editor_state.write_document(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\r\nb");
+        let doc = editor_state.get_document(&path).await.unwrap();
+        assert!(!doc.is_dirty);
+        assert!(!doc.format.final_newline);
+
+        // A format chosen by the user is kept on save
+        let format = FileFormat {
+            line_ending: LineEnding::Lf,
+            ..doc.format
+        };
+        editor_state.set_file_format(&path, format).unwrap();
+        editor_state.write_document(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb");
+    }
 }
@@ -0,0 +1,314 @@
+use std::fs;
+use std::path::Path;
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use globset::GlobBuilder;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::editor_state::Change;
+use super::file_format::{FileFormat, LineEnding};
+
+pub const EDITORCONFIG_FILE: &str = ".editorconfig";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IndentStyle {
+    Tab,
+    Space,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Charset {
+    Latin1,
+    Utf8,
+    Utf8Bom,
+    Utf16be,
+    Utf16le,
+}
+
+impl Charset {
+    fn encoding(&self) -> (&'static Encoding, bool) {
+        match self {
+            Charset::Latin1 => (WINDOWS_1252, false),
+            Charset::Utf8 => (UTF_8, false),
+            Charset::Utf8Bom => (UTF_8, true),
+            Charset::Utf16be => (UTF_16BE, true),
+            Charset::Utf16le => (UTF_16LE, true),
+        }
+    }
+}
+
+// The properties of all `.editorconfig` sections that match a file. Unset
+// properties keep the format of the file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EditorConfig {
+    pub indent_style: Option<IndentStyle>,
+    // None for `indent_size = tab`, which means the tab width
+    pub indent_size: Option<u32>,
+    pub tab_width: Option<u32>,
+    pub end_of_line: Option<LineEnding>,
+    pub charset: Option<Charset>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+}
+
+impl EditorConfig {
+    // Reads the `.editorconfig` files from the dir of `path` up to the worktree
+    // root, or until a file with `root = true`. Closer files win.
+    pub fn resolve(path: &Path, worktree_path: Option<&Path>) -> Self {
+        let mut files = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            if let Ok(contents) = fs::read_to_string(dir.join(EDITORCONFIG_FILE)) {
+                let root = is_root(&contents);
+                files.push((dir, contents));
+                if root {
+                    break;
+                }
+            }
+            if Some(dir) == worktree_path {
+                break;
+            }
+        }
+
+        let mut config = EditorConfig::default();
+        for (dir, contents) in files.iter().rev() {
+            if let Ok(relative_path) = path.strip_prefix(dir) {
+                let relative_path = relative_path.to_string_lossy().replace('\\', "/");
+                config.parse(contents, &relative_path);
+            }
+        }
+
+        debug!(
+            "Resolved editorconfig (path={:?}, config={:?})",
+            path, config
+        );
+        config
+    }
+
+    pub fn indent_width(&self) -> Option<u32> {
+        self.indent_size.or(self.tab_width)
+    }
+
+    pub fn use_tabs(&self) -> Option<bool> {
+        self.indent_style.map(|style| style == IndentStyle::Tab)
+    }
+
+    // Overrides the detected format of an opened file. A charset that cannot
+    // encode the text is ignored.
+    pub fn apply_format(&self, format: &mut FileFormat, text: &Rope) {
+        if let Some(line_ending) = self.end_of_line {
+            format.line_ending = line_ending;
+        }
+        if let Some(final_newline) = self.insert_final_newline {
+            format.final_newline = final_newline;
+        }
+        if let Some(charset) = self.charset {
+            let (encoding, bom) = charset.encoding();
+            if FileFormat::can_encode(encoding, text) {
+                (format.encoding, format.bom) = (encoding, bom);
+            }
+        }
+    }
+
+    // Changes that remove trailing whitespace, empty if not enabled
+    pub fn trim_changes(&self, text: &Rope) -> Vec<Change> {
+        if self.trim_trailing_whitespace != Some(true) {
+            return Vec::new();
+        }
+
+        let mut changes = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.to_string();
+            let content = line.trim_end_matches(['\n', '\r']);
+            let trimmed = content.trim_end_matches([' ', '\t']);
+            if trimmed.len() == content.len() {
+                continue;
+            }
+
+            let start = text.char_to_utf16_cu(text.line_to_char(i));
+            let from = start + trimmed.encode_utf16().count();
+            let to = start + content.encode_utf16().count();
+            changes.push(Change {
+                from,
+                to,
+                text: String::new(),
+            });
+        }
+        changes
+    }
+
+    // Applies the sections of one file that match the path relative to it
+    fn parse(&mut self, contents: &str, relative_path: &str) {
+        let mut matches = false;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                matches = section_matches(section, relative_path);
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            if matches {
+                self.set(&key.trim().to_lowercase(), &value.trim().to_lowercase());
+            }
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "indent_style" => {
+                self.indent_style = match value {
+                    "tab" => Some(IndentStyle::Tab),
+                    "space" => Some(IndentStyle::Space),
+                    _ => None,
+                }
+            }
+            "indent_size" => self.indent_size = value.parse().ok(),
+            "tab_width" => self.tab_width = value.parse().ok(),
+            "end_of_line" => {
+                // CR line endings are not supported
+                self.end_of_line = match value {
+                    "lf" => Some(LineEnding::Lf),
+                    "crlf" => Some(LineEnding::Crlf),
+                    _ => None,
+                }
+            }
+            "charset" => {
+                self.charset = match value {
+                    "latin1" => Some(Charset::Latin1),
+                    "utf-8" => Some(Charset::Utf8),
+                    "utf-8-bom" => Some(Charset::Utf8Bom),
+                    "utf-16be" => Some(Charset::Utf16be),
+                    "utf-16le" => Some(Charset::Utf16le),
+                    _ => None,
+                }
+            }
+            // Invalid values and `unset` clear the property
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = value.parse().ok(),
+            "insert_final_newline" => self.insert_final_newline = value.parse().ok(),
+            _ => {}
+        }
+    }
+}
+
+// `root = true` must be in the preamble before the first section
+fn is_root(contents: &str) -> bool {
+    contents
+        .lines()
+        .map(str::trim)
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(|line| line.split_once('='))
+        .any(|(key, value)| {
+            key.trim().eq_ignore_ascii_case("root") && value.trim().eq_ignore_ascii_case("true")
+        })
+}
+
+// Patterns without a slash match the file name in any dir, others are
+// relative to the dir of the `.editorconfig` file
+fn section_matches(section: &str, relative_path: &str) -> bool {
+    let pattern = match section.strip_prefix('/') {
+        Some(pattern) => pattern.to_string(),
+        None if section.contains('/') => section.to_string(),
+        None => format!("**/{}", section),
+    };
+
+    GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher().is_match(relative_path))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::{UTF_8, WINDOWS_1252};
+    use ropey::Rope;
+    use serial_test::serial;
+
+    use crate::editor::editor_state::Change;
+    use crate::editor::file_format::{FileFormat, LineEnding};
+    use crate::editor::search::apply_replacements;
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+
+    use super::{EditorConfig, IndentStyle, EDITORCONFIG_FILE};
+
+    #[test]
+    #[serial]
+    fn test_resolve() {
+        create_test_workspace(true);
+        let dir = get_test_dir();
+        let root_config = [
+            "root = true",
+            "[*]",
+            "indent_style = space",
+            "indent_size = 2",
+            "end_of_line = lf",
+            "[*.{rs,toml}]",
+            "indent_size = 4",
+            "[src/*.ts]",
+            "charset = latin1",
+        ]
+        .join("\n");
+        std::fs::write(dir.join(EDITORCONFIG_FILE), root_config).unwrap();
+        let src_config = ["[main.rs]", "indent_style = Tab", "end_of_line = unset"].join("\n");
+        std::fs::write(dir.join("src").join(EDITORCONFIG_FILE), src_config).unwrap();
+
+        let config = EditorConfig::resolve(&dir.join("README.md"), Some(&dir));
+        assert_eq!(config.indent_style, Some(IndentStyle::Space));
+        assert_eq!(config.indent_width(), Some(2));
+        assert_eq!(config.end_of_line, Some(LineEnding::Lf));
+        assert_eq!(config.charset, None);
+
+        let main = dir.join("src").join("main.rs");
+        let config = EditorConfig::resolve(&main, Some(&dir));
+        assert_eq!(config.use_tabs(), Some(true));
+        assert_eq!(config.indent_width(), Some(4));
+        assert_eq!(config.end_of_line, None);
+
+        let config = EditorConfig::resolve(&dir.join("src").join("index.ts"), Some(&dir));
+        assert_eq!(config.indent_width(), Some(2));
+        let mut format = FileFormat::default();
+        config.apply_format(&mut format, &Rope::from_str("é"));
+        assert_eq!(format.encoding, WINDOWS_1252);
+        let mut format = FileFormat::default();
+        config.apply_format(&mut format, &Rope::from_str("😀"));
+        assert_eq!(format.encoding, UTF_8);
+
+        // Files above the worktree are not read
+        let config = EditorConfig::resolve(&main, Some(&dir.join("src")));
+        assert_eq!(config.indent_size, None);
+        assert_eq!(config.use_tabs(), Some(true));
+    }
+
+    #[test]
+    fn test_trim_changes() {
+        let config = EditorConfig {
+            trim_trailing_whitespace: Some(true),
+            ..Default::default()
+        };
+        let mut text = Rope::from_str("a  \n😀\t\nb\n  ");
+        let changes = config.trim_changes(&text);
+        assert_eq!(
+            changes[0],
+            Change {
+                from: 1,
+                to: 3,
+                text: "".to_string()
+            }
+        );
+        apply_replacements(&mut text, &changes);
+        assert_eq!(text.to_string(), "a\n😀\nb\n");
+
+        assert!(EditorConfig::default().trim_changes(&text).is_empty());
+    }
+}
@@ -0,0 +1,405 @@
+use std::io::{self, Write};
+
+use anyhow::anyhow;
+use chardetng::EncodingDetector;
+use encoding_rs::{DecoderResult, Encoder, EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+
+// Same heuristic as git, a NUL byte in the first 8000 bytes means binary
+const BINARY_CHECK_LEN: usize = 8000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+}
+
+// How the text is stored on disk. Documents keep their text normalized to LF
+// without BOM and final newline, the format is restored on save.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileFormat {
+    pub encoding: &'static Encoding,
+    pub line_ending: LineEnding,
+    pub bom: bool,
+    pub final_newline: bool,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            line_ending: LineEnding::default(),
+            bom: false,
+            final_newline: true,
+        }
+    }
+}
+
+impl FileFormat {
+    // Detects the format of the file contents and returns the normalized text.
+    // Without an explicit encoding it is guessed from the BOM or the contents.
+    pub fn decode(
+        bytes: &[u8],
+        encoding: Option<&'static Encoding>,
+    ) -> anyhow::Result<(FileFormat, String)> {
+        let (raw, contents) = Self::decode_raw(bytes, encoding)?;
+        let (format, text) = Self::detect(&contents);
+        let format = FileFormat {
+            encoding: raw.encoding,
+            bom: raw.bom,
+            ..format
+        };
+        Ok((format, text))
+    }
+
+    // Decodes the contents without normalizing them. Written with the returned
+    // format, line endings and final newline stay as they are, even if mixed.
+    pub fn decode_raw(
+        bytes: &[u8],
+        encoding: Option<&'static Encoding>,
+    ) -> anyhow::Result<(FileFormat, String)> {
+        let (encoding, bom_len) = match (Encoding::for_bom(bytes), encoding) {
+            (Some((bom_encoding, len)), Some(encoding)) if bom_encoding == encoding => {
+                (encoding, len)
+            }
+            (_, Some(encoding)) => (encoding, 0),
+            (Some((bom_encoding, len)), None) => (bom_encoding, len),
+            (None, None) => {
+                // UTF-16 text has NUL bytes too, so it is checked first
+                if let Some(encoding) = guess_utf16(bytes) {
+                    (encoding, 0)
+                } else if is_binary(bytes) {
+                    return Err(anyhow!("Binary files are not supported"));
+                } else {
+                    (guess_encoding(bytes), 0)
+                }
+            }
+        };
+
+        let (contents, _) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+        let format = FileFormat {
+            encoding,
+            line_ending: LineEnding::Lf,
+            bom: bom_len > 0,
+            final_newline: false,
+        };
+        Ok((format, contents.into_owned()))
+    }
+
+    // Detects line endings and final newline of decoded contents
+    pub fn detect(contents: &str) -> (FileFormat, String) {
+        if contents.is_empty() {
+            return (FileFormat::default(), String::new());
+        }
+
+        // Mixed files get the line ending that is used the most
+        let crlf = contents.matches("\r\n").count();
+        let lf = contents.matches('\n').count() - crlf;
+        let line_ending = if crlf > lf {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        };
+
+        let mut text = contents.replace("\r\n", "\n");
+        let final_newline = text.ends_with('\n');
+        if final_newline {
+            text.pop();
+        }
+
+        let format = FileFormat {
+            line_ending,
+            final_newline,
+            ..FileFormat::default()
+        };
+
+        (format, text)
+    }
+
+    // Encodes the text chunk by chunk, the whole file is never in memory
+    pub fn write(&self, text: &Rope, w: &mut dyn Write) -> io::Result<()> {
+        self.write_chunks(w, |f| text.chunks().try_for_each(f))
+    }
+
+    // Like `write` with the text of a source that passes its chunks to `f`
+    pub fn write_chunks<C>(&self, w: &mut dyn Write, chunks: C) -> io::Result<()>
+    where
+        C: FnOnce(&mut dyn FnMut(&str) -> io::Result<()>) -> io::Result<()>,
+    {
+        let mut w = io::BufWriter::new(w);
+        let mut encoder = ChunkEncoder::new(self.encoding);
+        if self.bom {
+            encoder.write("\u{feff}", &mut w)?;
+        }
+
+        chunks(&mut |chunk| match self.line_ending {
+            LineEnding::Lf => encoder.write(chunk, &mut w),
+            LineEnding::Crlf => encoder.write(&chunk.replace('\n', "\r\n"), &mut w),
+        })?;
+
+        if self.final_newline {
+            encoder.write(self.line_ending.as_str(), &mut w)?;
+        }
+
+        encoder.finish(&mut w)?;
+        w.flush()
+    }
+
+    // Whether every character of the text exists in the encoding
+    pub fn can_encode(encoding: &'static Encoding, text: &Rope) -> bool {
+        if encoding == UTF_8 || encoding == UTF_16LE || encoding == UTF_16BE {
+            return true;
+        }
+        text.chunks().all(|chunk| !encoding.encode(chunk).2)
+    }
+}
+
+// Keeps the state of stateful encodings like ISO-2022-JP between chunks
+struct ChunkEncoder {
+    encoding: &'static Encoding,
+    encoder: Encoder,
+    buf: Vec<u8>,
+}
+
+impl ChunkEncoder {
+    fn new(encoding: &'static Encoding) -> Self {
+        Self {
+            encoding,
+            encoder: encoding.new_encoder(),
+            buf: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, text: &str, w: &mut dyn Write) -> io::Result<()> {
+        self.encode(text, false)?;
+        w.write_all(&self.buf)
+    }
+
+    fn finish(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        self.encode("", true)?;
+        w.write_all(&self.buf)
+    }
+
+    fn encode(&mut self, mut text: &str, last: bool) -> io::Result<()> {
+        self.buf.clear();
+
+        // encoding_rs only decodes UTF-16
+        if self.encoding == UTF_16LE {
+            self.buf
+                .extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+            return Ok(());
+        } else if self.encoding == UTF_16BE {
+            self.buf
+                .extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+            return Ok(());
+        }
+
+        loop {
+            let needed = self
+                .encoder
+                .max_buffer_length_from_utf8_without_replacement(text.len())
+                .ok_or_else(|| io::Error::other("Text is too long"))?;
+            self.buf.reserve(needed);
+            let (result, read) =
+                self.encoder
+                    .encode_from_utf8_to_vec_without_replacement(text, &mut self.buf, last);
+            text = &text[read..];
+
+            match result {
+                EncoderResult::InputEmpty => return Ok(()),
+                EncoderResult::OutputFull => {}
+                EncoderResult::Unmappable(_) => {
+                    return Err(io::Error::other(format!(
+                        "Text cannot be encoded as {}",
+                        self.encoding.name()
+                    )))
+                }
+            }
+        }
+    }
+}
+
+// Detects UTF-16 without BOM from the NUL bytes of ASCII characters, which
+// are in every other byte
+fn guess_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(BINARY_CHECK_LEN)];
+    let units = sample.len() / 2;
+    if units == 0 {
+        return None;
+    }
+
+    let (mut even, mut odd) = (0, 0);
+    for unit in sample.chunks_exact(2) {
+        even += usize::from(unit[0] == 0);
+        odd += usize::from(unit[1] == 0);
+    }
+    let encoding = if odd * 2 >= units && even * 10 < units {
+        UTF_16LE
+    } else if even * 2 >= units && odd * 10 < units {
+        UTF_16BE
+    } else {
+        return None;
+    };
+
+    // Binary data is rarely valid UTF-16, e.g. it has unpaired surrogates
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut out =
+        String::with_capacity(decoder.max_utf8_buffer_length_without_replacement(sample.len())?);
+    let (result, _) = decoder.decode_to_string_without_replacement(sample, &mut out, false);
+    matches!(result, DecoderResult::InputEmpty).then_some(encoding)
+}
+
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(BINARY_CHECK_LEN).any(|b| *b == 0)
+}
+
+fn guess_encoding(bytes: &[u8]) -> &'static Encoding {
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector.guess(None, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::{ISO_2022_JP, SHIFT_JIS, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+    use ropey::Rope;
+
+    use super::{FileFormat, LineEnding};
+
+    fn encode(format: &FileFormat, text: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        format.write(&Rope::from_str(text), &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_detect() {
+        let (format, text) =
+            FileFormat::decode("\u{feff}a\r\nb\r\nc\nd\r\n".as_bytes(), None).unwrap();
+        assert_eq!(
+            format,
+            FileFormat {
+                encoding: UTF_8,
+                line_ending: LineEnding::Crlf,
+                bom: true,
+                final_newline: true,
+            }
+        );
+        assert_eq!(text, "a\nb\nc\nd");
+
+        let (format, text) = FileFormat::detect("a\nb");
+        assert_eq!(
+            format,
+            FileFormat {
+                encoding: UTF_8,
+                line_ending: LineEnding::Lf,
+                bom: false,
+                final_newline: false,
+            }
+        );
+        assert_eq!(text, "a\nb");
+
+        let (format, text) = FileFormat::detect("");
+        assert_eq!(format, FileFormat::default());
+        assert_eq!(text, "");
+    }
+
+    #[test]
+    fn test_write() {
+        let contents = "\u{feff}a\r\n\r\nb\r\n";
+        let (format, text) = FileFormat::decode(contents.as_bytes(), None).unwrap();
+        assert_eq!(encode(&format, &text), contents.as_bytes());
+
+        let format = FileFormat {
+            final_newline: false,
+            ..FileFormat::default()
+        };
+        assert_eq!(encode(&format, &text), b"a\n\nb");
+
+        let contents = "\u{feff}a\r\nb\nc";
+        let (format, text) = FileFormat::decode_raw(contents.as_bytes(), None).unwrap();
+        assert_eq!(text, "a\r\nb\nc");
+        assert_eq!(encode(&format, &text), contents.as_bytes());
+    }
+
+    #[test]
+    fn test_encodings() {
+        // Latin-1 without BOM is guessed
+        let latin1 = b"caf\xe9 cr\xe8me br\xfbl\xe9e\n";
+        let (format, text) = FileFormat::decode(latin1, None).unwrap();
+        assert_eq!(format.encoding, WINDOWS_1252);
+        assert_eq!(text, "café crème brûlée");
+        assert_eq!(encode(&format, &text), latin1);
+
+        // UTF-16 with BOM
+        let utf16: Vec<u8> = "\u{feff}ä😀\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let (format, text) = FileFormat::decode(&utf16, None).unwrap();
+        assert_eq!(format.encoding, UTF_16LE);
+        assert!(format.bom);
+        assert_eq!(text, "ä😀");
+        assert_eq!(encode(&format, &text), utf16);
+
+        // UTF-16 without BOM is not binary
+        let utf16: Vec<u8> = "hello\nä😀\nworld\n"
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        let (format, text) = FileFormat::decode(&utf16, None).unwrap();
+        assert_eq!(format.encoding, UTF_16BE);
+        assert!(!format.bom);
+        assert_eq!(text, "hello\nä😀\nworld");
+        assert_eq!(encode(&format, &text), utf16);
+
+        // Stateful encodings keep their state across chunks
+        let text: String = (0..2000).map(|i| format!("{} あ\n", i)).collect();
+        let format = FileFormat {
+            encoding: ISO_2022_JP,
+            ..FileFormat::default()
+        };
+        let rope = Rope::from_str(&text);
+        assert!(rope.chunks().count() > 1);
+        assert_eq!(
+            encode(&format, &text),
+            ISO_2022_JP.encode(&format!("{}\n", text)).0.into_owned()
+        );
+
+        // Explicit encoding
+        let (format, text) = FileFormat::decode(b"\x82\xa0", Some(SHIFT_JIS)).unwrap();
+        assert_eq!(format.encoding, SHIFT_JIS);
+        assert_eq!(text, "あ");
+
+        // Characters that don't exist in the encoding are an error
+        let format = FileFormat {
+            encoding: WINDOWS_1252,
+            ..FileFormat::default()
+        };
+        let mut out = Vec::new();
+        assert!(format.write(&Rope::from_str("😀"), &mut out).is_err());
+    }
+
+    #[test]
+    fn test_binary() {
+        let err = FileFormat::decode(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", None).unwrap_err();
+        assert!(err.to_string().contains("Binary"));
+    }
+}
@@ -0,0 +1,484 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use tracing::{debug, error};
+
+use crate::fs::write::write_atomic;
+
+use super::editor_state::{hash_content, is_buffer, EditorState};
+use super::file_format::FileFormat;
+use super::pathutil::stable_hash;
+
+// How often dirty documents are checked for interval snapshots
+pub const HISTORY_INTERVAL: Duration = Duration::from_secs(60);
+// Autosaves take a snapshot at most this often without a snapshot interval
+const AUTOSAVE_INTERVAL_MINUTES: u64 = 5;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Retention {
+    // Snapshots per document
+    pub max_count: usize,
+    pub max_age_days: u64,
+    // Compressed size of all snapshots in bytes
+    pub max_size: u64,
+    // Also take snapshots of documents with unsaved edits every N minutes
+    pub snapshot_interval_minutes: Option<u64>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_count: 50,
+            max_age_days: 30,
+            max_size: 50 * 1024 * 1024,
+            snapshot_interval_minutes: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    // Creation time in milliseconds since the epoch
+    pub id: u64,
+    pub path: PathBuf,
+    pub created: SystemTime,
+    // Compressed size in bytes
+    pub size: u64,
+}
+
+// Stores compressed snapshots of documents, one dir for each canonical path
+pub struct LocalHistory {
+    dir: PathBuf,
+    retention: RwLock<Retention>,
+    // Keeps ids increasing across all documents
+    last_id: Mutex<u64>,
+    // Size of all snapshots by id and dir, oldest first. The size limit is
+    // applied with it, so only the start and retention changes walk all dirs.
+    index: Mutex<BTreeMap<(u64, PathBuf), u64>>,
+    // Content hash of the latest snapshot by dir, so that saves don't read it
+    latest: Mutex<HashMap<PathBuf, u64>>,
+}
+
+impl LocalHistory {
+    // Prunes the snapshots of all documents with the stored retention
+    pub fn new(dir: PathBuf, retention: Retention) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let history = Self {
+            dir,
+            retention: RwLock::new(retention),
+            last_id: Mutex::new(0),
+            index: Mutex::new(BTreeMap::new()),
+            latest: Mutex::new(HashMap::new()),
+        };
+
+        if let Err(e) = history.prune() {
+            error!("Could not prune history: {:?}", e);
+        }
+        Ok(history)
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.retention.read().unwrap().clone()
+    }
+
+    pub fn set_retention(&self, retention: Retention) -> anyhow::Result<()> {
+        *self.retention.write().unwrap() = retention;
+        self.prune()
+    }
+
+    // Explicit saves always take a snapshot, autosaves only once per
+    // snapshot interval
+    pub fn snapshot_saved(
+        &self,
+        path: &Path,
+        text: &Rope,
+        hash: u64,
+        explicit: bool,
+    ) -> anyhow::Result<Option<Snapshot>> {
+        if explicit {
+            return self.snapshot(path, text, hash);
+        }
+
+        let minutes = self
+            .retention()
+            .snapshot_interval_minutes
+            .unwrap_or(AUTOSAVE_INTERVAL_MINUTES);
+        self.snapshot_if_due(path, text, hash, Duration::from_secs(minutes * 60))
+    }
+
+    // Stores the text unless it is the same as in the latest snapshot. `hash`
+    // is the content hash of the document.
+    pub fn snapshot(
+        &self,
+        path: &Path,
+        text: &Rope,
+        hash: u64,
+    ) -> anyhow::Result<Option<Snapshot>> {
+        let path = canonical_path(path);
+        let dir = self.snapshot_dir(&path);
+        let cached = self.latest.lock().unwrap().get(&dir).copied();
+        let unchanged = match cached {
+            Some(latest) => latest == hash,
+            // Read once per run, later snapshots compare hashes
+            None => match self.list(&path)?.first() {
+                Some(latest) => *text == self.read(&path, latest.id)?.as_str(),
+                None => false,
+            },
+        };
+        if unchanged {
+            self.latest.lock().unwrap().insert(dir, hash);
+            return Ok(None);
+        }
+
+        fs::create_dir_all(&dir)?;
+
+        // Snapshots within the same millisecond get the next free id
+        let id = {
+            let mut last_id = self.last_id.lock().unwrap();
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+            *last_id = now.max(*last_id + 1);
+            *last_id
+        };
+
+        debug!("Create history snapshot (path={:?}, id={})", path, id);
+        let file = dir.join(snapshot_file_name(id));
+        write_atomic(&file, |w| {
+            let mut encoder = GzEncoder::new(w, Compression::default());
+            for chunk in text.chunks() {
+                encoder.write_all(chunk.as_bytes())?;
+            }
+            encoder.finish()?;
+            Ok(())
+        })?;
+
+        let snapshot = Snapshot {
+            id,
+            path,
+            created: UNIX_EPOCH + Duration::from_millis(id),
+            size: fs::metadata(&file)?.len(),
+        };
+
+        self.index
+            .lock()
+            .unwrap()
+            .insert((id, dir.clone()), snapshot.size);
+        self.latest.lock().unwrap().insert(dir.clone(), hash);
+        let retention = self.retention();
+        self.prune_dir(&dir, &retention)?;
+        self.prune_size(retention.max_size)?;
+        Ok(Some(snapshot))
+    }
+
+    // Takes snapshots of dirty documents if the interval is enabled and the
+    // latest snapshot is older than the interval
+    pub fn snapshot_dirty(&self, editor_state: &EditorState) -> anyhow::Result<()> {
+        let Some(minutes) = self.retention.read().unwrap().snapshot_interval_minutes else {
+            return Ok(());
+        };
+
+        let interval = Duration::from_secs(minutes * 60);
+        let docs: Vec<(PathBuf, Rope, FileFormat)> = editor_state
+            .documents
+            .read()
+            .unwrap()
+            .values()
+            .filter(|doc| doc.is_dirty && !doc.large_file && !is_buffer(&doc.path))
+            .map(|doc| (doc.path.clone(), doc.text.clone(), doc.format))
+            .collect();
+
+        for (path, text, format) in docs {
+            let hash = hash_content(&text, &format);
+            self.snapshot_if_due(&path, &text, hash, interval)?;
+        }
+
+        Ok(())
+    }
+
+    // Takes a snapshot unless the latest one is younger than `interval`
+    fn snapshot_if_due(
+        &self,
+        path: &Path,
+        text: &Rope,
+        hash: u64,
+        interval: Duration,
+    ) -> anyhow::Result<Option<Snapshot>> {
+        if let Some(latest) = self.list(path)?.first() {
+            if latest.created.elapsed().unwrap_or_default() < interval {
+                return Ok(None);
+            }
+        }
+        self.snapshot(path, text, hash)
+    }
+
+    // Lists the snapshots of a path, newest first
+    pub fn list(&self, path: &Path) -> anyhow::Result<Vec<Snapshot>> {
+        let path = canonical_path(path);
+        let mut snapshots = self.list_dir(&self.snapshot_dir(&path))?;
+        for snapshot in snapshots.iter_mut() {
+            snapshot.path = path.clone();
+        }
+
+        Ok(snapshots)
+    }
+
+    pub fn read(&self, path: &Path, id: u64) -> anyhow::Result<String> {
+        let file = self
+            .snapshot_dir(&canonical_path(path))
+            .join(snapshot_file_name(id));
+        let file = File::open(&file).map_err(|_| anyhow!("Snapshot not found (id={})", id))?;
+
+        let mut text = String::new();
+        GzDecoder::new(file).read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    // Returns a unified diff from the snapshot to the current text
+    pub fn diff(&self, path: &Path, id: u64, text: &Rope) -> anyhow::Result<String> {
+        let old = self.read(path, id)?;
+        let new = text.to_string();
+        let diff = TextDiff::from_lines(&old, &new)
+            .unified_diff()
+            .header(&format!("snapshot {}", id), "current")
+            .to_string();
+        Ok(diff)
+    }
+
+    // Removes snapshots of all documents that exceed count, age or total size
+    // limits and rebuilds the index
+    fn prune(&self) -> anyhow::Result<()> {
+        let retention = self.retention();
+        let mut index = BTreeMap::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let dir = entry?.path();
+            if !dir.is_dir() {
+                continue;
+            }
+
+            for snapshot in self.prune_dir(&dir, &retention)? {
+                index.insert((snapshot.id, dir.clone()), snapshot.size);
+            }
+        }
+
+        // Ids of the last run can be ahead of the clock
+        if let Some((id, _)) = index.keys().next_back() {
+            let mut last_id = self.last_id.lock().unwrap();
+            *last_id = (*last_id).max(*id);
+        }
+        *self.index.lock().unwrap() = index;
+        self.prune_size(retention.max_size)
+    }
+
+    // Removes the snapshots of one document that exceed count or age limits
+    // and returns the others
+    fn prune_dir(&self, dir: &Path, retention: &Retention) -> anyhow::Result<Vec<Snapshot>> {
+        let max_age = Duration::from_secs(retention.max_age_days * 24 * 60 * 60);
+        let mut kept = Vec::new();
+        for (i, snapshot) in self.list_dir(dir)?.into_iter().enumerate() {
+            let age = snapshot.created.elapsed().unwrap_or_default();
+            if i >= retention.max_count || age > max_age {
+                remove_snapshot(dir, snapshot.id)?;
+                if i == 0 {
+                    self.latest.lock().unwrap().remove(dir);
+                }
+                self.index
+                    .lock()
+                    .unwrap()
+                    .remove(&(snapshot.id, dir.to_path_buf()));
+            } else {
+                kept.push(snapshot);
+            }
+        }
+        Ok(kept)
+    }
+
+    // Drops the oldest snapshots of all documents first
+    fn prune_size(&self, max_size: u64) -> anyhow::Result<()> {
+        let mut index = self.index.lock().unwrap();
+        let mut size: u64 = index.values().sum();
+        while size > max_size {
+            let Some(((id, dir), snapshot_size)) = index.pop_first() else {
+                break;
+            };
+            remove_snapshot(&dir, id)?;
+            self.latest.lock().unwrap().remove(&dir);
+            size -= snapshot_size;
+        }
+        Ok(())
+    }
+
+    fn list_dir(&self, dir: &Path) -> anyhow::Result<Vec<Snapshot>> {
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(id) = name
+                .to_str()
+                .and_then(|n| n.strip_suffix(".gz"))
+                .and_then(|n| n.parse::<u64>().ok())
+            else {
+                continue;
+            };
+
+            snapshots.push(Snapshot {
+                id,
+                path: PathBuf::new(),
+                created: UNIX_EPOCH + Duration::from_millis(id),
+                size: entry.metadata()?.len(),
+            });
+        }
+
+        snapshots.sort_by_key(|s| std::cmp::Reverse(s.id));
+        Ok(snapshots)
+    }
+
+    fn snapshot_dir(&self, path: &Path) -> PathBuf {
+        self.dir.join(format!("{:016x}", stable_hash(path)))
+    }
+}
+
+fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or(path.to_path_buf())
+}
+
+fn snapshot_file_name(id: u64) -> String {
+    format!("{}.gz", id)
+}
+
+fn remove_snapshot(dir: &Path, id: u64) -> anyhow::Result<()> {
+    debug!("Remove history snapshot (dir={:?}, id={})", dir, id);
+    fs::remove_file(dir.join(snapshot_file_name(id)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use ropey::Rope;
+    use serial_test::serial;
+
+    use crate::editor::editor_state::hash_content;
+    use crate::editor::file_format::FileFormat;
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+
+    use super::{LocalHistory, Retention, Snapshot};
+
+    fn snapshot(history: &LocalHistory, path: &Path, text: &str) -> Option<Snapshot> {
+        let text = Rope::from_str(text);
+        let hash = hash_content(&text, &FileFormat::default());
+        history.snapshot(path, &text, hash).unwrap()
+    }
+
+    #[test]
+    #[serial]
+    fn test_history() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("README.md");
+        let history =
+            LocalHistory::new(get_test_dir().join("history"), Retention::default()).unwrap();
+
+        let first = snapshot(&history, &path, "a\nb\nc").unwrap();
+        // Unchanged text is not stored twice
+        assert!(snapshot(&history, &path, "a\nb\nc").is_none());
+        let second = snapshot(&history, &path, "a\nc").unwrap();
+
+        let snapshots = history.list(&path).unwrap();
+        assert_eq!(snapshots, vec![second.clone(), first.clone()]);
+        assert_eq!(history.read(&path, first.id).unwrap(), "a\nb\nc");
+
+        let diff = history
+            .diff(&path, first.id, &Rope::from_str("a\nc\nd"))
+            .unwrap();
+        assert!(diff.contains("-b\n"));
+        assert!(diff.contains("+d"));
+
+        assert!(history.read(&path, 1).is_err());
+
+        // After a restart the latest snapshot is read once
+        let history =
+            LocalHistory::new(get_test_dir().join("history"), Retention::default()).unwrap();
+        assert!(snapshot(&history, &path, "a\nc").is_none());
+        assert!(history.latest.lock().unwrap().len() == 1);
+
+        // Autosaves only take a snapshot once per interval
+        let text = Rope::from_str("autosaved");
+        let hash = hash_content(&text, &FileFormat::default());
+        assert!(history
+            .snapshot_saved(&path, &text, hash, false)
+            .unwrap()
+            .is_none());
+        assert!(history
+            .snapshot_saved(&path, &text, hash, true)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    #[serial]
+    fn test_retention() {
+        create_test_workspace(true);
+        let a = get_test_dir().join("README.md");
+        let b = get_test_dir().join("src").join("main.rs");
+        let history =
+            LocalHistory::new(get_test_dir().join("history"), Retention::default()).unwrap();
+
+        history
+            .set_retention(Retention {
+                max_count: 2,
+                ..Retention::default()
+            })
+            .unwrap();
+        for i in 0..4 {
+            snapshot(&history, &a, &format!("text {}", i));
+            snapshot(&history, &b, &format!("text {}", i));
+        }
+        assert_eq!(history.list(&a).unwrap().len(), 2);
+        assert_eq!(history.list(&b).unwrap().len(), 2);
+        let latest = history.list(&a).unwrap()[0].id;
+        assert_eq!(history.read(&a, latest).unwrap(), "text 3");
+
+        // The retention is applied to existing snapshots on start
+        let retention = Retention {
+            max_count: 1,
+            ..Retention::default()
+        };
+        let history = LocalHistory::new(get_test_dir().join("history"), retention).unwrap();
+        assert_eq!(history.list(&a).unwrap().len(), 1);
+        assert_eq!(history.read(&a, latest).unwrap(), "text 3");
+
+        // Size limit removes the oldest snapshots of all paths
+        let size = history.list(&a).unwrap()[0].size;
+        history
+            .set_retention(Retention {
+                max_size: size * 2,
+                ..Retention::default()
+            })
+            .unwrap();
+        assert_eq!(history.list(&a).unwrap().len(), 1);
+        assert_eq!(history.list(&b).unwrap().len(), 1);
+
+        // New snapshots also apply it
+        snapshot(&history, &a, "text 4");
+        let snapshots = history.list(&a).unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(history.read(&a, snapshots[0].id).unwrap(), "text 4");
+        assert_eq!(history.list(&b).unwrap().len(), 1);
+    }
+}
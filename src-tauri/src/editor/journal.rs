@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+
+use crate::fs::write::write_atomic;
+
+use super::editor_state::{Document, EditorState, UpdateDocument};
+use super::file_format::FileFormat;
+use super::pathutil::stable_hash;
+
+pub const JOURNAL_INTERVAL: Duration = Duration::from_secs(5);
+const RECOVERABLE_DIR: &str = "recoverable";
+
+// Unsaved text of a document at the time of the snapshot
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub path: PathBuf,
+    pub text: String,
+    pub format: FileFormat,
+    pub version: i32,
+    pub journaled_at: SystemTime,
+}
+
+// Keeps snapshots of dirty documents and buffers on disk, so that unsaved
+// changes survive a crash. Entries are removed on save, close and a clean
+// shutdown. Entries of the last run are moved to `recoverable/` and kept until
+// they are restored or discarded.
+pub struct Journal {
+    dir: PathBuf,
+    // Snapshotted version of each document with an entry from this run
+    written: Mutex<HashMap<PathBuf, i32>>,
+    recoverable: Mutex<Vec<JournalEntry>>,
+}
+
+impl Journal {
+    pub fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        let recoverable_dir = dir.join(RECOVERABLE_DIR);
+        fs::create_dir_all(&recoverable_dir)?;
+
+        // Older entries of the same document from a run before are replaced
+        for (path, entry) in read_entries(&dir)? {
+            let target = recoverable_dir.join(entry_file_name(&entry.path));
+            let newer = fs::read(&target)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<JournalEntry>(&bytes).ok())
+                .is_some_and(|existing| existing.journaled_at > entry.journaled_at);
+            if newer {
+                fs::remove_file(&path)?;
+            } else {
+                fs::rename(&path, &target)?;
+            }
+        }
+
+        let mut recoverable: Vec<JournalEntry> = read_entries(&recoverable_dir)?
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect();
+        recoverable.sort_by_key(|e| std::cmp::Reverse(e.journaled_at));
+        info!("Found {} recoverable documents", recoverable.len());
+
+        Ok(Self {
+            dir,
+            written: Mutex::new(HashMap::new()),
+            recoverable: Mutex::new(recoverable),
+        })
+    }
+
+    pub fn list_recoverable_documents(&self) -> Vec<JournalEntry> {
+        self.recoverable.lock().unwrap().clone()
+    }
+
+    // Replaces the text of the document with the recovered one. The document
+    // becomes dirty and is journaled again until it is saved.
+    pub async fn restore(
+        &self,
+        editor_state: &EditorState,
+        path: &Path,
+    ) -> anyhow::Result<Document> {
+        let entry = self
+            .recoverable
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| e.path == path)
+            .cloned()
+            .ok_or(anyhow!("No recoverable document (path={:?})", path))?;
+
+        let doc = editor_state.get_document(path).await?;
+        let update = UpdateDocument {
+            text: entry.text,
+            language: doc.language,
+        };
+        editor_state.replace_text(path, &update)?;
+        let doc = editor_state.set_file_format(path, entry.format)?;
+
+        self.discard(path)?;
+        Ok(doc)
+    }
+
+    pub fn discard(&self, path: &Path) -> anyhow::Result<()> {
+        debug!("Discard recoverable document (path={:?})", path);
+        self.recoverable.lock().unwrap().retain(|e| e.path != path);
+        let entry_path = self.dir.join(RECOVERABLE_DIR).join(entry_file_name(path));
+        if entry_path.exists() {
+            fs::remove_file(entry_path)?;
+        }
+        Ok(())
+    }
+
+    // Moves the entry of a renamed document, also a recoverable one
+    pub fn rename(&self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        let version = self.written.lock().unwrap().remove(from);
+        if let Some(version) = version {
+            debug!("Move journal entry (from={:?}, to={:?})", from, to);
+            move_entry(&self.dir, from, to)?;
+            self.written
+                .lock()
+                .unwrap()
+                .insert(to.to_path_buf(), version);
+        }
+
+        let mut recoverable = self.recoverable.lock().unwrap();
+        if let Some(entry) = recoverable.iter_mut().find(|e| e.path == from) {
+            move_entry(&self.dir.join(RECOVERABLE_DIR), from, to)?;
+            entry.path = to.to_path_buf();
+        }
+        Ok(())
+    }
+
+    // Removes the entry of this run, e.g. after the document was saved
+    pub fn remove(&self, path: &Path) -> anyhow::Result<()> {
+        if self.written.lock().unwrap().remove(path).is_some() {
+            debug!("Remove journal entry (path={:?})", path);
+            let entry_path = self.dir.join(entry_file_name(path));
+            if entry_path.exists() {
+                fs::remove_file(entry_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Removes all entries of this run on a clean shutdown
+    pub fn clear(&self) -> anyhow::Result<()> {
+        let paths: Vec<PathBuf> = self.written.lock().unwrap().keys().cloned().collect();
+        for path in paths {
+            self.remove(&path)?;
+        }
+        Ok(())
+    }
+
+    // Writes entries of documents that changed since the last snapshot and
+    // removes entries of this run whose documents are saved or closed
+    pub fn snapshot(&self, editor_state: &EditorState) -> anyhow::Result<()> {
+        let mut changed = Vec::new();
+        let mut saved = Vec::new();
+
+        {
+            let written = self.written.lock().unwrap();
+            let documents = editor_state.documents.read().unwrap();
+            for doc in documents.values() {
+                let snapshot = written.get(&doc.path);
+                if doc.is_dirty && !doc.large_file {
+                    if snapshot != Some(&doc.version) {
+                        // Cloning a rope is cheap, the text is copied outside of the lock
+                        changed.push((doc.path.clone(), doc.text.clone(), doc.format, doc.version));
+                    }
+                } else if snapshot.is_some() {
+                    saved.push(doc.path.clone());
+                }
+            }
+            saved.extend(
+                written
+                    .keys()
+                    .filter(|path| !documents.contains_key(*path))
+                    .cloned(),
+            );
+        }
+
+        for (path, text, format, version) in changed {
+            debug!("Journal document (path={:?}, version={})", path, version);
+            let entry = JournalEntry {
+                path: path.clone(),
+                text: text.to_string(),
+                format,
+                version,
+                journaled_at: SystemTime::now(),
+            };
+
+            write_atomic(&self.dir.join(entry_file_name(&path)), |w| {
+                serde_json::to_writer(w, &entry).map_err(std::io::Error::other)
+            })?;
+            self.written.lock().unwrap().insert(path, version);
+        }
+
+        for path in saved {
+            self.remove(&path)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn entry_file_name(path: &Path) -> String {
+    format!("{:016x}.json", stable_hash(path))
+}
+
+// Writes the entry of `from` in `dir` under the name and with the path of `to`
+fn move_entry(dir: &Path, from: &Path, to: &Path) -> anyhow::Result<()> {
+    let from_path = dir.join(entry_file_name(from));
+    let mut entry: JournalEntry = serde_json::from_slice(&fs::read(&from_path)?)?;
+    entry.path = to.to_path_buf();
+    write_atomic(&dir.join(entry_file_name(to)), |w| {
+        serde_json::to_writer(w, &entry).map_err(std::io::Error::other)
+    })?;
+    fs::remove_file(from_path)?;
+    Ok(())
+}
+
+// Reads the entries of a dir, invalid ones are removed
+fn read_entries(dir: &Path) -> anyhow::Result<Vec<(PathBuf, JournalEntry)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+
+        match fs::read(&path).map(|bytes| serde_json::from_slice::<JournalEntry>(&bytes)) {
+            Ok(Ok(entry)) => entries.push((path, entry)),
+            _ => {
+                error!("Invalid journal entry (path={:?})", path);
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serial_test::serial;
+
+    use crate::editor::editor_state::{EditorState, Insert, UpdateDocument};
+    use crate::editor::testutil::{create_test_workspace, doc_version, get_test_dir};
+
+    use super::{entry_file_name, Journal, JournalEntry};
+
+    #[tokio::test]
+    #[serial]
+    async fn test_journal() {
+        create_test_workspace(true);
+        let dir = get_test_dir().join("journal");
+        let path = get_test_dir().join("README.md");
+        let buffer = Path::new("buffer://1");
+
+        let editor_state = EditorState::default();
+        let journal = Journal::new(dir.clone()).unwrap();
+        assert!(journal.list_recoverable_documents().is_empty());
+
+        editor_state.get_document(&path).await.unwrap();
+        editor_state.get_document(buffer).await.unwrap();
+        let insert = Insert {
+            from_a: 0,
+            text: "unsaved".to_string(),
+        };
+        editor_state
+            .insert_text(&path, doc_version(&editor_state, &path), &insert)
+            .unwrap();
+        let update = UpdateDocument {
+            text: "scratch".to_string(),
+            language: None,
+        };
+        editor_state.replace_text(buffer, &update).unwrap();
+
+        journal.snapshot(&editor_state).unwrap();
+        let entries = || std::fs::read_dir(&dir).unwrap().count();
+        // Two entries and the dir of recoverable entries
+        assert_eq!(entries(), 3);
+
+        // The next start finds both documents
+        let editor_state = EditorState::default();
+        let journal = Journal::new(dir.clone()).unwrap();
+        let mut recoverable = journal.list_recoverable_documents();
+        recoverable.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(recoverable.len(), 2);
+        assert_eq!(recoverable[0].path, path);
+        assert_eq!(recoverable[0].text, "unsaved");
+        assert_eq!(recoverable[1].path, buffer);
+        assert_eq!(recoverable[1].text, "scratch");
+        assert_eq!(entries(), 1);
+
+        // Opening a clean document keeps its recoverable entry
+        editor_state.get_document(&path).await.unwrap();
+        journal.snapshot(&editor_state).unwrap();
+        assert_eq!(journal.list_recoverable_documents().len(), 2);
+
+        let doc = journal.restore(&editor_state, &path).await.unwrap();
+        assert!(doc.is_dirty);
+        assert_eq!(doc.text.to_string(), "unsaved");
+        journal.discard(buffer).unwrap();
+        assert!(journal.list_recoverable_documents().is_empty());
+        assert_eq!(
+            std::fs::read_dir(dir.join("recoverable")).unwrap().count(),
+            0
+        );
+
+        // Saved documents are removed from the journal
+        journal.snapshot(&editor_state).unwrap();
+        assert_eq!(entries(), 2);
+        editor_state.write_document(&path).unwrap();
+        journal.snapshot(&editor_state).unwrap();
+        assert_eq!(entries(), 1);
+
+        // Entries move with renamed documents
+        editor_state.replace_text(&path, &update).unwrap();
+        journal.snapshot(&editor_state).unwrap();
+        let renamed = get_test_dir().join("renamed.md");
+        editor_state.rename_document(&path, &renamed).unwrap();
+        journal.rename(&path, &renamed).unwrap();
+        journal.snapshot(&editor_state).unwrap();
+        assert_eq!(entries(), 2);
+        let entry = std::fs::read(dir.join(entry_file_name(&renamed))).unwrap();
+        let entry: JournalEntry = serde_json::from_slice(&entry).unwrap();
+        assert_eq!(entry.path, renamed);
+        assert_eq!(entry.text, "scratch");
+
+        // Closed documents are removed
+        editor_state.documents.write().unwrap().remove(&renamed);
+        journal.snapshot(&editor_state).unwrap();
+        assert_eq!(entries(), 1);
+
+        // A clean shutdown leaves nothing to recover
+        editor_state.get_document(buffer).await.unwrap();
+        editor_state.replace_text(buffer, &update).unwrap();
+        journal.snapshot(&editor_state).unwrap();
+        assert_eq!(entries(), 2);
+        journal.clear().unwrap();
+        assert_eq!(entries(), 1);
+        assert!(Journal::new(dir.clone())
+            .unwrap()
+            .list_recoverable_documents()
+            .is_empty());
+    }
+}
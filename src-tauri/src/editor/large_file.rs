@@ -0,0 +1,442 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+
+use super::editor_state::Lines;
+use super::file_format::FileFormat;
+
+// Every INDEX_STEP-th line start is kept, so reading a page skips at most
+// that many lines
+const INDEX_STEP: usize = 1024;
+// Bytes read to detect the format of the file
+const SNIFF_LEN: u64 = 64 * 1024;
+const CHUNK_LEN: usize = 64 * 1024;
+
+// Lines of the original file or lines that replaced some of them
+#[derive(Clone, Debug, PartialEq)]
+enum Piece {
+    Original(Range<usize>),
+    Edited(Vec<String>),
+}
+
+impl Piece {
+    fn len(&self) -> usize {
+        match self {
+            Piece::Original(range) => range.len(),
+            Piece::Edited(lines) => lines.len(),
+        }
+    }
+
+    fn slice(&self, range: Range<usize>) -> Piece {
+        match self {
+            Piece::Original(original) => {
+                Piece::Original(original.start + range.start..original.start + range.end)
+            }
+            Piece::Edited(lines) => Piece::Edited(lines[range].to_vec()),
+        }
+    }
+}
+
+// A file that is too big to be read into memory. Its lines are read from disk
+// on demand with a sparse index of line offsets. Edits are kept as a table of
+// pieces over the lines of the file, until they are written.
+pub struct LargeFile {
+    path: PathBuf,
+    encoding: &'static Encoding,
+    // Byte offsets of the lines 0, INDEX_STEP, 2 * INDEX_STEP, ...
+    offsets: Vec<u64>,
+    // Lines of the document in order, without empty pieces
+    pieces: Vec<Piece>,
+}
+
+impl LargeFile {
+    // Detects the format from the start of the file and indexes its lines
+    pub fn open(
+        path: &Path,
+        encoding: Option<&'static Encoding>,
+    ) -> anyhow::Result<(Self, FileFormat)> {
+        let mut file = File::open(path)?;
+        let mut head = Vec::new();
+        (&mut file).take(SNIFF_LEN).read_to_end(&mut head)?;
+
+        // Don't cut a character in two at the end
+        let end = head
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(head.len(), |i| i + 1);
+        let (mut format, _) = FileFormat::decode(&head[..end], encoding)?;
+        let bom_len = match Encoding::for_bom(&head) {
+            Some((_, len)) if format.bom => len as u64,
+            _ => 0,
+        };
+
+        let newline = newline(format.encoding);
+        file.seek(SeekFrom::Start(bom_len))?;
+        let mut offsets = vec![bom_len];
+        let mut breaks = 0;
+        let final_newline = scan_breaks(&mut file, bom_len, newline, |offset| {
+            breaks += 1;
+            if breaks % INDEX_STEP == 0 {
+                offsets.push(offset);
+            }
+        })?;
+        format.final_newline = final_newline;
+
+        // A final line break does not start another line
+        let total = breaks + usize::from(!final_newline);
+        let large_file = Self {
+            path: path.to_path_buf(),
+            encoding: format.encoding,
+            offsets,
+            pieces: if total > 0 {
+                vec![Piece::Original(0..total)]
+            } else {
+                Vec::new()
+            },
+        };
+        Ok((large_file, format))
+    }
+
+    // The file was moved on disk
+    pub fn rename(&mut self, path: &Path) {
+        self.path = path.to_path_buf();
+    }
+
+    pub fn total(&self) -> usize {
+        self.pieces.iter().map(Piece::len).sum()
+    }
+
+    // Returns a range of lines without line breaks
+    pub fn read_lines(&self, start: usize, count: usize) -> anyhow::Result<Lines> {
+        let total = self.total();
+        let start = start.min(total);
+        let end = start.saturating_add(count).min(total);
+
+        let mut lines = Vec::with_capacity(end - start);
+        self.for_each_line(start..end, |line| {
+            lines.push(line);
+            Ok(())
+        })?;
+
+        Ok(Lines {
+            start,
+            lines,
+            total,
+        })
+    }
+
+    // Returns the lines from `start` on joined by line feeds, up to the line
+    // that contains the UTF-16 offset `len`, and the number of lines
+    pub fn read_text(&self, start: usize, len: usize) -> anyhow::Result<(String, usize)> {
+        let mut text = String::new();
+        let mut count = 0;
+        let mut text_len = 0;
+        while text_len < len || count == 0 {
+            let lines = self.read_lines(start + count, INDEX_STEP)?;
+            if lines.lines.is_empty() {
+                break;
+            }
+
+            for line in lines.lines {
+                if count > 0 {
+                    text.push('\n');
+                    text_len += 1;
+                }
+                text_len += line.encode_utf16().count();
+                text.push_str(&line);
+                count += 1;
+                if text_len >= len {
+                    break;
+                }
+            }
+        }
+        Ok((text, count))
+    }
+
+    // Replaces the lines in `range` with `lines`
+    pub fn replace_lines(&mut self, range: Range<usize>, lines: Vec<String>) {
+        let mut inserted = Some(Piece::Edited(lines));
+        let mut pieces = Vec::with_capacity(self.pieces.len() + 2);
+        let mut end = 0;
+        for piece in mem::take(&mut self.pieces) {
+            let (start, len) = (end, piece.len());
+            end += len;
+
+            // Lines of the piece before and after the range
+            let before = range.start.clamp(start, end) - start;
+            let after = range.end.clamp(start, end) - start;
+            if before > 0 {
+                pieces.push(piece.slice(0..before));
+            }
+            if range.start <= end {
+                pieces.extend(inserted.take());
+            }
+            if after < len {
+                pieces.push(piece.slice(after..len));
+            }
+        }
+        pieces.extend(inserted);
+
+        // Merge the edited lines, so that the table stays small
+        for piece in pieces {
+            match (self.pieces.last_mut(), piece) {
+                (_, piece) if piece.len() == 0 => {}
+                (Some(Piece::Edited(last)), Piece::Edited(lines)) => last.extend(lines),
+                (_, piece) => self.pieces.push(piece),
+            }
+        }
+    }
+
+    // Calls `f` with chunks of the whole text, so that only a part of it is
+    // in memory at a time. Lines are joined by line feeds.
+    pub fn for_each_chunk(&self, f: &mut dyn FnMut(&str) -> io::Result<()>) -> io::Result<()> {
+        let total = self.total();
+        let mut start = 0;
+        while start < total {
+            let end = (start + INDEX_STEP).min(total);
+            let mut chunk = String::new();
+            let mut first = start == 0;
+            self.for_each_line(start..end, |line| {
+                if !first {
+                    chunk.push('\n');
+                }
+                first = false;
+                chunk.push_str(&line);
+                Ok(())
+            })?;
+            f(&chunk)?;
+            start = end;
+        }
+        Ok(())
+    }
+
+    fn for_each_line<F>(&self, range: Range<usize>, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(String) -> io::Result<()>,
+    {
+        let mut end = 0;
+        for piece in &self.pieces {
+            let start = end;
+            end += piece.len();
+            if end <= range.start || start >= range.end {
+                continue;
+            }
+
+            let from = range.start.max(start) - start;
+            let to = range.end.min(end) - start;
+            match piece {
+                Piece::Original(original) => {
+                    self.read_original(original.start + from..original.start + to, &mut f)?
+                }
+                Piece::Edited(lines) => {
+                    for line in &lines[from..to] {
+                        f(line.clone())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Reads lines of the file on disk
+    fn read_original<F>(&self, range: Range<usize>, f: &mut F) -> io::Result<()>
+    where
+        F: FnMut(String) -> io::Result<()>,
+    {
+        let checkpoint = (range.start / INDEX_STEP).min(self.offsets.len() - 1);
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(self.offsets[checkpoint]))?;
+        let newline = newline(self.encoding);
+        let mut buf = Vec::new();
+        for _ in checkpoint * INDEX_STEP..range.start {
+            buf.clear();
+            read_line(&mut reader, newline, &mut buf)?;
+        }
+
+        for _ in range {
+            buf.clear();
+            read_line(&mut reader, newline, &mut buf)?;
+            if buf.ends_with(newline) {
+                buf.truncate(buf.len() - newline.len());
+            }
+            let (line, _) = self.encoding.decode_without_bom_handling(&buf);
+            f(line.trim_end_matches('\r').to_string())?;
+        }
+        Ok(())
+    }
+}
+
+// UTF-16 line breaks are two bytes at an even offset
+fn newline(encoding: &'static Encoding) -> &'static [u8] {
+    if encoding == UTF_16LE {
+        b"\n\0"
+    } else if encoding == UTF_16BE {
+        b"\0\n"
+    } else {
+        b"\n"
+    }
+}
+
+// Calls `f` with the offset after each line break and returns whether the
+// contents end with one. Only one chunk is in memory at a time.
+fn scan_breaks<R: Read, F: FnMut(u64)>(
+    reader: &mut R,
+    start: u64,
+    newline: &[u8],
+    mut f: F,
+) -> io::Result<bool> {
+    let unit = newline.len();
+    let mut chunk = vec![0; CHUNK_LEN];
+    let mut offset = start;
+    let mut carried = 0;
+    let mut ends_with_break = false;
+
+    loop {
+        let n = reader.read(&mut chunk[carried..])?;
+        if n == 0 {
+            return Ok(ends_with_break);
+        }
+
+        // A unit that is split across reads is completed by the next one
+        let len = carried + n;
+        let usable = len - len % unit;
+        for i in (0..usable).step_by(unit) {
+            if chunk[i..i + unit] == *newline {
+                f(offset + (i + unit) as u64);
+            }
+        }
+        if usable > 0 {
+            ends_with_break = chunk[usable - unit..usable] == *newline;
+        }
+
+        offset += usable as u64;
+        chunk.copy_within(usable..len, 0);
+        carried = len - usable;
+    }
+}
+
+// Appends the next line with its line break to `buf`
+fn read_line<R: BufRead>(reader: &mut R, newline: &[u8], buf: &mut Vec<u8>) -> io::Result<()> {
+    if let [byte] = newline {
+        reader.read_until(*byte, buf)?;
+        return Ok(());
+    }
+
+    let mut unit = [0; 2];
+    loop {
+        match reader.read_exact(&mut unit) {
+            Ok(()) => {
+                buf.extend_from_slice(&unit);
+                if unit == newline {
+                    return Ok(());
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::UTF_16LE;
+
+    use crate::editor::file_format::LineEnding;
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+
+    use super::{LargeFile, INDEX_STEP};
+
+    #[test]
+    fn test_read_lines() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("large.txt");
+        let count = INDEX_STEP * 2 + 10;
+        let contents: String = (0..count).map(|i| format!("line {}\r\n", i)).collect();
+        std::fs::write(&path, &contents).unwrap();
+
+        let (file, format) = LargeFile::open(&path, None).unwrap();
+        assert_eq!(format.line_ending, LineEnding::Crlf);
+        assert!(format.final_newline);
+
+        let lines = file.read_lines(INDEX_STEP - 1, 2).unwrap();
+        assert_eq!(lines.total, count);
+        assert_eq!(
+            lines.lines,
+            vec![
+                format!("line {}", INDEX_STEP - 1),
+                format!("line {}", INDEX_STEP)
+            ]
+        );
+
+        let lines = file.read_lines(count - 1, 10).unwrap();
+        assert_eq!(lines.lines, vec![format!("line {}", count - 1)]);
+        let lines = file.read_lines(count + 1, 10).unwrap();
+        assert_eq!(lines.start, count);
+        assert!(lines.lines.is_empty());
+
+        // Without a final line break the last line still counts
+        std::fs::write(&path, "a\nb").unwrap();
+        let (file, format) = LargeFile::open(&path, None).unwrap();
+        assert!(!format.final_newline);
+        assert_eq!(file.read_lines(0, 10).unwrap().lines, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_replace_lines() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("large.txt");
+        let count = INDEX_STEP * 2;
+        let contents: String = (0..count).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(&path, &contents).unwrap();
+
+        let (mut file, _) = LargeFile::open(&path, None).unwrap();
+        file.replace_lines(1..3, vec!["a".to_string()]);
+        file.replace_lines(2..2, vec!["b".to_string(), "c".to_string()]);
+        file.replace_lines(file.total()..file.total(), vec!["end".to_string()]);
+        assert_eq!(file.total(), count + 2);
+        assert_eq!(
+            file.read_lines(0, 6).unwrap().lines,
+            vec!["line 0", "a", "b", "c", "line 3", "line 4"]
+        );
+        // Adjacent edited lines are merged into one piece
+        assert_eq!(file.pieces.len(), 4);
+
+        let (text, lines) = file.read_text(1, 3).unwrap();
+        assert_eq!((text.as_str(), lines), ("a\nb", 2));
+
+        let mut written = String::new();
+        file.for_each_chunk(&mut |chunk| {
+            written.push_str(chunk);
+            Ok(())
+        })
+        .unwrap();
+        let expected = contents
+            .replacen("line 1\nline 2\n", "a\nb\nc\n", 1)
+            .trim_end()
+            .to_string()
+            + "\nend";
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn test_utf16() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("large.txt");
+        // U+0A0A has the bytes of a line break in UTF-16
+        let bytes: Vec<u8> = "\u{feff}ä\n\u{0a0a}\nc"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        std::fs::write(&path, bytes).unwrap();
+
+        let (file, format) = LargeFile::open(&path, None).unwrap();
+        assert_eq!(format.encoding, UTF_16LE);
+        assert!(format.bom);
+        let lines = file.read_lines(0, 10).unwrap();
+        assert_eq!(lines.lines, vec!["ä", "\u{0a0a}", "c"]);
+    }
+}
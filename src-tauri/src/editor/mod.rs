@@ -1,6 +1,17 @@
 pub mod command_args;
 pub mod command_editor_state;
+pub mod command_history;
+pub mod command_project_settings;
 pub mod editor_state;
+pub mod editorconfig;
+pub mod file_format;
+pub mod history;
+pub mod journal;
+pub mod large_file;
 pub mod pathutil;
+pub mod project_settings;
+pub mod search;
 #[cfg(test)]
 pub mod testutil;
+pub mod undo;
+pub mod watcher;
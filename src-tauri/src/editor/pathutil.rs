@@ -109,6 +109,17 @@ pub fn home_dir() -> Option<PathBuf> {
     }
 }
 
+// FNV-1a of the path. Unlike `DefaultHasher` it is the same across Rust
+// releases, so it can name files that have to survive app updates.
+pub fn stable_hash(path: &Path) -> u64 {
+    path.as_os_str()
+        .as_encoded_bytes()
+        .iter()
+        .fold(0xcbf29ce484222325, |hash, b| {
+            (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -162,6 +173,12 @@ mod tests {
         std::fs::remove_file(test_file_path).unwrap();
     }
 
+    #[test]
+    fn test_stable_hash() {
+        assert_eq!(stable_hash(Path::new("")), 0xcbf29ce484222325);
+        assert_eq!(stable_hash(Path::new("a")), 0xaf63dc4c8601ec8c);
+    }
+
     #[test]
     fn test_dirname() {
         assert_eq!(
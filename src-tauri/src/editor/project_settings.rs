@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::anyhow;
+use ignore::overrides::{Override, OverrideBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use super::editor_state::Document;
+
+pub const PROJECT_SETTINGS_FILE: &str = ".tinywrite.toml";
+pub const PROJECT_SETTINGS_CHANGED: &str = "project-settings-changed";
+
+// Used by language servers if the project does not set a tab width
+pub const DEFAULT_TAB_WIDTH: u32 = 4;
+
+// Settings that can be overridden for each language in `[languages.<id>]`
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct EditorSettings {
+    pub tab_width: Option<u32>,
+    pub use_tabs: Option<bool>,
+    pub format_on_save: Option<bool>,
+    pub copilot: Option<bool>,
+}
+
+impl EditorSettings {
+    fn merge(&self, other: &EditorSettings) -> EditorSettings {
+        EditorSettings {
+            tab_width: other.tab_width.or(self.tab_width),
+            use_tabs: other.use_tabs.or(self.use_tabs),
+            format_on_save: other.format_on_save.or(self.format_on_save),
+            copilot: other.copilot.or(self.copilot),
+        }
+    }
+}
+
+// Initialization options are merged over the ones of the app settings. The
+// command is only used if the worktree is trusted in the app settings, so that
+// opening a repo never runs anything the repo chose.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, rename_all(serialize = "camelCase"))]
+pub struct ProjectLanguageServerSettings {
+    pub enabled: Option<bool>,
+    // Shell command that replaces the built-in one
+    pub command: Option<String>,
+    pub initialization_options: Option<serde_json::Value>,
+}
+
+// The contents of `.tinywrite.toml`, e.g.
+//
+// tab_width = 2
+// exclude = ["dist/**"]
+//
+// [languages.rust]
+// tab_width = 4
+//
+// [language_servers.typescript]
+// initialization_options = { preferences = { quoteStyle = "single" } }
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ProjectSettingsFile {
+    #[serde(flatten)]
+    pub editor: EditorSettings,
+    // Globs relative to the worktree, e.g. `dist/**`
+    pub exclude: Vec<String>,
+    pub languages: HashMap<String, EditorSettings>,
+    pub language_servers: HashMap<String, ProjectLanguageServerSettings>,
+}
+
+impl ProjectSettingsFile {
+    // A missing file is the same as an empty one
+    pub fn load(worktree: &Path) -> anyhow::Result<Self> {
+        let path = worktree.join(PROJECT_SETTINGS_FILE);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        toml::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid project settings (path={:?}): {}", path, e))
+    }
+
+    // Matches paths below `root` against the exclude globs
+    pub fn excludes(&self, root: &Path) -> anyhow::Result<Override> {
+        let mut overrides = OverrideBuilder::new(root);
+        for glob in &self.exclude {
+            overrides.add(&format!("!{}", glob))?;
+        }
+        Ok(overrides.build()?)
+    }
+}
+
+// Effective settings of a document. Unset values are left to the frontend.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectSettings {
+    pub worktree_path: Option<PathBuf>,
+    pub tab_width: Option<u32>,
+    pub use_tabs: Option<bool>,
+    pub format_on_save: bool,
+    pub copilot: bool,
+    pub exclude: Vec<String>,
+    pub language_server: ProjectLanguageServerSettings,
+}
+
+// Caches the settings file of each worktree. Invalid files are logged and
+// treated as empty until they are fixed.
+pub struct ProjectSettingsStore {
+    settings: RwLock<HashMap<PathBuf, Arc<ProjectSettingsFile>>>,
+}
+
+impl ProjectSettingsStore {
+    pub fn new() -> Self {
+        Self {
+            settings: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // Loads the settings of a worktree once, returns true if they were loaded
+    pub fn open(&self, worktree: &Path) -> bool {
+        if self.settings.read().unwrap().contains_key(worktree) {
+            return false;
+        }
+
+        self.reload(worktree);
+        true
+    }
+
+    pub fn reload(&self, worktree: &Path) -> Arc<ProjectSettingsFile> {
+        debug!("Load project settings (worktree={:?})", worktree);
+        let settings = ProjectSettingsFile::load(worktree).unwrap_or_else(|e| {
+            error!("{:?}", e);
+            ProjectSettingsFile::default()
+        });
+
+        let settings = Arc::new(settings);
+        self.settings
+            .write()
+            .unwrap()
+            .insert(worktree.to_path_buf(), settings.clone());
+        settings
+    }
+
+    pub fn get(&self, worktree: &Path) -> Arc<ProjectSettingsFile> {
+        let settings = self.settings.read().unwrap().get(worktree).cloned();
+        settings.unwrap_or_else(|| self.reload(worktree))
+    }
+
+    // Merges the project settings with the overrides of the document language.
+    // Unset tab settings are taken from the EditorConfig of the document.
+    pub fn resolve(&self, doc: &Document) -> ProjectSettings {
+        let Some(worktree) = doc.worktree_path.as_ref() else {
+            return ProjectSettings {
+                tab_width: doc.editor_config.indent_width(),
+                use_tabs: doc.editor_config.use_tabs(),
+                copilot: true,
+                ..Default::default()
+            };
+        };
+
+        let file = self.get(worktree);
+        let language = doc.language.as_ref().map(|l| l.0.as_str());
+        let editor = match language.and_then(|l| file.languages.get(l)) {
+            Some(overrides) => file.editor.merge(overrides),
+            None => file.editor.clone(),
+        };
+        let language_server = language
+            .and_then(|l| file.language_servers.get(l))
+            .cloned()
+            .unwrap_or_default();
+
+        ProjectSettings {
+            worktree_path: Some(worktree.clone()),
+            tab_width: editor.tab_width.or(doc.editor_config.indent_width()),
+            use_tabs: editor.use_tabs.or(doc.editor_config.use_tabs()),
+            format_on_save: editor.format_on_save.unwrap_or(false),
+            copilot: editor.copilot.unwrap_or(true),
+            exclude: file.exclude.clone(),
+            language_server,
+        }
+    }
+}
+
+pub fn is_project_settings_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name == PROJECT_SETTINGS_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use crate::editor::editor_state::EditorState;
+    use crate::editor::editorconfig::EDITORCONFIG_FILE;
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+
+    use super::{ProjectSettingsFile, ProjectSettingsStore, PROJECT_SETTINGS_FILE};
+
+    #[tokio::test]
+    #[serial]
+    async fn test_project_settings() {
+        create_test_workspace(true);
+        let dir = get_test_dir();
+        let settings = [
+            "tab_width = 2",
+            "format_on_save = true",
+            "exclude = [\"dist/**\"]",
+            "[languages.rust]",
+            "tab_width = 4",
+            "copilot = false",
+            "[language_servers.rust]",
+            "enabled = false",
+            "command = \"ra\"",
+            "initialization_options = { check = { command = \"clippy\" } }",
+        ]
+        .join("\n");
+        std::fs::write(dir.join(PROJECT_SETTINGS_FILE), settings).unwrap();
+
+        // Used for tab settings that the project does not set
+        let editorconfig = "[*]\nindent_style = tab\nindent_size = 8";
+        std::fs::write(dir.join(EDITORCONFIG_FILE), editorconfig).unwrap();
+
+        let editor_state = EditorState::default();
+        let store = ProjectSettingsStore::new();
+        assert!(store.open(&dir));
+        assert!(!store.open(&dir));
+
+        let doc = editor_state
+            .get_document(&dir.join("README.md"))
+            .await
+            .unwrap();
+        let settings = store.resolve(&doc);
+        assert_eq!(settings.worktree_path, Some(dir.clone()));
+        assert_eq!(settings.tab_width, Some(2));
+        assert_eq!(settings.use_tabs, Some(true));
+        assert!(settings.format_on_save);
+        assert!(settings.copilot);
+        assert_eq!(settings.language_server.enabled, None);
+
+        let main = dir.join("src").join("main.rs");
+        let doc = editor_state.get_document(&main).await.unwrap();
+        let settings = store.resolve(&doc);
+        assert_eq!(settings.tab_width, Some(4));
+        assert!(settings.format_on_save);
+        assert!(!settings.copilot);
+        assert_eq!(settings.language_server.enabled, Some(false));
+        // Only used in trusted worktrees, see `resolve_language_server`
+        assert_eq!(settings.language_server.command.as_deref(), Some("ra"));
+        let options = settings.language_server.initialization_options.unwrap();
+        assert_eq!(options["check"]["command"], "clippy");
+
+        let excludes = store.get(&dir).excludes(&dir).unwrap();
+        assert!(excludes
+            .matched(dir.join("dist/index.js"), false)
+            .is_ignore());
+        assert!(!excludes.matched(&main, false).is_ignore());
+
+        // Invalid files are ignored until they are fixed
+        std::fs::write(dir.join(PROJECT_SETTINGS_FILE), "tab_width = \"2\"").unwrap();
+        assert!(ProjectSettingsFile::load(&dir).is_err());
+        assert_eq!(store.reload(&dir).editor.tab_width, None);
+
+        std::fs::remove_file(dir.join(PROJECT_SETTINGS_FILE)).unwrap();
+        assert_eq!(*store.reload(&dir), ProjectSettingsFile::default());
+    }
+}
@@ -0,0 +1,282 @@
+use regex::{Regex, RegexBuilder};
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+
+use super::editor_state::Change;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SearchOptions {
+    pub ignore_case: bool,
+    pub whole_word: bool,
+    // Treat the query as regular expression instead of literal text
+    pub regex: bool,
+}
+
+// UTF-16 range of a match
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchMatch {
+    pub from: usize,
+    pub to: usize,
+}
+
+pub fn build_regex(query: &str, options: &SearchOptions) -> anyhow::Result<Regex> {
+    let mut pattern = if options.regex {
+        query.to_string()
+    } else {
+        regex::escape(query)
+    };
+
+    if options.whole_word {
+        pattern = format!(r"\b(?:{})\b", pattern);
+    }
+
+    let regex = RegexBuilder::new(&pattern)
+        .case_insensitive(options.ignore_case)
+        .build()?;
+    Ok(regex)
+}
+
+// Finds matches line by line, so only a single line is copied at a time.
+// Matches can't span multiple lines and empty matches are skipped.
+pub fn search(text: &Rope, regex: &Regex) -> Vec<SearchMatch> {
+    let mut matches = Vec::new();
+    for_each_line(text, |line, offset| {
+        for m in regex.find_iter(line) {
+            if !m.is_empty() {
+                matches.push(to_search_match(line, offset, m.start(), m.end()));
+            }
+        }
+    });
+    matches
+}
+
+// Matches of one line with the line as preview
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineMatch {
+    pub line: usize,
+    pub preview: String,
+    // UTF-16 ranges within the line
+    pub ranges: Vec<SearchMatch>,
+}
+
+pub fn search_lines(text: &Rope, regex: &Regex) -> Vec<LineMatch> {
+    let mut lines = Vec::new();
+    let mut index = 0;
+    for_each_line(text, |line, _| {
+        let ranges: Vec<SearchMatch> = regex
+            .find_iter(line)
+            .filter(|m| !m.is_empty())
+            .map(|m| to_search_match(line, 0, m.start(), m.end()))
+            .collect();
+
+        if !ranges.is_empty() {
+            lines.push(LineMatch {
+                line: index,
+                preview: line.to_string(),
+                ranges,
+            });
+        }
+        index += 1;
+    });
+    lines
+}
+
+// Applies changes from `replace` to a rope that is not open in the editor
+pub fn apply_replacements(text: &mut Rope, changes: &[Change]) {
+    for change in changes.iter().rev() {
+        let from = text.utf16_cu_to_char(change.from);
+        let to = text.utf16_cu_to_char(change.to);
+        text.remove(from..to);
+        text.insert(from, &change.text);
+    }
+}
+
+// Returns one change for each match. Regex replacements can refer to capture
+// groups with `$1` or `$name`.
+pub fn replace(text: &Rope, regex: &Regex, replacement: &str, expand: bool) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for_each_line(text, |line, offset| {
+        for caps in regex.captures_iter(line) {
+            let m = caps.get(0).unwrap();
+            if m.is_empty() {
+                continue;
+            }
+
+            let mut text = String::new();
+            if expand {
+                caps.expand(replacement, &mut text);
+            } else {
+                text.push_str(replacement);
+            }
+
+            let range = to_search_match(line, offset, m.start(), m.end());
+            changes.push(Change {
+                from: range.from,
+                to: range.to,
+                text,
+            });
+        }
+    });
+    changes
+}
+
+// Calls `f` with each line without line break and its UTF-16 offset. Only
+// `\n` and `\r\n` break lines like in the frontend, ropey's `lines()` also
+// breaks on `\r` and Unicode line separators.
+fn for_each_line<F: FnMut(&str, usize)>(text: &Rope, mut f: F) {
+    let mut offset = 0;
+    let mut line = String::new();
+    let mut emit = |line: &mut String| {
+        f(line.strip_suffix('\r').unwrap_or(line), offset);
+        offset += line.encode_utf16().count() + 1;
+        line.clear();
+    };
+
+    for chunk in text.chunks() {
+        let mut rest = chunk;
+        while let Some(i) = rest.find('\n') {
+            line.push_str(&rest[..i]);
+            emit(&mut line);
+            rest = &rest[i + 1..];
+        }
+        line.push_str(rest);
+    }
+    emit(&mut line);
+}
+
+fn to_search_match(line: &str, offset: usize, start: usize, end: usize) -> SearchMatch {
+    let from = offset + line[..start].encode_utf16().count();
+    let to = from + line[start..end].encode_utf16().count();
+    SearchMatch { from, to }
+}
+
+#[cfg(test)]
+mod tests {
+    use ropey::Rope;
+
+    use super::{
+        apply_replacements, build_regex, replace, search, search_lines, SearchMatch, SearchOptions,
+    };
+
+    fn find(text: &str, query: &str, options: SearchOptions) -> Vec<(usize, usize)> {
+        let regex = build_regex(query, &options).unwrap();
+        search(&Rope::from_str(text), &regex)
+            .into_iter()
+            .map(|SearchMatch { from, to }| (from, to))
+            .collect()
+    }
+
+    #[test]
+    fn test_search() {
+        let text = "Foo foo.bar\n😀 food foo";
+
+        assert_eq!(
+            find(text, "foo", SearchOptions::default()),
+            vec![(4, 7), (15, 18), (20, 23)]
+        );
+        assert_eq!(find(text, "foo.", SearchOptions::default()), vec![(4, 8)]);
+        assert_eq!(
+            find(
+                text,
+                "foo",
+                SearchOptions {
+                    ignore_case: true,
+                    ..Default::default()
+                }
+            ),
+            vec![(0, 3), (4, 7), (15, 18), (20, 23)]
+        );
+        assert_eq!(
+            find(
+                text,
+                "foo",
+                SearchOptions {
+                    whole_word: true,
+                    ..Default::default()
+                }
+            ),
+            vec![(4, 7), (20, 23)]
+        );
+        assert_eq!(
+            find(
+                text,
+                r"^\w+",
+                SearchOptions {
+                    regex: true,
+                    ..Default::default()
+                }
+            ),
+            vec![(0, 3)]
+        );
+        assert_eq!(
+            find(
+                text,
+                r"\w+$",
+                SearchOptions {
+                    regex: true,
+                    ..Default::default()
+                }
+            ),
+            vec![(8, 11), (20, 23)]
+        );
+
+        let options = SearchOptions {
+            regex: true,
+            ..Default::default()
+        };
+        assert!(build_regex("(", &options).is_err());
+    }
+
+    #[test]
+    fn test_replace() {
+        let text = Rope::from_str("a=1\nb=2");
+        let options = SearchOptions {
+            regex: true,
+            ..Default::default()
+        };
+        let regex = build_regex(r"(\w)=(\d)", &options).unwrap();
+        let changes = replace(&text, &regex, "$2=$1", true);
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[1].from, changes[1].to), (4, 7));
+        assert_eq!(changes[1].text, "2=b");
+
+        let changes = replace(&text, &regex, "$2", false);
+        assert_eq!(changes[0].text, "$2");
+
+        let mut text = Rope::from_str("a=1\nb=2");
+        let changes = replace(&text, &regex, "$1 = $2", true);
+        apply_replacements(&mut text, &changes);
+        assert_eq!(text.to_string(), "a = 1\nb = 2");
+    }
+
+    #[test]
+    fn test_search_lines() {
+        let text = Rope::from_str("foo\n😀 foo foo\nbar");
+        let regex = build_regex("foo", &SearchOptions::default()).unwrap();
+        let lines = search_lines(&text, &regex);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].line, 1);
+        assert_eq!(lines[1].preview, "😀 foo foo");
+        assert_eq!(
+            lines[1].ranges,
+            vec![
+                SearchMatch { from: 3, to: 6 },
+                SearchMatch { from: 7, to: 10 }
+            ]
+        );
+
+        // Only line feeds start a new line
+        let text = Rope::from_str("a\rb\u{2028}c\u{85}d\r\nfoo\nx foo");
+        let lines = search_lines(&text, &regex);
+        let numbers: Vec<usize> = lines.iter().map(|l| l.line).collect();
+        assert_eq!(numbers, vec![1, 2]);
+        assert_eq!(lines[0].preview, "foo");
+        assert_eq!(
+            find("a\u{2028}b\r\nfoo", "foo", SearchOptions::default()),
+            vec![(5, 8)]
+        );
+    }
+}
@@ -1,5 +1,9 @@
-use std::{fs::File, path::PathBuf};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
 
+use crate::editor::editor_state::EditorState;
 use crate::editor::pathutil::{home_dir, path_buf_to_string};
 
 pub fn create_test_workspace(worktree_root: bool) {
@@ -32,3 +36,8 @@ pub fn get_home_as_string() -> String {
 pub fn get_test_dir() -> PathBuf {
     get_home().join("tinywrite")
 }
+
+// Version of an open document, for edits that must not conflict
+pub fn doc_version(editor_state: &EditorState, path: &Path) -> i32 {
+    editor_state.documents.read().unwrap()[path].version
+}
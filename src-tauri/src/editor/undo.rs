@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use super::editor_state::Change;
+
+// Edits within this interval are undone together
+pub const UNDO_GROUP_INTERVAL: Duration = Duration::from_millis(1000);
+const MAX_UNDO_GROUPS: usize = 1000;
+
+// Inverse changes of the transactions of one group, in the order they were
+// applied. Undoing a group applies them in reverse.
+#[derive(Clone, Debug)]
+struct UndoGroup {
+    steps: Vec<Vec<Change>>,
+    time: Instant,
+    // Groups restored by redo don't take new edits
+    closed: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct UndoHistory {
+    undo: Vec<UndoGroup>,
+    redo: Vec<UndoGroup>,
+}
+
+impl UndoHistory {
+    // Records the inverse of a new edit and drops the redo stack
+    pub fn record(&mut self, inverse: Vec<Change>) {
+        self.redo.clear();
+
+        let now = Instant::now();
+        match self.undo.last_mut() {
+            Some(group)
+                if !group.closed && now.duration_since(group.time) < UNDO_GROUP_INTERVAL =>
+            {
+                group.steps.push(inverse);
+                group.time = now;
+            }
+            _ => {
+                self.undo.push(UndoGroup {
+                    steps: vec![inverse],
+                    time: now,
+                    closed: false,
+                });
+                if self.undo.len() > MAX_UNDO_GROUPS {
+                    self.undo.remove(0);
+                }
+            }
+        }
+    }
+
+    // Returns the steps of the last group in the order they have to be applied
+    pub fn pop_undo(&mut self) -> Option<Vec<Vec<Change>>> {
+        let group = self.undo.pop()?;
+        // Edits after an undo start a new group
+        if let Some(last) = self.undo.last_mut() {
+            last.closed = true;
+        }
+        Some(group.steps.into_iter().rev().collect())
+    }
+
+    pub fn pop_redo(&mut self) -> Option<Vec<Vec<Change>>> {
+        self.redo.pop().map(|g| g.steps.into_iter().rev().collect())
+    }
+
+    // The inverses of applied undo steps, in the order they were applied
+    pub fn push_redo(&mut self, steps: Vec<Vec<Change>>) {
+        self.redo.push(UndoGroup {
+            steps,
+            time: Instant::now(),
+            closed: true,
+        });
+    }
+
+    // The inverses of applied redo steps
+    pub fn push_undo(&mut self, steps: Vec<Vec<Change>>) {
+        self.undo.push(UndoGroup {
+            steps,
+            time: Instant::now(),
+            closed: true,
+        });
+    }
+}
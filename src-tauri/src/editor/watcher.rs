@@ -0,0 +1,472 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use async_channel::{unbounded, Receiver};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use tracing::{debug, error};
+
+use super::editor_state::{is_buffer, Document, EditorState, ExternalChange};
+use super::journal::Journal;
+use super::project_settings::{
+    is_project_settings_file, ProjectSettingsStore, PROJECT_SETTINGS_CHANGED,
+};
+
+pub const DOCUMENT_CONFLICT: &str = "document-conflict";
+pub const DOCUMENT_DELETED: &str = "document-deleted";
+pub const DOCUMENT_RENAMED: &str = "document-renamed";
+// How long the first half of a rename waits for the second one
+const RENAME_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WatchEvent {
+    // Created, modified or removed
+    Changed(PathBuf),
+    Renamed(PathBuf, PathBuf),
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenamedDocument {
+    pub from: PathBuf,
+    pub document: Document,
+}
+
+pub struct DocumentWatcher {
+    watcher: Mutex<RecommendedWatcher>,
+    // Parent dirs of open documents. Watching the dir instead of the file also
+    // catches saves that replace the file with a renamed temp file.
+    dirs: Mutex<HashSet<PathBuf>>,
+    pub rx: Receiver<WatchEvent>,
+}
+
+impl DocumentWatcher {
+    pub fn new() -> anyhow::Result<Self> {
+        let (tx, rx) = unbounded();
+        let (event_tx, event_rx) = channel();
+        let watcher = notify::recommended_watcher(event_tx)?;
+
+        // Renames are paired on a thread, so that a first half without a
+        // second one is still reported after the timeout. The thread ends
+        // with the watcher.
+        std::thread::spawn(move || {
+            let mut renames = Renames::default();
+            loop {
+                let events = match event_rx.recv_timeout(RENAME_TIMEOUT) {
+                    Ok(Ok(event)) => renames.push(event, Instant::now()),
+                    Ok(Err(e)) => {
+                        error!("Watch error: {:?}", e);
+                        Vec::new()
+                    }
+                    Err(RecvTimeoutError::Timeout) => Vec::new(),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                let expired = renames.expire(Instant::now());
+                for event in events.into_iter().chain(expired) {
+                    let _ = tx.send_blocking(event);
+                }
+            }
+        });
+
+        Ok(Self {
+            watcher: Mutex::new(watcher),
+            dirs: Mutex::new(HashSet::new()),
+            rx,
+        })
+    }
+
+    pub fn watch(&self, path: &Path) -> anyhow::Result<()> {
+        if is_buffer(path) {
+            return Ok(());
+        }
+
+        let dir = path.parent().ok_or(anyhow!("No parent dir"))?;
+        if self.dirs.lock().unwrap().insert(dir.to_path_buf()) {
+            debug!("Watch dir (path={:?})", dir);
+            self.watcher
+                .lock()
+                .unwrap()
+                .watch(dir, RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Pairs the halves of renames that are reported as separate events: From and
+// To with the same tracker on Linux, From and To without a tracker on Windows
+// and two Any events on macOS. Linux also sends Both after From and To.
+#[derive(Default)]
+struct Renames {
+    // First half that waits for the second one
+    pending: Option<(Event, Instant)>,
+    // Tracker of the last paired rename, its Both event is a duplicate
+    paired: Option<usize>,
+}
+
+impl Renames {
+    fn push(&mut self, event: Event, now: Instant) -> Vec<WatchEvent> {
+        let EventKind::Modify(ModifyKind::Name(mode)) = event.kind else {
+            return match event.kind {
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
+                    event.paths.into_iter().map(WatchEvent::Changed).collect()
+                }
+                _ => Vec::new(),
+            };
+        };
+
+        match mode {
+            RenameMode::Both if event.paths.len() == 2 => {
+                if event.tracker().is_some() && event.tracker() == self.paired.take() {
+                    return Vec::new();
+                }
+                let mut paths = event.paths.into_iter();
+                let (from, to) = (paths.next().unwrap(), paths.next().unwrap());
+                vec![WatchEvent::Renamed(from, to)]
+            }
+            RenameMode::From | RenameMode::Any if event.paths.len() == 1 => {
+                if let Some(from) = self.pair(&event) {
+                    let to = event.paths.into_iter().next().unwrap();
+                    return vec![WatchEvent::Renamed(from, to)];
+                }
+                // The second half may follow
+                let events = self.flush();
+                self.pending = Some((event, now));
+                events
+            }
+            RenameMode::To if event.paths.len() == 1 => {
+                if let Some(from) = self.pair(&event) {
+                    let to = event.paths.into_iter().next().unwrap();
+                    return vec![WatchEvent::Renamed(from, to)];
+                }
+                let mut events = self.flush();
+                events.extend(event.paths.into_iter().map(WatchEvent::Changed));
+                events
+            }
+            _ => event.paths.into_iter().map(WatchEvent::Changed).collect(),
+        }
+    }
+
+    // Reports a first half without second one as a change, e.g. a document
+    // that was moved out of the watched dirs
+    fn expire(&mut self, now: Instant) -> Vec<WatchEvent> {
+        match &self.pending {
+            Some((_, time)) if now.duration_since(*time) >= RENAME_TIMEOUT => self.flush(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn flush(&mut self) -> Vec<WatchEvent> {
+        match self.pending.take() {
+            Some((event, _)) => event.paths.into_iter().map(WatchEvent::Changed).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Returns the source path if `event` is the second half of the pending
+    // rename
+    fn pair(&mut self, event: &Event) -> Option<PathBuf> {
+        let (pending, _) = self.pending.as_ref()?;
+        let to = event.paths.first()?;
+        let paired = match (pending.kind, event.kind) {
+            (
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+                EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+            ) => pending.tracker() == event.tracker(),
+            // Nothing tells the halves apart, but the source is gone
+            (
+                EventKind::Modify(ModifyKind::Name(RenameMode::Any)),
+                EventKind::Modify(ModifyKind::Name(RenameMode::Any)),
+            ) => !pending.paths[0].exists() && to.exists(),
+            _ => false,
+        };
+        if !paired {
+            return None;
+        }
+
+        self.paired = event.tracker();
+        let (pending, _) = self.pending.take()?;
+        pending.paths.into_iter().next()
+    }
+}
+
+pub async fn handle_watch_event<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    event: WatchEvent,
+) -> anyhow::Result<()> {
+    let paths = match event {
+        WatchEvent::Renamed(from, to) => match move_document(app_handle, &from, &to).await {
+            Ok(true) => return Ok(()),
+            // A temp file was renamed over an open document
+            Ok(false) => vec![to],
+            // Both paths are open, so the documents are synced with the disk
+            // instead
+            Err(e) => {
+                debug!("Could not move document: {:?}", e);
+                vec![from, to]
+            }
+        },
+        WatchEvent::Changed(path) => vec![path],
+    };
+
+    for path in paths {
+        sync_path(app_handle, &path).await?;
+    }
+    Ok(())
+}
+
+// Moves an open document and its journal entry to a renamed path. The
+// language servers close the old path and open the new one. Returns false if no document is open at
+// `from`.
+pub async fn move_document<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    from: &Path,
+    to: &Path,
+) -> anyhow::Result<bool> {
+    let editor_state = app_handle.state::<EditorState>();
+    let Some((old, document)) = editor_state.rename_document(from, to)? else {
+        return Ok(false);
+    };
+
+    debug!("Document renamed (from={:?}, to={:?})", from, to);
+    if let Some(journal) = app_handle.try_state::<Journal>() {
+        if let Err(e) = journal.rename(from, to) {
+            error!("Could not move journal entry: {:?}", e);
+        }
+    }
+    editor_state.closed_doc_tx.send(old).await?;
+    editor_state.open_doc_tx.send(to.to_path_buf()).await?;
+    app_handle.emit(
+        DOCUMENT_RENAMED,
+        RenamedDocument {
+            from: from.to_path_buf(),
+            document,
+        },
+    )?;
+    Ok(true)
+}
+
+async fn sync_path<R: Runtime>(app_handle: &AppHandle<R>, path: &Path) -> anyhow::Result<()> {
+    let editor_state = app_handle.state::<EditorState>();
+
+    // The settings file can also be open as a document
+    if let Some(worktree) = path.parent().filter(|_| is_project_settings_file(path)) {
+        if editor_state.get_worktree_path(worktree).as_deref() == Some(worktree) {
+            debug!("Project settings changed (path={:?})", path);
+            app_handle.state::<ProjectSettingsStore>().reload(worktree);
+            app_handle.emit(PROJECT_SETTINGS_CHANGED, worktree)?;
+        }
+    }
+
+    match editor_state.sync_document(path)? {
+        Some(ExternalChange::Reloaded(doc)) => editor_state.changed_doc_tx.send(doc).await?,
+        Some(ExternalChange::Conflict(doc)) => app_handle.emit(DOCUMENT_CONFLICT, &doc)?,
+        Some(ExternalChange::Deleted(doc)) => app_handle.emit(DOCUMENT_DELETED, &doc)?,
+        None => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::mpsc::channel;
+    use std::time::{Duration, Instant};
+
+    use notify::event::{AccessKind, CreateKind, ModifyKind, RenameMode};
+    use notify::{Event, EventKind};
+    use serial_test::serial;
+    use tauri::test::mock_app;
+    use tauri::{Listener, Manager};
+
+    use crate::editor::editor_state::EditorState;
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+
+    use super::{
+        handle_watch_event, DocumentWatcher, Renames, WatchEvent, DOCUMENT_RENAMED, RENAME_TIMEOUT,
+    };
+
+    fn rename(mode: RenameMode, path: &Path, tracker: Option<usize>) -> Event {
+        let event = Event::new(EventKind::Modify(ModifyKind::Name(mode))).add_path(path.into());
+        match tracker {
+            Some(tracker) => event.set_tracker(tracker),
+            None => event,
+        }
+    }
+
+    #[test]
+    fn test_watch_events() {
+        let a = get_test_dir().join("a.ts");
+        let b = get_test_dir().join("b.ts");
+        let now = Instant::now();
+        let mut renames = Renames::default();
+
+        let event = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+            .add_path(a.clone())
+            .add_path(b.clone());
+        assert_eq!(
+            renames.push(event, now),
+            vec![WatchEvent::Renamed(a.clone(), b.clone())]
+        );
+
+        let event = Event::new(EventKind::Create(CreateKind::File)).add_path(a.clone());
+        assert_eq!(
+            renames.push(event, now),
+            vec![WatchEvent::Changed(a.clone())]
+        );
+
+        let event = Event::new(EventKind::Access(AccessKind::Any)).add_path(a.clone());
+        assert!(renames.push(event, now).is_empty());
+    }
+
+    #[test]
+    fn test_rename_halves() {
+        create_test_workspace(true);
+        let a = get_test_dir().join("a.ts");
+        let b = get_test_dir().join("README.md");
+        let now = Instant::now();
+        let mut renames = Renames::default();
+
+        // Linux sends From, To and Both with the same tracker
+        assert!(renames
+            .push(rename(RenameMode::From, &a, Some(1)), now)
+            .is_empty());
+        assert_eq!(
+            renames.push(rename(RenameMode::To, &b, Some(1)), now),
+            vec![WatchEvent::Renamed(a.clone(), b.clone())]
+        );
+        let both = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+            .add_path(a.clone())
+            .add_path(b.clone())
+            .set_tracker(1);
+        assert!(renames.push(both, now).is_empty());
+
+        // Windows sends From and To without tracker
+        renames.push(rename(RenameMode::From, &a, None), now);
+        assert_eq!(
+            renames.push(rename(RenameMode::To, &b, None), now),
+            vec![WatchEvent::Renamed(a.clone(), b.clone())]
+        );
+
+        // Halves of different renames are changes
+        renames.push(rename(RenameMode::From, &a, Some(2)), now);
+        assert_eq!(
+            renames.push(rename(RenameMode::To, &b, Some(3)), now),
+            vec![
+                WatchEvent::Changed(a.clone()),
+                WatchEvent::Changed(b.clone())
+            ]
+        );
+
+        // macOS sends Any for both paths, only the source is gone
+        renames.push(rename(RenameMode::Any, &a, None), now);
+        assert_eq!(
+            renames.push(rename(RenameMode::Any, &b, None), now),
+            vec![WatchEvent::Renamed(a.clone(), b.clone())]
+        );
+
+        // A file moved out of the watched dirs is a change after the timeout
+        renames.push(rename(RenameMode::From, &a, Some(4)), now);
+        assert!(renames.expire(now).is_empty());
+        assert_eq!(
+            renames.expire(now + RENAME_TIMEOUT),
+            vec![WatchEvent::Changed(a.clone())]
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_watcher() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("README.md");
+
+        let watcher = DocumentWatcher::new().unwrap();
+        watcher.watch(&path).unwrap();
+        std::fs::write(&path, "changed").unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let WatchEvent::Changed(p) = watcher.rx.recv().await.unwrap() {
+                    if p == path {
+                        break;
+                    }
+                }
+            }
+        })
+        .await;
+        assert!(event.is_ok());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_watch_rename() {
+        create_test_workspace(true);
+        let from = get_test_dir().join("README.md");
+        let to = get_test_dir().join("renamed.md");
+
+        let watcher = DocumentWatcher::new().unwrap();
+        watcher.watch(&from).unwrap();
+        std::fs::rename(&from, &to).unwrap();
+
+        // The separate halves arrive as one rename
+        let event = tokio::time::timeout(Duration::from_secs(5), watcher.rx.recv()).await;
+        assert_eq!(
+            event.unwrap().unwrap(),
+            WatchEvent::Renamed(from.clone(), to.clone())
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_rename() {
+        create_test_workspace(true);
+        let from = get_test_dir().join("src").join("index.ts");
+        let to = get_test_dir().join("src").join("renamed.ts");
+
+        let app = mock_app();
+        app.manage(EditorState::default());
+        let editor_state = app.state::<EditorState>();
+        editor_state.get_document(&from).await.unwrap();
+        editor_state.open_doc_rx.recv().await.unwrap();
+
+        let (tx, rx) = channel();
+        app.listen(DOCUMENT_RENAMED, move |event| {
+            let _ = tx.send(event.payload().to_string());
+        });
+
+        std::fs::rename(&from, &to).unwrap();
+        handle_watch_event(app.handle(), WatchEvent::Renamed(from.clone(), to.clone()))
+            .await
+            .unwrap();
+
+        assert!(editor_state.documents.read().unwrap().get(&from).is_none());
+        let doc = editor_state.documents.read().unwrap().get(&to).cloned();
+        assert_eq!(doc.unwrap().path, to);
+        assert_eq!(editor_state.open_doc_rx.recv().await.unwrap(), to);
+        let closed = editor_state.closed_doc_rx.recv().await.unwrap();
+        assert_eq!(closed.path, from);
+
+        let payload = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(payload.contains("renamed.ts"));
+
+        // A document that is open at the new path is not replaced
+        editor_state.get_document(&from).await.unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        std::fs::write(&from, "moved").unwrap();
+        std::fs::rename(&from, &to).unwrap();
+        handle_watch_event(app.handle(), WatchEvent::Renamed(from.clone(), to.clone()))
+            .await
+            .unwrap();
+        assert!(editor_state.documents.read().unwrap().contains_key(&from));
+        let doc = editor_state.documents.read().unwrap().get(&to).cloned();
+        assert_eq!(doc.unwrap().text.to_string(), "moved");
+    }
+}
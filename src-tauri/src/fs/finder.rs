@@ -0,0 +1,443 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use ignore::overrides::Override;
+use ignore::WalkBuilder;
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use tauri::{path::SafePathBuf, AppHandle, Manager, Runtime};
+use tracing::{debug, error, info};
+
+use crate::editor::editor_state::EditorState;
+use crate::editor::project_settings::ProjectSettingsStore;
+
+const DEFAULT_LIMIT: usize = 50;
+
+#[derive(Default)]
+struct Indexes {
+    files: HashMap<PathBuf, Arc<Vec<String>>>,
+    // Bumped when a root is invalidated, a walk that overlapped with an
+    // invalidation is not cached
+    generations: HashMap<PathBuf, u64>,
+}
+
+impl Indexes {
+    fn generation(&mut self, root: &Path) -> u64 {
+        *self.generations.entry(root.to_path_buf()).or_default()
+    }
+
+    // Returns false if the root was invalidated since `generation`
+    fn insert(&mut self, root: &Path, generation: u64, files: Arc<Vec<String>>) -> bool {
+        if self.generations.get(root) != Some(&generation) {
+            return false;
+        }
+        self.files.insert(root.to_path_buf(), files);
+        true
+    }
+
+    fn invalidate(&mut self, path: &Path) {
+        for (root, generation) in &mut self.generations {
+            if path.starts_with(root) {
+                *generation += 1;
+                self.files.remove(root);
+            }
+        }
+    }
+}
+
+type SharedIndexes = Arc<RwLock<Indexes>>;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileMatch {
+    pub path: PathBuf,
+    pub relative_path: String,
+    pub score: i64,
+    // UTF-16 offsets of the matched characters in the relative path
+    pub positions: Vec<usize>,
+}
+
+// Caches the non-ignored files of each worktree. An index is dropped when
+// files are added, removed or renamed, or ignore files change, and rebuilt on
+// the next query.
+pub struct FileIndex {
+    indexes: SharedIndexes,
+    watcher: Mutex<RecommendedWatcher>,
+    // Dirs found by the walks. Each one is watched on its own, so ignored
+    // dirs like node_modules or target use no watches and cause no events.
+    watched: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl FileIndex {
+    pub fn new() -> anyhow::Result<Self> {
+        let indexes: SharedIndexes = Arc::new(RwLock::new(Indexes::default()));
+        let watched = Arc::new(Mutex::new(HashSet::new()));
+        let cache = indexes.clone();
+        let dirs = watched.clone();
+        let watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+            Ok(event) => {
+                Self::forget_removed(&dirs, &event);
+                Self::invalidate(&cache, &event);
+            }
+            Err(e) => error!("Watch error: {:?}", e),
+        })?;
+
+        Ok(Self {
+            indexes,
+            watcher: Mutex::new(watcher),
+            watched,
+        })
+    }
+
+    pub fn find(
+        &self,
+        root: &Path,
+        query: &str,
+        limit: usize,
+        excludes: &Override,
+    ) -> anyhow::Result<Vec<FileMatch>> {
+        let files = self.files(root)?;
+        let matcher = SkimMatcherV2::default().smart_case();
+
+        let mut matches: Vec<FileMatch> = files
+            .iter()
+            .filter(|file| !excludes.matched(root.join(file), false).is_ignore())
+            .filter_map(|file| {
+                let (score, indices) = if query.is_empty() {
+                    (0, Vec::new())
+                } else {
+                    matcher.fuzzy_indices(file, query)?
+                };
+
+                Some(FileMatch {
+                    path: root.join(file),
+                    relative_path: file.clone(),
+                    score,
+                    positions: to_utf16_positions(file, &indices),
+                })
+            })
+            .collect();
+
+        // Shorter paths win on equal scores
+        matches.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then(a.relative_path.len().cmp(&b.relative_path.len()))
+                .then(a.relative_path.cmp(&b.relative_path))
+        });
+        matches.truncate(limit);
+        Ok(matches)
+    }
+
+    fn files(&self, root: &Path) -> anyhow::Result<Arc<Vec<String>>> {
+        if let Some(files) = self.indexes.read().unwrap().files.get(root) {
+            return Ok(files.clone());
+        }
+
+        let generation = self.indexes.write().unwrap().generation(root);
+        let (files, dirs) = index_files(root);
+        let files = Arc::new(files);
+        info!("Indexed {} files (root={:?})", files.len(), root);
+        // Files created in new dirs before their watch was added are missed,
+        // so the next query walks again
+        if self.watch_dirs(root, dirs) {
+            debug!("New dirs watched, index not cached (root={:?})", root);
+        } else if !self
+            .indexes
+            .write()
+            .unwrap()
+            .insert(root, generation, files.clone())
+        {
+            debug!("Index changed during walk, not cached (root={:?})", root);
+        }
+        Ok(files)
+    }
+
+    // Watches the dirs of the walk that are not watched yet and unwatches the
+    // ones that are gone or ignored now. Returns true if new dirs are watched.
+    fn watch_dirs(&self, root: &Path, mut dirs: HashSet<PathBuf>) -> bool {
+        // Hidden, so not part of the walk
+        let exclude_dir = root.join(".git").join("info");
+        if exclude_dir.is_dir() {
+            dirs.insert(exclude_dir);
+        }
+
+        let mut watcher = self.watcher.lock().unwrap();
+        let mut watched = self.watched.lock().unwrap();
+        watched.retain(|dir| {
+            let stale = dir.starts_with(root) && !dirs.contains(dir);
+            if stale {
+                // Fails if the dir was removed, its watch is gone then too
+                let _ = watcher.unwatch(dir);
+            }
+            !stale
+        });
+
+        let mut count = 0;
+        for dir in dirs {
+            if watched.contains(&dir) {
+                continue;
+            }
+            match watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    watched.insert(dir);
+                    count += 1;
+                }
+                Err(e) => debug!("Could not watch dir (path={:?}): {:?}", dir, e),
+            }
+        }
+        if count > 0 {
+            debug!("Watch {} new dirs (root={:?})", count, root);
+        }
+        count > 0
+    }
+
+    // Watches of removed or renamed dirs are gone, so they are watched again
+    // by the next walk that finds them
+    fn forget_removed(watched: &Mutex<HashSet<PathBuf>>, event: &Event) {
+        if !matches!(
+            event.kind,
+            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+        ) {
+            return;
+        }
+        let mut watched = watched.lock().unwrap();
+        for path in &event.paths {
+            watched.retain(|dir| !dir.starts_with(path));
+        }
+    }
+
+    fn invalidate(indexes: &SharedIndexes, event: &Event) {
+        let structural = matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+        );
+        let modified = matches!(event.kind, EventKind::Modify(_));
+
+        let mut indexes = indexes.write().unwrap();
+        for path in &event.paths {
+            // Changed ignore rules change which files are indexed
+            let ignore_rules = is_ignore_file(path) && (structural || modified);
+            if ignore_rules || (structural && !in_git_dir(path)) {
+                indexes.invalidate(path);
+            }
+        }
+    }
+}
+
+fn in_git_dir(path: &Path) -> bool {
+    path.components()
+        .any(|c| c == Component::Normal(".git".as_ref()))
+}
+
+// Files that the walk reads ignore rules from
+fn is_ignore_file(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str());
+    matches!(name, Some(".gitignore" | ".ignore")) || path.ends_with(".git/info/exclude")
+}
+
+// Returns the relative paths of the non-ignored files and the non-ignored
+// dirs including the root
+fn index_files(root: &Path) -> (Vec<String>, HashSet<PathBuf>) {
+    let mut files = Vec::new();
+    let mut dirs = HashSet::new();
+    for entry in WalkBuilder::new(root).require_git(false).build().flatten() {
+        match entry.file_type() {
+            Some(t) if t.is_dir() => {
+                dirs.insert(entry.into_path());
+            }
+            Some(t) if t.is_file() => {
+                if let Ok(path) = entry.path().strip_prefix(root) {
+                    files.push(path.to_string_lossy().to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    files.sort();
+    (files, dirs)
+}
+
+fn to_utf16_positions(text: &str, char_indices: &[usize]) -> Vec<usize> {
+    let mut positions = Vec::with_capacity(char_indices.len());
+    let mut indices = char_indices.iter().peekable();
+    let mut offset = 0;
+    for (i, c) in text.chars().enumerate() {
+        if indices.next_if_eq(&&i).is_some() {
+            positions.push(offset);
+        }
+        offset += c.len_utf16();
+    }
+    positions
+}
+
+// Finds files in the worktree of `path`, or in `path` itself if it is a
+// directory outside of a worktree
+#[tauri::command]
+pub async fn find_files<R: Runtime>(
+    path: SafePathBuf,
+    query: String,
+    limit: Option<usize>,
+    app_handle: AppHandle<R>,
+) -> tauri::Result<Vec<FileMatch>> {
+    let path = path.as_ref();
+    let worktree = app_handle.state::<EditorState>().get_worktree_path(path);
+    let root = match worktree.clone() {
+        Some(root) => root,
+        None if path.is_dir() => path.to_path_buf(),
+        None => path.parent().unwrap_or(path).to_path_buf(),
+    };
+
+    // Paths excluded in the project settings
+    let excludes = match worktree {
+        Some(_) => app_handle
+            .state::<ProjectSettingsStore>()
+            .get(&root)
+            .excludes(&root)?,
+        None => Override::empty(),
+    };
+
+    let matches = tauri::async_runtime::spawn_blocking(move || {
+        let file_index = app_handle.state::<FileIndex>();
+        file_index.find(&root, &query, limit.unwrap_or(DEFAULT_LIMIT), &excludes)
+    })
+    .await??;
+
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use ignore::overrides::{Override, OverrideBuilder};
+    use serial_test::serial;
+
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+
+    use super::{is_ignore_file, to_utf16_positions, FileIndex, Indexes};
+
+    #[test]
+    fn test_to_utf16_positions() {
+        assert_eq!(to_utf16_positions("a😀b", &[0, 2]), vec![0, 3]);
+    }
+
+    #[test]
+    fn test_indexes() {
+        let root = Path::new("/repo");
+        let mut indexes = Indexes::default();
+
+        // An invalidation during the walk is not lost
+        let generation = indexes.generation(root);
+        indexes.invalidate(&root.join("new.rs"));
+        assert!(!indexes.insert(root, generation, Arc::new(Vec::new())));
+        assert!(indexes.files.is_empty());
+
+        let generation = indexes.generation(root);
+        indexes.invalidate(Path::new("/other/new.rs"));
+        assert!(indexes.insert(root, generation, Arc::new(Vec::new())));
+        indexes.invalidate(&root.join("new.rs"));
+        assert!(indexes.files.is_empty());
+
+        assert!(is_ignore_file(&root.join("src").join(".gitignore")));
+        assert!(is_ignore_file(
+            &root.join(".git").join("info").join("exclude")
+        ));
+        assert!(!is_ignore_file(&root.join("gitignore.md")));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_find() {
+        create_test_workspace(true);
+        std::fs::write(get_test_dir().join(".gitignore"), "target\n").unwrap();
+        std::fs::create_dir(get_test_dir().join("target")).unwrap();
+        std::fs::write(get_test_dir().join("target").join("main.rs"), "").unwrap();
+
+        let file_index = FileIndex::new().unwrap();
+        let root = get_test_dir().canonicalize().unwrap();
+        let none = Override::empty();
+
+        let matches = file_index.find(&root, "mn", 10, &none).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].relative_path, "src/main.rs");
+        assert_eq!(matches[0].path, root.join("src").join("main.rs"));
+        assert_eq!(matches[0].positions, vec![4, 7]);
+
+        let matches = file_index.find(&root, "", 10, &none).unwrap();
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[0].relative_path, "README.md");
+
+        let mut excludes = OverrideBuilder::new(&root);
+        excludes.add("!src/**").unwrap();
+        let matches = file_index
+            .find(&root, "", 10, &excludes.build().unwrap())
+            .unwrap();
+        assert_eq!(matches.len(), 1);
+
+        // The first walk added watches, the second one is cached
+        assert!(file_index.indexes.read().unwrap().files.contains_key(&root));
+
+        // Ignored dirs are not watched
+        {
+            let watched = file_index.watched.lock().unwrap();
+            assert!(watched.contains(&root));
+            assert!(watched.contains(&root.join("src")));
+            assert!(!watched.contains(&root.join("target")));
+        }
+
+        // New files show up once the watcher dropped the index, also in new
+        // dirs
+        std::fs::create_dir(get_test_dir().join("src").join("sub")).unwrap();
+        std::fs::write(get_test_dir().join("src").join("mod.rs"), "").unwrap();
+        let found = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let matches = file_index.find(&root, "mod", 10, &none).unwrap();
+                if !matches.is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+        assert!(found.is_ok());
+        assert!(file_index
+            .watched
+            .lock()
+            .unwrap()
+            .contains(&root.join("src").join("sub")));
+
+        std::fs::write(get_test_dir().join("src").join("sub").join("lib.rs"), "").unwrap();
+        let found = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let matches = file_index.find(&root, "sub/lib", 10, &none).unwrap();
+                if !matches.is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+        assert!(found.is_ok());
+
+        // Changed ignore rules drop the index too
+        std::fs::write(get_test_dir().join(".gitignore"), "").unwrap();
+        let found = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let matches = file_index.find(&root, "target", 10, &none).unwrap();
+                if !matches.is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+        assert!(found.is_ok());
+    }
+}
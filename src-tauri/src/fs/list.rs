@@ -2,8 +2,13 @@ use crate::editor::pathutil::{self as pu};
 use anyhow::anyhow;
 use globset::Glob;
 use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tauri::path::SafePathBuf;
 use tracing::info;
-use std::path::PathBuf;
 
 #[tauri::command]
 pub fn list_contents(path: String, base_path: Option<String>) -> tauri::Result<Vec<String>> {
@@ -43,18 +48,127 @@ pub fn list_contents(path: String, base_path: Option<String>) -> tauri::Result<V
         }
     }
 
-    files.truncate(10);
     files.sort();
+    files.truncate(10);
     Ok(files)
 }
 
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ListDirectoryOptions {
+    // Leave out entries that are ignored by .gitignore and similar files
+    pub hide_ignored: bool,
+    pub show_hidden: bool,
+    // Number of levels to list, defaults to one so that dirs are expanded lazily
+    pub depth: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub symlink_target: Option<PathBuf>,
+    pub is_ignored: bool,
+    pub is_hidden: bool,
+    // Only set for dirs within the requested depth
+    pub children: Option<Vec<DirEntry>>,
+}
+
+#[tauri::command]
+pub async fn list_directory(
+    path: SafePathBuf,
+    options: Option<ListDirectoryOptions>,
+) -> tauri::Result<Vec<DirEntry>> {
+    let options = options.unwrap_or_default();
+    let depth = options.depth.unwrap_or(1);
+    let entries = list_dir(path.as_ref(), &options, depth)?;
+    Ok(entries)
+}
+
+fn list_dir(
+    dir: &Path,
+    options: &ListDirectoryOptions,
+    depth: usize,
+) -> anyhow::Result<Vec<DirEntry>> {
+    let walk = |standard_filters| {
+        WalkBuilder::new(dir)
+            .max_depth(Some(1))
+            .standard_filters(standard_filters)
+            .hidden(false)
+            .require_git(false)
+            .build()
+            .flatten()
+            .filter(|e| e.depth() == 1)
+    };
+
+    // Entries that are missing in the filtered walk are ignored
+    let not_ignored: HashSet<PathBuf> = walk(true).map(|e| e.into_path()).collect();
+
+    let mut entries = Vec::new();
+    for entry in walk(false) {
+        let path = entry.into_path();
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let is_hidden = name.starts_with('.');
+        let is_ignored = !not_ignored.contains(&path);
+        if (is_hidden && !options.show_hidden) || (is_ignored && options.hide_ignored) {
+            continue;
+        }
+
+        let metadata = fs::symlink_metadata(&path)?;
+        let (kind, symlink_target) = if metadata.is_symlink() {
+            (EntryKind::Symlink, fs::read_link(&path).ok())
+        } else if metadata.is_dir() {
+            (EntryKind::Dir, None)
+        } else {
+            (EntryKind::File, None)
+        };
+
+        let children = if kind == EntryKind::Dir && depth > 1 {
+            Some(list_dir(&path, options, depth - 1)?)
+        } else {
+            None
+        };
+
+        entries.push(DirEntry {
+            name,
+            path,
+            kind,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            symlink_target,
+            is_ignored,
+            is_hidden,
+            children,
+        });
+    }
+
+    // Dirs first, symlinks to dirs count as dirs
+    entries.sort_by_cached_key(|e| (!e.path.is_dir(), e.name.to_lowercase()));
+    Ok(entries)
+}
+
 #[cfg(test)]
 mod tests {
     use serial_test::serial;
 
     use super::*;
 
-    use crate::editor::testutil::{create_test_workspace, get_home_as_string};
+    use crate::editor::testutil::{create_test_workspace, get_home_as_string, get_test_dir};
 
     #[test]
     #[serial]
@@ -99,4 +213,67 @@ mod tests {
 
         assert!(!list_contents("".to_string(), None).unwrap().is_empty());
     }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_list_directory() {
+        create_test_workspace(true);
+        let dir = get_test_dir();
+        std::fs::write(dir.join(".gitignore"), "dist\n").unwrap();
+        std::fs::create_dir(dir.join("dist")).unwrap();
+        std::fs::write(dir.join("src").join("main.rs"), "fn main() {}").unwrap();
+
+        let list = |options: ListDirectoryOptions| {
+            let entries = list_dir(&dir, &options, options.depth.unwrap_or(1)).unwrap();
+            entries.into_iter().map(|e| e.name).collect::<Vec<_>>()
+        };
+
+        assert_eq!(list(Default::default()), vec!["dist", "src", "README.md"]);
+        assert_eq!(
+            list(ListDirectoryOptions {
+                hide_ignored: true,
+                ..Default::default()
+            }),
+            vec!["src", "README.md"]
+        );
+        assert_eq!(
+            list(ListDirectoryOptions {
+                show_hidden: true,
+                ..Default::default()
+            }),
+            vec![".git", "dist", "src", ".gitignore", "README.md"]
+        );
+
+        let options = ListDirectoryOptions {
+            depth: Some(2),
+            ..Default::default()
+        };
+        let entries = list_dir(&dir, &options, 2).unwrap();
+        let dist = &entries[0];
+        assert_eq!(dist.kind, EntryKind::Dir);
+        assert!(dist.is_ignored);
+        assert!(!dist.is_hidden);
+
+        let src = entries[1].children.as_ref().unwrap();
+        let names: Vec<_> = src.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["index.ts", "main.rs"]);
+        assert_eq!(src[1].size, 12);
+        assert!(src[1].modified.is_some());
+        assert!(entries[2].children.is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    #[serial]
+    async fn test_list_directory_symlink() {
+        create_test_workspace(true);
+        let dir = get_test_dir();
+        std::os::unix::fs::symlink(dir.join("src"), dir.join("link")).unwrap();
+
+        let entries = list_dir(&dir, &Default::default(), 1).unwrap();
+        let link = entries.iter().find(|e| e.name == "link").unwrap();
+        assert_eq!(link.kind, EntryKind::Symlink);
+        assert_eq!(link.symlink_target, Some(dir.join("src")));
+        assert_eq!(entries[0].name, "link");
+    }
 }
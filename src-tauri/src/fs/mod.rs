@@ -1,3 +1,8 @@
+pub mod finder;
 pub mod list;
 pub mod metadata;
+pub mod operations;
 pub mod path;
+pub mod search;
+pub mod trash;
+pub mod write;
@@ -0,0 +1,310 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use tauri::{AppHandle, Manager, Runtime};
+use tracing::{error, info};
+
+use crate::editor::editor_state::EditorState;
+use crate::editor::pathutil::{self as pu};
+use crate::editor::watcher::move_document;
+use crate::lsp::service::LspService;
+
+use super::trash::{self, Trash, TrashEntry};
+
+#[tauri::command]
+pub async fn create_file(path: String) -> tauri::Result<PathBuf> {
+    let path = resolve_new_path(&path)?;
+    info!("Create file (path={:?})", path);
+    fs::File::create_new(&path)?;
+    Ok(path)
+}
+
+#[tauri::command]
+pub async fn create_dir(path: String) -> tauri::Result<PathBuf> {
+    let path = resolve_new_path(&path)?;
+    info!("Create dir (path={:?})", path);
+    fs::create_dir(&path)?;
+    Ok(path)
+}
+
+#[tauri::command]
+pub async fn rename_path<R: Runtime>(
+    from: String,
+    to: String,
+    app_handle: AppHandle<R>,
+) -> tauri::Result<PathBuf> {
+    let from = pu::resolve_path(&from, None)?;
+    let to = resolve_new_path(&to)?;
+    rename(&app_handle, &from, &to).await?;
+    Ok(to)
+}
+
+// Moves a file or dir into another dir and keeps its name
+#[tauri::command]
+pub async fn move_path<R: Runtime>(
+    from: String,
+    dir: String,
+    app_handle: AppHandle<R>,
+) -> tauri::Result<PathBuf> {
+    let from = pu::resolve_path(&from, None)?;
+    let dir = pu::resolve_path(&dir, None)?;
+    let to = dir.join(pu::basename(&from)?);
+    check_not_exists(&to)?;
+    rename(&app_handle, &from, &to).await?;
+    Ok(to)
+}
+
+#[tauri::command]
+pub async fn copy_path(from: String, to: String) -> tauri::Result<PathBuf> {
+    let from = pu::resolve_path(&from, None)?;
+    let to = resolve_new_path(&to)?;
+    if to.starts_with(&from) {
+        return Err(anyhow!("Cannot copy a dir into itself (path={:?})", from).into());
+    }
+
+    info!("Copy (from={:?}, to={:?})", from, to);
+    trash::copy_path(&from, &to)?;
+    Ok(to)
+}
+
+// Moves a file or dir to the trash. Open documents are kept and marked as
+// deleted by the document watcher.
+#[tauri::command]
+pub async fn delete_path<R: Runtime>(
+    path: String,
+    app_handle: AppHandle<R>,
+) -> tauri::Result<TrashEntry> {
+    let path = pu::resolve_path(&path, None)?;
+    info!("Delete (path={:?})", path);
+    let entry = app_handle.state::<Trash>().delete(&path)?;
+    Ok(entry)
+}
+
+#[tauri::command]
+pub async fn restore_from_trash<R: Runtime>(
+    id: u64,
+    app_handle: AppHandle<R>,
+) -> tauri::Result<PathBuf> {
+    let path = app_handle.state::<Trash>().restore(id)?;
+    Ok(path)
+}
+
+#[tauri::command]
+pub async fn list_trash<R: Runtime>(app_handle: AppHandle<R>) -> tauri::Result<Vec<TrashEntry>> {
+    let entries = app_handle.state::<Trash>().list()?;
+    Ok(entries)
+}
+
+// Renames on disk and moves open documents below `from` to their new paths.
+// Language servers can update references before the rename.
+async fn rename<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    from: &Path,
+    to: &Path,
+) -> anyhow::Result<()> {
+    info!("Rename (from={:?}, to={:?})", from, to);
+    let lsp_service = app_handle.state::<LspService<R>>();
+    let editor_state = app_handle.state::<EditorState>();
+
+    lsp_service.will_rename_files(from, to).await;
+    trash::move_path(from, to)?;
+
+    let paths: Vec<PathBuf> = editor_state
+        .documents
+        .read()
+        .unwrap()
+        .keys()
+        .filter(|path| path.starts_with(from))
+        .cloned()
+        .collect();
+
+    for path in paths {
+        let new_path = to.join(path.strip_prefix(from)?);
+        // The watcher syncs both documents if the new path is open too
+        if let Err(e) = move_document(app_handle, &path, &new_path).await {
+            error!("Could not move document: {:?}", e);
+        }
+    }
+
+    lsp_service.did_rename_files(from, to).await;
+    Ok(())
+}
+
+// Resolves the parent dir of a path that does not exist yet
+fn resolve_new_path(path: &str) -> anyhow::Result<PathBuf> {
+    let path = pu::to_absolute_path(path, None)?;
+    let parent = path
+        .parent()
+        .ok_or(anyhow!("No parent dir (path={:?})", path))?;
+    let path = pu::resolve_path(parent, None)?.join(pu::basename(&path)?);
+    check_not_exists(&path)?;
+    Ok(path)
+}
+
+fn check_not_exists(path: &Path) -> anyhow::Result<()> {
+    if path.exists() || path.is_symlink() {
+        return Err(anyhow!("Path already exists (path={:?})", path));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use async_lsp::lsp_types::{Position, Range, TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit};
+    use serial_test::serial;
+    use tauri::test::{mock_app, MockRuntime};
+    use tauri::{App, Manager};
+    use tempfile::TempDir;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use crate::editor::editor_state::EditorState;
+    use crate::editor::project_settings::ProjectSettingsStore;
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+    use crate::fs::trash::Trash;
+    use crate::lsp::service::{LspService, OffsetEncoding};
+    use crate::lsp::testutil::{FakeLanguageServer, FakeMessage};
+    use crate::settings::store::{SettingsStore, SETTINGS_FILE};
+
+    use super::*;
+
+    fn create_app(
+        fake: FakeLanguageServer,
+    ) -> (App<MockRuntime>, UnboundedReceiver<FakeMessage>, TempDir) {
+        let (lsp_registry, rx) = fake.registry();
+        let trash_dir = tempfile::tempdir().unwrap();
+        let app = mock_app();
+        app.manage(EditorState::default());
+        app.manage(ProjectSettingsStore::new());
+        app.manage(SettingsStore::new(get_test_dir().join(SETTINGS_FILE)));
+        app.manage(lsp_registry);
+        app.manage(LspService::new(app.handle().clone(), false));
+        app.manage(Trash::new(trash_dir.path().to_path_buf()).unwrap());
+        (app, rx, trash_dir)
+    }
+
+    fn test_path(path: &str) -> String {
+        get_test_dir().join(path).to_string_lossy().to_string()
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_create_copy_delete() {
+        create_test_workspace(true);
+        let fake = FakeLanguageServer::new(OffsetEncoding::Utf16, TextDocumentSyncKind::FULL);
+        let (app, _, _trash_dir) = create_app(fake);
+        let dir = get_test_dir().canonicalize().unwrap();
+
+        let path = create_dir(test_path("docs")).await.unwrap();
+        assert_eq!(path, dir.join("docs"));
+        let path = create_file(test_path("docs/notes.md")).await.unwrap();
+        assert!(path.is_file());
+        assert!(create_file(test_path("docs/notes.md")).await.is_err());
+        assert!(create_file(test_path("missing/notes.md")).await.is_err());
+
+        std::fs::write(dir.join("docs").join("notes.md"), "notes").unwrap();
+        let copy = copy_path(test_path("docs"), test_path("copy"))
+            .await
+            .unwrap();
+        let notes = copy.join("notes.md");
+        assert_eq!(std::fs::read_to_string(&notes).unwrap(), "notes");
+        assert!(copy_path(test_path("docs"), test_path("docs/copy"))
+            .await
+            .is_err());
+
+        let entry = delete_path(test_path("copy"), app.handle().clone())
+            .await
+            .unwrap();
+        assert!(!copy.exists());
+        let entries = list_trash(app.handle().clone()).await.unwrap();
+        assert_eq!(entries, vec![entry.clone()]);
+
+        restore_from_trash(entry.id, app.handle().clone())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&notes).unwrap(), "notes");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_rename() {
+        create_test_workspace(true);
+        let dir = get_test_dir().canonicalize().unwrap();
+        let index = dir.join("src").join("index.ts");
+        let main = dir.join("src").join("main.ts");
+        std::fs::write(&index, "import './main'\r\n").unwrap();
+        std::fs::write(&main, "").unwrap();
+
+        // The server updates the import in the closed index.ts
+        let mut fake = FakeLanguageServer::new(OffsetEncoding::Utf16, TextDocumentSyncKind::FULL);
+        let uri = Url::from_file_path(&index).unwrap();
+        fake.will_rename = Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(
+                uri,
+                vec![TextEdit {
+                    range: Range::new(Position::new(0, 10), Position::new(0, 14)),
+                    new_text: "app".to_string(),
+                }],
+            )])),
+            ..Default::default()
+        });
+        let (app, mut rx, _trash_dir) = create_app(fake);
+
+        let editor_state = app.state::<EditorState>();
+        let lsp_service = app.state::<LspService<MockRuntime>>();
+        editor_state.get_document(&main).await.unwrap();
+        lsp_service.register_language_server(&main).await.unwrap();
+        while !matches!(rx.recv().await, Some(FakeMessage::DidOpen(_))) {}
+
+        let to = rename_path(
+            test_path("src/main.ts"),
+            test_path("src/app.ts"),
+            app.handle().clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(to, dir.join("src").join("app.ts"));
+        assert!(to.exists());
+        assert!(!main.exists());
+
+        match rx.recv().await {
+            Some(FakeMessage::WillRenameFiles(params)) => {
+                assert!(params.files[0].old_uri.ends_with("main.ts"));
+                assert!(params.files[0].new_uri.ends_with("app.ts"));
+            }
+            msg => panic!("Expected willRenameFiles, got {:?}", msg),
+        }
+        match rx.recv().await {
+            Some(FakeMessage::DidRenameFiles(params)) => {
+                assert_eq!(
+                    params.files[0].new_uri,
+                    Url::from_file_path(&to).unwrap().as_str()
+                );
+            }
+            msg => panic!("Expected didRenameFiles, got {:?}", msg),
+        }
+        assert_eq!(
+            std::fs::read_to_string(&index).unwrap(),
+            "import './app'\r\n"
+        );
+
+        let doc = editor_state.documents.read().unwrap().get(&to).cloned();
+        assert_eq!(doc.unwrap().path, to);
+        assert!(!editor_state.documents.read().unwrap().contains_key(&main));
+
+        // The renamed document is registered again after the first open
+        assert_eq!(editor_state.open_doc_rx.recv().await.unwrap(), main);
+        assert_eq!(editor_state.open_doc_rx.recv().await.unwrap(), to);
+
+        // Moving keeps the name
+        let docs_dir = create_dir(test_path("docs")).await.unwrap();
+        let moved = move_path(test_path("src"), test_path("docs"), app.handle().clone())
+            .await
+            .unwrap();
+        assert_eq!(moved, docs_dir.join("src"));
+        let path = moved.join("app.ts");
+        assert!(editor_state.documents.read().unwrap().contains_key(&path));
+    }
+}
@@ -59,7 +59,7 @@ mod tests {
         let test_base_path = get_test_dir().join("src");
         let test_path = test_base_path.join("index.rs");
 
-        let editor_state = EditorState::new();
+        let editor_state = EditorState::default();
         let app = mock_app();
         app.manage(editor_state);
         let handle = app.app_handle();
@@ -86,7 +86,7 @@ mod tests {
 
         create_test_workspace(false);
 
-        let editor_state = EditorState::new();
+        let editor_state = EditorState::default();
         let app = mock_app();
         app.manage(editor_state);
         let handle = app.app_handle();
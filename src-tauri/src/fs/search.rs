@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use regex::Regex;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use tauri::ipc::Channel;
+use tauri::{path::SafePathBuf, AppHandle, Manager, Runtime};
+use tracing::{debug, error, info};
+
+use crate::editor::editor_state::{EditorState, LARGE_FILE_SIZE};
+use crate::editor::file_format::FileFormat;
+use crate::editor::project_settings::ProjectSettingsStore;
+use crate::editor::search::{
+    apply_replacements, build_regex, replace, search_lines, LineMatch, SearchOptions,
+};
+use crate::fs::write::write_atomic;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ProjectSearchOptions {
+    #[serde(flatten)]
+    pub search: SearchOptions,
+    // Globs relative to the root, e.g. `src/**` or `*.ts`
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileMatches {
+    pub path: PathBuf,
+    pub lines: Vec<LineMatch>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectSearchSummary {
+    pub files: usize,
+    pub matches: usize,
+}
+
+// Files that could not be replaced keep their text and have an error
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileReplacement {
+    pub path: PathBuf,
+    pub matches: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Searches all files below `path` that are not ignored and sends the matches
+// of each file as soon as it is searched
+#[tauri::command]
+pub async fn search_project<R: Runtime>(
+    path: SafePathBuf,
+    query: String,
+    options: ProjectSearchOptions,
+    on_event: Channel<FileMatches>,
+    app_handle: AppHandle<R>,
+) -> tauri::Result<ProjectSearchSummary> {
+    let root = path.as_ref().to_path_buf();
+    let open_docs = open_documents(&app_handle);
+    let mut options = options;
+    add_project_excludes(&app_handle, &root, &mut options);
+
+    let summary = tauri::async_runtime::spawn_blocking(move || {
+        search_files(&root, &query, &options, &open_docs, |matches| {
+            on_event.send(matches)?;
+            Ok(())
+        })
+    })
+    .await??;
+
+    Ok(summary)
+}
+
+// Replaces all matches below `path` and returns the files with matches. Open
+// documents are edited in the editor state and keep unsaved, their editors
+// reload them. Closed files are written directly.
+#[tauri::command]
+pub async fn replace_project<R: Runtime>(
+    path: SafePathBuf,
+    query: String,
+    replacement: String,
+    options: ProjectSearchOptions,
+    app_handle: AppHandle<R>,
+) -> tauri::Result<Vec<FileReplacement>> {
+    let root = path.as_ref().to_path_buf();
+    let mut options = options;
+    add_project_excludes(&app_handle, &root, &mut options);
+
+    let results = tauri::async_runtime::spawn_blocking(move || {
+        replace_files(&root, &query, &replacement, &options, &app_handle)
+    })
+    .await??;
+
+    Ok(results)
+}
+
+pub fn search_files<F>(
+    root: &Path,
+    query: &str,
+    options: &ProjectSearchOptions,
+    open_docs: &HashMap<PathBuf, Rope>,
+    mut on_matches: F,
+) -> anyhow::Result<ProjectSearchSummary>
+where
+    F: FnMut(FileMatches) -> anyhow::Result<()>,
+{
+    info!("Search project (root={:?}, query={})", root, query);
+    let mut summary = ProjectSearchSummary::default();
+    if query.is_empty() {
+        return Ok(summary);
+    }
+
+    let regex = build_regex(query, &options.search)?;
+    for path in walk(root, options)? {
+        let Some(text) = open_docs.get(&path).cloned().or_else(|| read_text(&path)) else {
+            continue;
+        };
+
+        let lines = search_lines(&text, &regex);
+        if lines.is_empty() {
+            continue;
+        }
+
+        summary.files += 1;
+        summary.matches += lines.iter().map(|l| l.ranges.len()).sum::<usize>();
+        on_matches(FileMatches { path, lines })?;
+    }
+
+    Ok(summary)
+}
+
+fn replace_files<R: Runtime>(
+    root: &Path,
+    query: &str,
+    replacement: &str,
+    options: &ProjectSearchOptions,
+    app_handle: &AppHandle<R>,
+) -> anyhow::Result<Vec<FileReplacement>> {
+    info!("Replace in project (root={:?}, query={})", root, query);
+    let mut results = Vec::new();
+    if query.is_empty() {
+        return Ok(results);
+    }
+
+    let editor_state = app_handle.state::<EditorState>();
+    let regex = build_regex(query, &options.search)?;
+    let expand = options.search.regex;
+
+    for path in walk(root, options)? {
+        let version = editor_state
+            .documents
+            .read()
+            .unwrap()
+            .get(&path)
+            .map(|doc| doc.version);
+
+        let result = match version {
+            Some(version) => {
+                replace_document(&editor_state, &path, version, query, replacement, options)
+            }
+            None => replace_file(&path, &regex, replacement, expand),
+        };
+
+        match result {
+            Ok(0) => {}
+            Ok(matches) => results.push(FileReplacement {
+                path,
+                matches,
+                error: None,
+            }),
+            Err(e) => {
+                error!("Could not replace in file (path={:?}): {:?}", path, e);
+                results.push(FileReplacement {
+                    path,
+                    matches: 0,
+                    error: Some(e.to_string()),
+                });
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+// The changed document is sent to the language servers and the editor
+fn replace_document(
+    editor_state: &EditorState,
+    path: &Path,
+    version: i32,
+    query: &str,
+    replacement: &str,
+    options: &ProjectSearchOptions,
+) -> anyhow::Result<usize> {
+    let tx = editor_state.replace_all(path, version, query, replacement, &options.search)?;
+    let count = tx.changes.len();
+    if count > 0 {
+        editor_state.changed_doc_tx.send_blocking(tx.doc)?;
+    }
+    Ok(count)
+}
+
+// Replaces in the raw contents, so that mixed line endings are kept
+fn replace_file(
+    path: &Path,
+    regex: &Regex,
+    replacement: &str,
+    expand: bool,
+) -> anyhow::Result<usize> {
+    let Some(bytes) = read_bytes(path) else {
+        return Ok(0);
+    };
+    let Ok((format, text)) = FileFormat::decode_raw(&bytes, None) else {
+        return Ok(0);
+    };
+
+    let mut text = Rope::from_str(&text);
+    let changes = replace(&text, regex, replacement, expand);
+    if changes.is_empty() {
+        return Ok(0);
+    }
+
+    debug!("Replace in file (path={:?}, count={})", path, changes.len());
+    apply_replacements(&mut text, &changes);
+    write_atomic(path, |w| format.write(&text, w))?;
+    Ok(changes.len())
+}
+
+// Adds the excludes of the project settings. Their globs are relative to the
+// worktree, so they are rebased onto the search root.
+fn add_project_excludes<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    root: &Path,
+    options: &mut ProjectSearchOptions,
+) {
+    let editor_state = app_handle.state::<EditorState>();
+    let Some(worktree) = editor_state.get_worktree_path(root) else {
+        return;
+    };
+    let Ok(root) = root.canonicalize() else {
+        return;
+    };
+    let Ok(prefix) = root.strip_prefix(&worktree) else {
+        return;
+    };
+    let prefix = prefix.to_string_lossy().replace('\\', "/");
+
+    let settings = app_handle.state::<ProjectSettingsStore>().get(&worktree);
+    for glob in &settings.exclude {
+        let glob = glob.trim_start_matches('/');
+        // Globs without a slash match at any level
+        if prefix.is_empty() || !glob.contains('/') || glob.starts_with("**/") {
+            options.exclude.push(glob.to_string());
+        } else if let Some(rest) = glob.strip_prefix(&format!("{}/", prefix)) {
+            options.exclude.push(rest.to_string());
+        }
+    }
+}
+
+fn walk(
+    root: &Path,
+    options: &ProjectSearchOptions,
+) -> anyhow::Result<impl Iterator<Item = PathBuf>> {
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in &options.include {
+        overrides.add(glob)?;
+    }
+    for glob in &options.exclude {
+        overrides.add(&format!("!{}", glob))?;
+    }
+
+    let walker = WalkBuilder::new(root)
+        .overrides(overrides.build()?)
+        // Also use .gitignore files outside of git repos
+        .require_git(false)
+        .build();
+
+    Ok(walker
+        .flatten()
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .map(|entry| entry.into_path()))
+}
+
+// Skips large and unreadable files
+fn read_bytes(path: &Path) -> Option<Vec<u8>> {
+    if fs::metadata(path).ok()?.len() > LARGE_FILE_SIZE {
+        return None;
+    }
+    fs::read(path).ok()
+}
+
+// Also skips binary files
+fn read_text(path: &Path) -> Option<Rope> {
+    let (_, text) = FileFormat::decode(&read_bytes(path)?, None).ok()?;
+    Some(Rope::from_str(&text))
+}
+
+fn open_documents<R: Runtime>(app_handle: &AppHandle<R>) -> HashMap<PathBuf, Rope> {
+    app_handle
+        .state::<EditorState>()
+        .documents
+        .read()
+        .unwrap()
+        .values()
+        .map(|doc| (doc.path.clone(), doc.text.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use ropey::Rope;
+    use serial_test::serial;
+    use tauri::test::mock_app;
+    use tauri::Manager;
+
+    use crate::editor::editor_state::EditorState;
+    use crate::editor::search::SearchOptions;
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+
+    use super::{replace_files, search_files, FileMatches, ProjectSearchOptions};
+
+    fn write_files() {
+        create_test_workspace(true);
+        std::fs::write(get_test_dir().join(".gitignore"), "dist\n").unwrap();
+        std::fs::create_dir(get_test_dir().join("dist")).unwrap();
+        std::fs::write(get_test_dir().join("dist").join("index.js"), "foo").unwrap();
+        std::fs::write(get_test_dir().join("README.md"), "# foo\nfoo bar").unwrap();
+        std::fs::write(get_test_dir().join("src").join("main.rs"), "fn foo() {}").unwrap();
+        std::fs::write(get_test_dir().join("src").join("index.ts"), "foo()").unwrap();
+        std::fs::write(get_test_dir().join("image.png"), b"foo\0").unwrap();
+    }
+
+    fn search(
+        options: &ProjectSearchOptions,
+        open_docs: &HashMap<PathBuf, Rope>,
+    ) -> Vec<FileMatches> {
+        let mut results = Vec::new();
+        search_files(&get_test_dir(), "foo", options, open_docs, |m| {
+            results.push(m);
+            Ok(())
+        })
+        .unwrap();
+        results.sort_by(|a, b| a.path.cmp(&b.path));
+        results
+    }
+
+    #[test]
+    #[serial]
+    fn test_search_files() {
+        write_files();
+
+        let results = search(&ProjectSearchOptions::default(), &HashMap::new());
+        let paths: Vec<_> = results.iter().map(|r| r.path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                get_test_dir().join("README.md"),
+                get_test_dir().join("src").join("index.ts"),
+                get_test_dir().join("src").join("main.rs"),
+            ]
+        );
+        assert_eq!(results[0].lines.len(), 2);
+        assert_eq!(results[0].lines[1].preview, "foo bar");
+
+        let options = ProjectSearchOptions {
+            include: vec!["src/**".to_string()],
+            exclude: vec!["*.ts".to_string()],
+            ..Default::default()
+        };
+        let results = search(&options, &HashMap::new());
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, get_test_dir().join("src").join("main.rs"));
+
+        // Open documents are searched with their unsaved text
+        let readme = get_test_dir().join("README.md");
+        let open_docs = HashMap::from([(readme.clone(), Rope::from_str("nothing"))]);
+        let results = search(&ProjectSearchOptions::default(), &open_docs);
+        assert!(results.iter().all(|r| r.path != readme));
+
+        let options = ProjectSearchOptions {
+            search: SearchOptions {
+                whole_word: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(search(&options, &HashMap::new()).len(), 3);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_replace_files() {
+        write_files();
+        let readme = get_test_dir().join("README.md");
+        let main = get_test_dir().join("src").join("main.rs");
+        let mixed = get_test_dir().join("src").join("mixed.txt");
+        std::fs::write(&mixed, "foo\r\nfoo\n").unwrap();
+        let latin1 = get_test_dir().join("latin1.txt");
+        std::fs::write(&latin1, b"caf\xe9 cr\xe8me br\xfbl\xe9e foo\n").unwrap();
+
+        let app = mock_app();
+        app.manage(EditorState::default());
+        let editor_state = app.state::<EditorState>();
+        editor_state.get_document(&readme).await.unwrap();
+
+        let options = ProjectSearchOptions::default();
+        let results = replace_files(&get_test_dir(), "foo", "baz", &options, app.handle()).unwrap();
+        assert_eq!(results.len(), 5);
+        assert_eq!(results.iter().map(|r| r.matches).sum::<usize>(), 7);
+        assert!(results.iter().all(|r| r.error.is_none()));
+
+        // The open document is changed but not saved
+        let doc = editor_state
+            .documents
+            .read()
+            .unwrap()
+            .get(&readme)
+            .cloned()
+            .unwrap();
+        assert_eq!(doc.text.to_string(), "# baz\nbaz bar");
+        assert!(doc.is_dirty);
+        assert_eq!(std::fs::read_to_string(&readme).unwrap(), "# foo\nfoo bar");
+        assert_eq!(
+            editor_state.changed_doc_rx.recv().await.unwrap().path,
+            readme
+        );
+
+        assert_eq!(std::fs::read_to_string(&main).unwrap(), "fn baz() {}");
+        assert_eq!(std::fs::read_to_string(&mixed).unwrap(), "baz\r\nbaz\n");
+        let ignored = get_test_dir().join("dist").join("index.js");
+        assert_eq!(std::fs::read_to_string(ignored).unwrap(), "foo");
+
+        // Failed files are reported and the others are still replaced
+        let results = replace_files(&get_test_dir(), "baz", "😀", &options, app.handle()).unwrap();
+        let failed: Vec<_> = results
+            .iter()
+            .filter(|r| r.error.is_some())
+            .map(|r| r.path.clone())
+            .collect();
+        assert_eq!(failed, vec![latin1]);
+        assert_eq!(std::fs::read_to_string(&main).unwrap(), "fn 😀() {}");
+    }
+}
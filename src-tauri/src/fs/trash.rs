@@ -0,0 +1,260 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+
+use super::write::write_atomic;
+
+pub const TRASH_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashEntry {
+    pub id: u64,
+    // Where the file or dir was deleted from
+    pub path: PathBuf,
+    pub deleted: SystemTime,
+}
+
+// Deleted files and dirs are moved to `<dir>/<id>/<name>` with the entry as
+// `<dir>/<id>.json`, so that deletes can be undone
+pub struct Trash {
+    dir: PathBuf,
+    last_id: Mutex<u64>,
+}
+
+impl Trash {
+    pub fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let trash = Self {
+            dir,
+            last_id: Mutex::new(0),
+        };
+        trash.prune(TRASH_MAX_AGE)?;
+        Ok(trash)
+    }
+
+    pub fn delete(&self, path: &Path) -> anyhow::Result<TrashEntry> {
+        let name = path
+            .file_name()
+            .ok_or(anyhow!("No file name (path={:?})", path))?;
+
+        let id = {
+            let mut last_id = self.last_id.lock().unwrap();
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+            *last_id = now.max(*last_id + 1);
+            *last_id
+        };
+
+        let entry = TrashEntry {
+            id,
+            path: path.to_path_buf(),
+            deleted: UNIX_EPOCH + Duration::from_millis(id),
+        };
+
+        debug!("Move to trash (path={:?}, id={})", path, id);
+        let entry_dir = self.dir.join(id.to_string());
+        let target = entry_dir.join(name);
+        fs::create_dir(&entry_dir)?;
+        // The entry is written first, so that moved files always have one
+        let result = write_atomic(&self.entry_file(id), |w| {
+            serde_json::to_writer(w, &entry).map_err(std::io::Error::other)
+        })
+        .and_then(|_| move_path(path, &target));
+
+        if let Err(e) = result {
+            // Entries of partly moved dirs are kept, nothing may get lost
+            if fs::symlink_metadata(&target).is_err() {
+                let _ = fs::remove_file(self.entry_file(id));
+                let _ = fs::remove_dir(&entry_dir);
+            }
+            return Err(e);
+        }
+
+        Ok(entry)
+    }
+
+    // Moves the entry back to its original path
+    pub fn restore(&self, id: u64) -> anyhow::Result<PathBuf> {
+        let entry = self.read_entry(id)?;
+        if entry.path.exists() || entry.path.is_symlink() {
+            return Err(anyhow!("Path already exists (path={:?})", entry.path));
+        }
+
+        let name = entry.path.file_name().ok_or(anyhow!("No file name"))?;
+        if let Some(parent) = entry.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        debug!("Restore from trash (path={:?}, id={})", entry.path, id);
+        let entry_dir = self.dir.join(id.to_string());
+        move_path(&entry_dir.join(name), &entry.path)?;
+        self.remove(id)?;
+        Ok(entry.path)
+    }
+
+    // Newest first, invalid entries are skipped
+    pub fn list(&self) -> anyhow::Result<Vec<TrashEntry>> {
+        let mut entries = Vec::new();
+        for file in fs::read_dir(&self.dir)? {
+            let file = file?.path();
+            let id = file
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_suffix(".json"))
+                .and_then(|id| id.parse().ok());
+
+            match id.map(|id| self.read_entry(id)) {
+                Some(Ok(entry)) => entries.push(entry),
+                Some(Err(e)) => error!("Invalid trash entry (path={:?}): {:?}", file, e),
+                None => {}
+            }
+        }
+
+        entries.sort_by_key(|e| std::cmp::Reverse(e.id));
+        Ok(entries)
+    }
+
+    fn prune(&self, max_age: Duration) -> anyhow::Result<()> {
+        for entry in self.list()? {
+            if entry.deleted.elapsed().unwrap_or_default() > max_age {
+                info!("Remove from trash (path={:?}, id={})", entry.path, entry.id);
+                if let Err(e) = self.remove(entry.id) {
+                    error!("Could not remove from trash (id={}): {:?}", entry.id, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, id: u64) -> anyhow::Result<()> {
+        let entry_dir = self.dir.join(id.to_string());
+        if entry_dir.exists() {
+            fs::remove_dir_all(entry_dir)?;
+        }
+        fs::remove_file(self.entry_file(id))?;
+        Ok(())
+    }
+
+    fn read_entry(&self, id: u64) -> anyhow::Result<TrashEntry> {
+        let file = fs::File::open(self.entry_file(id))
+            .map_err(|_| anyhow!("No trash entry (id={})", id))?;
+        let entry = serde_json::from_reader(file)?;
+        Ok(entry)
+    }
+
+    fn entry_file(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+}
+
+// Renames or, if the trash is on another device, copies and removes
+pub fn move_path(from: &Path, to: &Path) -> anyhow::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            if let Err(e) = copy_path(from, to) {
+                // The source is still complete
+                let _ = remove_path(to);
+                return Err(e);
+            }
+            remove_path(from)
+        }
+        result => Ok(result?),
+    }
+}
+
+// Copies files and dirs recursively, symlinks are copied as links
+pub fn copy_path(from: &Path, to: &Path) -> anyhow::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.is_symlink() {
+        let target = fs::read_link(from)?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, to)?;
+        #[cfg(windows)]
+        if from.is_dir() {
+            std::os::windows::fs::symlink_dir(target, to)?;
+        } else {
+            std::os::windows::fs::symlink_file(target, to)?;
+        }
+    } else if metadata.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+fn remove_path(path: &Path) -> anyhow::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serial_test::serial;
+
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+
+    use super::Trash;
+
+    #[test]
+    #[serial]
+    fn test_trash() {
+        create_test_workspace(true);
+        let trash_dir = tempfile::tempdir().unwrap();
+        let trash = Trash::new(trash_dir.path().to_path_buf()).unwrap();
+
+        let readme = get_test_dir().join("README.md");
+        std::fs::write(&readme, "readme").unwrap();
+        let src = get_test_dir().join("src");
+
+        let file_entry = trash.delete(&readme).unwrap();
+        let dir_entry = trash.delete(&src).unwrap();
+        assert!(!readme.exists());
+        assert!(!src.exists());
+        assert_eq!(
+            trash.list().unwrap(),
+            vec![dir_entry.clone(), file_entry.clone()]
+        );
+
+        assert_eq!(trash.restore(file_entry.id).unwrap(), readme);
+        assert_eq!(std::fs::read_to_string(&readme).unwrap(), "readme");
+        assert!(trash.restore(file_entry.id).is_err());
+
+        // Restoring doesn't overwrite new files
+        std::fs::create_dir(&src).unwrap();
+        assert!(trash.restore(dir_entry.id).is_err());
+        std::fs::remove_dir(&src).unwrap();
+        trash.restore(dir_entry.id).unwrap();
+        assert!(src.join("index.ts").exists());
+        assert!(trash.list().unwrap().is_empty());
+
+        // Failed deletes leave nothing behind and invalid entries are skipped
+        assert!(trash.delete(&get_test_dir().join("missing")).is_err());
+        assert_eq!(std::fs::read_dir(trash_dir.path()).unwrap().count(), 0);
+        std::fs::write(trash_dir.path().join("1.json"), "{").unwrap();
+        let trash = Trash::new(trash_dir.path().to_path_buf()).unwrap();
+        assert!(trash.list().unwrap().is_empty());
+        std::fs::remove_file(trash_dir.path().join("1.json")).unwrap();
+
+        trash.delete(&readme).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        trash.prune(Duration::ZERO).unwrap();
+        assert!(trash.list().unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(trash_dir.path()).unwrap().count(), 0);
+    }
+}
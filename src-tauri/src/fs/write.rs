@@ -0,0 +1,98 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::anyhow;
+use tempfile::NamedTempFile;
+
+// Writes into a temp file in the same dir and renames it over the target, so
+// a crash mid-write never leaves a truncated file behind
+pub fn write_atomic<F>(path: &Path, write: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    let err = |e: io::Error| anyhow!("Could not write file (path={:?}): {}", path, e);
+
+    // Replace the target of a symlink and keep the link itself
+    let target = if path.is_symlink() {
+        fs::canonicalize(path).map_err(err)?
+    } else {
+        path.to_path_buf()
+    };
+
+    let dir = target
+        .parent()
+        .ok_or(anyhow!("No parent dir (path={:?})", path))?;
+    let permissions = fs::metadata(&target).ok().map(|m| m.permissions());
+
+    let mut file = NamedTempFile::new_in(dir).map_err(err)?;
+    let mut writer = BufWriter::new(file.as_file_mut());
+    write(&mut writer).map_err(err)?;
+    writer.flush().map_err(err)?;
+    drop(writer);
+
+    if let Some(permissions) = permissions {
+        fs::set_permissions(file.path(), permissions).map_err(err)?;
+    }
+
+    file.as_file().sync_all().map_err(err)?;
+    file.persist(&target).map_err(|e| err(e.error))?;
+
+    // Persist the rename itself
+    #[cfg(unix)]
+    File::open(dir).and_then(|d| d.sync_all()).map_err(err)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use super::*;
+
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+
+    #[test]
+    #[serial]
+    fn test_write_atomic() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("README.md");
+
+        write_atomic(&path, |w| w.write_all(b"test")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "test");
+
+        // A failing write keeps the old content and leaves no temp file
+        let result = write_atomic(&path, |w| {
+            w.write_all(b"partial")?;
+            Err(io::Error::other("failed"))
+        });
+        assert!(result.unwrap_err().to_string().contains("README.md"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "test");
+        assert_eq!(fs::read_dir(get_test_dir()).unwrap().count(), 3);
+
+        let missing = get_test_dir().join("missing").join("file.txt");
+        let result = write_atomic(&missing, |w| w.write_all(b"test"));
+        assert!(result.unwrap_err().to_string().contains("missing"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[serial]
+    fn test_write_atomic_unix() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        create_test_workspace(true);
+        let path = get_test_dir().join("src").join("main.rs");
+        let link = get_test_dir().join("link.rs");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+        symlink(&path, &link).unwrap();
+
+        write_atomic(&link, |w| w.write_all(b"fn main() {}")).unwrap();
+
+        assert!(link.is_symlink());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fn main() {}");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use serde::Serialize;
+
+use crate::editor::editor_state::{hash_content, Document};
+
+use super::cli;
+use super::repo::{git_dir, head_commit};
+
+pub const NOT_COMMITTED: &str = "Not committed yet";
+
+// git blame uses this id for lines that differ from HEAD
+const UNCOMMITTED_ID: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlameLine {
+    pub commit_id: String,
+    pub author: String,
+    pub date: Option<SystemTime>,
+    pub summary: String,
+    // False for lines that are changed in the unsaved text or on disk
+    pub committed: bool,
+}
+
+struct CacheEntry {
+    head: Option<String>,
+    content_hash: u64,
+    lines: Arc<Vec<BlameLine>>,
+}
+
+// Caches the blame of each document until HEAD or the text changes
+pub struct GitBlame {
+    cache: Mutex<HashMap<PathBuf, CacheEntry>>,
+}
+
+impl GitBlame {
+    pub fn new() -> Self {
+        Self {
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn blame(&self, doc: &Document) -> anyhow::Result<Arc<Vec<BlameLine>>> {
+        if doc.large_file {
+            return Ok(Arc::new(Vec::new()));
+        }
+
+        let worktree = doc
+            .worktree_path
+            .as_ref()
+            .filter(|w| git_dir(w).is_some())
+            .ok_or(anyhow!("Not in a git worktree (path={:?})", doc.path))?;
+        let head = head_commit(worktree);
+        let content_hash = hash_content(&doc.text, &doc.format);
+
+        if let Some(entry) = self.cache.lock().unwrap().get(&doc.path) {
+            if entry.head == head && entry.content_hash == content_hash {
+                return Ok(entry.lines.clone());
+            }
+        }
+
+        // Untracked files and repos without commits can't be blamed
+        let path = doc.get_relative_path().to_string_lossy().replace('\\', "/");
+        let tracked = head.is_some()
+            && cli::run(worktree, &["cat-file", "-e", &format!("HEAD:{}", path)])
+                .await
+                .is_ok();
+
+        let lines = if tracked {
+            // Blame the unsaved text instead of the file on disk
+            let mut contents = Vec::new();
+            doc.format.write(&doc.text, &mut contents)?;
+            let args = ["blame", "--porcelain", "--contents", "-", "--", &path];
+            let output = cli::run_with_input(worktree, &args, Some(&contents)).await?;
+            parse_blame(&String::from_utf8_lossy(&output))?
+        } else {
+            let line = BlameLine {
+                commit_id: UNCOMMITTED_ID.to_string(),
+                summary: NOT_COMMITTED.to_string(),
+                ..Default::default()
+            };
+            vec![line; count_lines(doc)]
+        };
+
+        let lines = Arc::new(lines);
+        self.cache.lock().unwrap().insert(
+            doc.path.clone(),
+            CacheEntry {
+                head,
+                content_hash,
+                lines: lines.clone(),
+            },
+        );
+        Ok(lines)
+    }
+}
+
+// Lines of the file as written, counted like git does: a final line break
+// does not start another line
+fn count_lines(doc: &Document) -> usize {
+    let text = &doc.text;
+    let final_newline = doc.format.final_newline;
+    let line_breaks = text.len_lines() - 1 + usize::from(final_newline);
+    let last_char = text.len_chars().checked_sub(1).map(|i| text.char(i));
+    let ends_with_break = final_newline || last_char.is_none_or(|c| c == '\n');
+    line_breaks + usize::from(!ends_with_break)
+}
+
+// Parses `git blame --porcelain`. Commit details are only printed for the
+// first line of each commit.
+fn parse_blame(output: &str) -> anyhow::Result<Vec<BlameLine>> {
+    let mut commits: HashMap<String, BlameLine> = HashMap::new();
+    let mut lines: Vec<BlameLine> = Vec::new();
+    let mut current: Option<(String, usize)> = None;
+
+    for line in output.lines() {
+        let invalid = || anyhow!("Invalid git blame line: {}", line);
+
+        // The content of the line ends each entry
+        if line.starts_with('\t') {
+            let (commit_id, final_line) = current.take().ok_or_else(invalid)?;
+            let mut blame_line = commits.get(&commit_id).cloned().ok_or_else(invalid)?;
+            if !blame_line.committed {
+                blame_line.summary = NOT_COMMITTED.to_string();
+            }
+
+            let index = final_line.checked_sub(1).ok_or_else(invalid)?;
+            if lines.len() <= index {
+                lines.resize(index + 1, BlameLine::default());
+            }
+            lines[index] = blame_line;
+            continue;
+        }
+
+        let Some((commit_id, _)) = current.as_ref() else {
+            let mut parts = line.split(' ');
+            let commit_id = parts.next().ok_or_else(invalid)?.to_string();
+            let final_line = parts.nth(1).ok_or_else(invalid)?.parse()?;
+            commits
+                .entry(commit_id.clone())
+                .or_insert_with(|| BlameLine {
+                    commit_id: commit_id.clone(),
+                    committed: commit_id != UNCOMMITTED_ID,
+                    ..Default::default()
+                });
+            current = Some((commit_id, final_line));
+            continue;
+        };
+
+        let commit = commits.get_mut(commit_id).ok_or_else(invalid)?;
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "author" => commit.author = value.to_string(),
+            "author-time" => {
+                commit.date = Some(UNIX_EPOCH + Duration::from_secs(value.parse()?));
+            }
+            "summary" => commit.summary = value.to_string(),
+            _ => {}
+        }
+    }
+
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serial_test::serial;
+
+    use crate::editor::editor_state::{Change, EditorState};
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+    use crate::git::testutil::{git, init_repo};
+
+    use super::{GitBlame, NOT_COMMITTED};
+
+    #[tokio::test]
+    #[serial]
+    async fn test_blame() {
+        create_test_workspace(false);
+        let dir = get_test_dir();
+        let readme = dir.join("README.md");
+        std::fs::write(&readme, "a\nb\nc\n").unwrap();
+        init_repo(&dir);
+
+        let editor_state = EditorState::default();
+        let git_blame = GitBlame::new();
+        let doc = editor_state.get_document(&readme).await.unwrap();
+        let lines = git_blame.blame(&doc).await.unwrap();
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|l| !l.committed));
+
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", "init"]);
+        git(&dir, &["commit", "-q", "--allow-empty", "-m", "empty"]);
+
+        // Change the second line without saving
+        let change = Change {
+            from: 2,
+            to: 3,
+            text: "B".to_string(),
+        };
+        let doc = editor_state
+            .apply_changes(&readme, 0, &[change])
+            .unwrap()
+            .doc;
+        let lines = git_blame.blame(&doc).await.unwrap();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].committed);
+        assert_eq!(lines[0].author, "test");
+        assert_eq!(lines[0].summary, "init");
+        assert_eq!(lines[0].commit_id.len(), 40);
+        assert!(lines[0].date.is_some());
+        assert_eq!(lines[2].commit_id, lines[0].commit_id);
+        assert!(!lines[1].committed);
+        assert_eq!(lines[1].summary, NOT_COMMITTED);
+
+        // Cached until the text or HEAD changes
+        assert!(Arc::ptr_eq(&lines, &git_blame.blame(&doc).await.unwrap()));
+        git(&dir, &["commit", "-q", "--allow-empty", "-m", "empty"]);
+        assert!(!Arc::ptr_eq(&lines, &git_blame.blame(&doc).await.unwrap()));
+
+        // Untracked files, the trailing empty line counts like in git
+        let untracked = dir.join("new.md");
+        std::fs::write(&untracked, "x\n\n").unwrap();
+        let doc = editor_state.get_document(&untracked).await.unwrap();
+        let lines = git_blame.blame(&doc).await.unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|l| !l.committed));
+    }
+}
@@ -0,0 +1,56 @@
+use std::path::Path;
+use std::process::Stdio;
+
+use anyhow::anyhow;
+use futures::AsyncWriteExt;
+use tracing::debug;
+
+// Runs git in `dir` and returns its stdout. Fails if git exits with an error.
+pub async fn run(dir: &Path, args: &[&str]) -> anyhow::Result<Vec<u8>> {
+    run_with_input(dir, args, None).await
+}
+
+// Same as `run` but writes `input` to stdin, e.g. for `--contents -`
+pub async fn run_with_input(
+    dir: &Path,
+    args: &[&str],
+    input: Option<&[u8]>,
+) -> anyhow::Result<Vec<u8>> {
+    debug!("Run git (dir={:?}, args={:?})", dir, args);
+    let stdin = if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+
+    let mut child = async_process::Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("Could not run git: {}", e))?;
+
+    // Write while git is reading, the stdin pipe is closed when dropped
+    let stdin = child.stdin.take();
+    let write = async move {
+        if let (Some(mut stdin), Some(input)) = (stdin, input) {
+            stdin.write_all(input).await?;
+        }
+        anyhow::Ok(())
+    };
+    let (written, output) = futures::join!(write, child.output());
+    let output = output?;
+    written?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(output.stdout)
+}
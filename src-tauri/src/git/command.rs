@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use serde::Serialize;
+use tauri::{path::SafePathBuf, AppHandle, Emitter, Manager, Runtime};
+use tracing::debug;
+
+use crate::editor::command_editor_state::notify_change;
+use crate::editor::editor_state::{Change, Document, EditorState};
+
+use super::blame::{BlameLine, GitBlame};
+use super::diff::{revert_change, GitDiff, Hunk};
+use super::status::{status, GitStatus, StatusRefresh, GIT_STATUS_CHANGED, REFRESH_DELAY};
+
+// The change is against the text before the revert, for the frontend to apply
+// to its editor
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevertedHunk {
+    pub document: Document,
+    pub change: Change,
+}
+
+#[tauri::command]
+pub async fn git_status(worktree: SafePathBuf) -> tauri::Result<GitStatus> {
+    let status = status(worktree.as_ref()).await?;
+    Ok(status)
+}
+
+// Hunks of the unsaved text compared to HEAD
+#[tauri::command]
+pub async fn git_diff_hunks<R: Runtime>(
+    path: SafePathBuf,
+    app_handle: AppHandle<R>,
+) -> tauri::Result<Vec<Hunk>> {
+    let editor_state = app_handle.state::<EditorState>();
+    let git_diff = app_handle.state::<GitDiff>();
+    let doc = editor_state.get_document(path.as_ref()).await?;
+    let hunks = git_diff.hunks(&doc).await?;
+    Ok(hunks.to_vec())
+}
+
+// Restores the HEAD version of the hunk at `line` as an unsaved edit
+#[tauri::command]
+pub async fn git_revert_hunk<R: Runtime>(
+    path: SafePathBuf,
+    line: usize,
+    app_handle: AppHandle<R>,
+) -> tauri::Result<RevertedHunk> {
+    let editor_state = app_handle.state::<EditorState>();
+    let git_diff = app_handle.state::<GitDiff>();
+    let doc = editor_state.get_document(path.as_ref()).await?;
+
+    let hunks = git_diff.hunks(&doc).await?;
+    let hunk = hunks
+        .iter()
+        .find(|h| h.contains_line(line))
+        .ok_or(anyhow!("No hunk at line {}", line))?;
+
+    let change = revert_change(hunk, &doc.text);
+    let tx =
+        editor_state.apply_changes(path.as_ref(), doc.version, std::slice::from_ref(&change))?;
+    notify_change(&app_handle, &tx).await;
+    Ok(RevertedHunk {
+        document: tx.doc,
+        change,
+    })
+}
+
+// Blames the unsaved text, changed lines are marked as not committed
+#[tauri::command]
+pub async fn git_blame<R: Runtime>(
+    path: SafePathBuf,
+    app_handle: AppHandle<R>,
+) -> tauri::Result<Vec<BlameLine>> {
+    let editor_state = app_handle.state::<EditorState>();
+    let git_blame = app_handle.state::<GitBlame>();
+    let doc = editor_state.get_document(path.as_ref()).await?;
+    let lines = git_blame.blame(&doc).await?;
+    Ok(lines.to_vec())
+}
+
+// Emits the new status of the worktree in the background, e.g. after a save.
+// Refreshes of the same worktree are coalesced.
+pub fn refresh_status<R: Runtime>(app_handle: &AppHandle<R>, worktree: PathBuf) {
+    if !app_handle.state::<StatusRefresh>().request(&worktree) {
+        return;
+    }
+
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let refresh = app_handle.state::<StatusRefresh>();
+        loop {
+            tokio::time::sleep(REFRESH_DELAY).await;
+            refresh.start(&worktree);
+            match status(&worktree).await {
+                Ok(status) => {
+                    let _ = app_handle.emit(GIT_STATUS_CHANGED, &status);
+                }
+                Err(e) => debug!("Could not refresh git status: {:?}", e),
+            }
+            if !refresh.finish(&worktree) {
+                break;
+            }
+        }
+    });
+}
@@ -0,0 +1,457 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ropey::Rope;
+use serde::Serialize;
+use similar::{DiffOp, TextDiff};
+use tracing::debug;
+
+use crate::editor::editor_state::{hash_content, Change, Document};
+use crate::editor::file_format::FileFormat;
+
+use super::cli;
+use super::repo::head_commit;
+
+const DIFF_TIMEOUT: Duration = Duration::from_secs(1);
+// Files whose HEAD version is kept, the least recently used one is dropped
+const MAX_BASES: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HunkKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+// Zero based, exclusive line ranges. Deleted hunks have an empty range at the
+// line that follows the deleted lines.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hunk {
+    pub kind: HunkKind,
+    pub from_line: usize,
+    pub to_line: usize,
+    pub old_from_line: usize,
+    pub old_to_line: usize,
+    pub old_text: String,
+}
+
+impl Hunk {
+    // Deleted hunks are hit by the line after the deletion
+    pub fn contains_line(&self, line: usize) -> bool {
+        line >= self.from_line && line < self.to_line.max(self.from_line + 1)
+    }
+}
+
+struct Base {
+    head: String,
+    // None if the file is not in HEAD
+    text: Option<Arc<String>>,
+    // Last diffed text with its content hash and hunks
+    hunks: Option<(u64, Arc<String>, Arc<Vec<Hunk>>)>,
+    used: Instant,
+}
+
+// Caches the HEAD version of files and the hunks of their last text. Edits
+// only diff the lines between the unchanged start and end of the text again,
+// see `update_hunks`. A cached version is dropped once HEAD moves.
+pub struct GitDiff {
+    bases: Mutex<HashMap<PathBuf, Base>>,
+}
+
+impl GitDiff {
+    pub fn new() -> Self {
+        Self {
+            bases: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Diffs the unsaved text of the document against HEAD
+    pub async fn hunks(&self, doc: &Document) -> anyhow::Result<Arc<Vec<Hunk>>> {
+        if doc.large_file {
+            return Ok(Arc::new(Vec::new()));
+        }
+
+        let Some(base) = self.base(doc).await? else {
+            return Ok(Arc::new(Vec::new()));
+        };
+
+        let content_hash = hash_content(&doc.text, &doc.format);
+        let mut bases = self.bases.lock().unwrap();
+        let last = bases.get(&doc.path).and_then(|b| b.hunks.clone());
+        if let Some((hash, _, hunks)) = &last {
+            if *hash == content_hash {
+                return Ok(hunks.clone());
+            }
+        }
+
+        let text = Arc::new(doc.text.to_string());
+        let hunks = Arc::new(match &last {
+            Some((_, last_text, hunks)) => update_hunks(&base, last_text, hunks, &text),
+            None => compute_hunks(&base, &text),
+        });
+        if let Some(entry) = bases.get_mut(&doc.path) {
+            entry.hunks = Some((content_hash, text, hunks.clone()));
+        }
+        Ok(hunks)
+    }
+
+    async fn base(&self, doc: &Document) -> anyhow::Result<Option<Arc<String>>> {
+        let Some(worktree) = doc.worktree_path.as_ref() else {
+            return Ok(None);
+        };
+        let Some(head) = head_commit(worktree) else {
+            return Ok(None);
+        };
+
+        if let Some(base) = self.bases.lock().unwrap().get_mut(&doc.path) {
+            if base.head == head {
+                base.used = Instant::now();
+                return Ok(base.text.clone());
+            }
+        }
+
+        let path = doc.path.canonicalize()?;
+        let relative_path = path.strip_prefix(worktree)?;
+        let spec = format!(
+            "{}:{}",
+            head,
+            relative_path.to_string_lossy().replace('\\', "/")
+        );
+
+        let text = match cli::run(worktree, &["show", &spec]).await {
+            Ok(bytes) => FileFormat::decode(&bytes, Some(doc.format.encoding))
+                .ok()
+                .map(|(_, text)| Arc::new(text)),
+            Err(e) => {
+                debug!("No HEAD version (path={:?}): {:?}", doc.path, e);
+                None
+            }
+        };
+
+        let mut bases = self.bases.lock().unwrap();
+        bases.insert(
+            doc.path.clone(),
+            Base {
+                head,
+                text: text.clone(),
+                hunks: None,
+                used: Instant::now(),
+            },
+        );
+        if bases.len() > MAX_BASES {
+            let oldest = bases
+                .iter()
+                .min_by_key(|(_, base)| base.used)
+                .map(|(path, _)| path.clone());
+            if let Some(path) = oldest {
+                bases.remove(&path);
+            }
+        }
+        Ok(text)
+    }
+}
+
+// Diffs all lines of both texts
+pub fn compute_hunks(old: &str, new: &str) -> Vec<Hunk> {
+    diff_lines(&split_lines(old), &split_lines(new), 0, 0)
+}
+
+// Diffs only the lines that differ between `last`, the text `hunks` belong
+// to, and `new`. The diffed lines are widened to the hunks they touch, so the
+// hunks before them are kept and the ones after them are moved.
+pub fn update_hunks(old: &str, last: &str, hunks: &[Hunk], new: &str) -> Vec<Hunk> {
+    let (from_line, last_to_line, new_to_line) = changed_lines(last, new);
+    let (mut from, mut to) = (from_line, last_to_line);
+    while let Some(hunk) = hunks
+        .iter()
+        .find(|h| h.from_line <= to && h.to_line >= from && (h.from_line < from || h.to_line > to))
+    {
+        from = from.min(hunk.from_line);
+        to = to.max(hunk.to_line);
+    }
+
+    // Lines outside of hunks are equal, only shifted by the hunks before them
+    let old_line = |line: usize, before: &dyn Fn(&Hunk) -> bool| {
+        hunks.iter().filter(|h| before(h)).fold(line, |line, h| {
+            line + (h.old_to_line - h.old_from_line) - (h.to_line - h.from_line)
+        })
+    };
+    let old_from = old_line(from, &|h| h.to_line < from);
+    let old_to = old_line(to, &|h| h.from_line <= to);
+    let new_from = from;
+    let new_to = to + new_to_line - last_to_line;
+
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let mut result: Vec<Hunk> = hunks.iter().filter(|h| h.to_line < from).cloned().collect();
+    result.extend(diff_lines(
+        &old_lines[old_from..old_to],
+        &new_lines[new_from..new_to],
+        old_from,
+        new_from,
+    ));
+    result.extend(hunks.iter().filter(|h| h.from_line > to).map(|h| Hunk {
+        from_line: h.from_line + new_to_line - last_to_line,
+        to_line: h.to_line + new_to_line - last_to_line,
+        ..h.clone()
+    }));
+    result
+}
+
+// Returns the first line that differs and the end of the differing lines in
+// both texts, both ends are followed by equal lines only
+fn changed_lines(last: &str, new: &str) -> (usize, usize, usize) {
+    let prefix = last
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let prefix = last[..prefix].rfind('\n').map_or(0, |i| i + 1);
+
+    let max_suffix = last.len().min(new.len()) - prefix;
+    let suffix = last
+        .bytes()
+        .rev()
+        .zip(new.bytes().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    // The equal end has to start at a line
+    let mut start = last.len() - suffix;
+    if start > 0 && last.as_bytes()[start - 1] != b'\n' {
+        start = last.as_bytes()[start..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(last.len(), |i| start + i + 1);
+    }
+    let suffix = last.len() - start;
+
+    let from_line = last[..prefix].matches('\n').count();
+    (
+        from_line,
+        from_line + split_lines(&last[prefix..last.len() - suffix]).len(),
+        from_line + split_lines(&new[prefix..new.len() - suffix]).len(),
+    )
+}
+
+// Lines with their line breaks, like `TextDiff::diff_lines` splits them
+fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+// Merges adjacent inserts and deletes of a line diff into hunks. The offsets
+// are added to the line numbers of the slices.
+fn diff_lines(old: &[&str], new: &[&str], old_offset: usize, new_offset: usize) -> Vec<Hunk> {
+    let diff = TextDiff::configure()
+        .timeout(DIFF_TIMEOUT)
+        .diff_slices(old, new);
+
+    let mut hunks = Vec::new();
+    let mut current: Option<(Range<usize>, Range<usize>)> = None;
+    // Ranges follow from the lengths, the index of a delete after a replace
+    // can be off in `similar`
+    let (mut old_pos, mut new_pos) = (0, 0);
+    let mut push = |current: Option<(Range<usize>, Range<usize>)>| {
+        if let Some((o, n)) = current {
+            let mut hunk = to_hunk(old, o, n);
+            hunk.from_line += new_offset;
+            hunk.to_line += new_offset;
+            hunk.old_from_line += old_offset;
+            hunk.old_to_line += old_offset;
+            hunks.push(hunk);
+        }
+    };
+    for op in diff.ops() {
+        let old_range = old_pos..old_pos + op.old_range().len();
+        let new_range = new_pos..new_pos + op.new_range().len();
+        (old_pos, new_pos) = (old_range.end, new_range.end);
+        if let DiffOp::Equal { .. } = op {
+            push(current.take());
+            continue;
+        }
+
+        current = Some(match current {
+            Some((o, n)) => (o.start..old_range.end, n.start..new_range.end),
+            None => (old_range, new_range),
+        });
+    }
+    push(current);
+    hunks
+}
+
+// Replaces the lines of the hunk with the HEAD version
+pub fn revert_change(hunk: &Hunk, text: &Rope) -> Change {
+    let offset = |line: usize| {
+        let line = line.min(text.len_lines());
+        text.char_to_utf16_cu(text.line_to_char(line))
+    };
+
+    Change {
+        from: offset(hunk.from_line),
+        to: offset(hunk.to_line),
+        text: hunk.old_text.clone(),
+    }
+}
+
+fn to_hunk(old_lines: &[&str], old: Range<usize>, new: Range<usize>) -> Hunk {
+    let kind = if old.is_empty() {
+        HunkKind::Added
+    } else if new.is_empty() {
+        HunkKind::Deleted
+    } else {
+        HunkKind::Modified
+    };
+
+    Hunk {
+        kind,
+        from_line: new.start,
+        to_line: new.end,
+        old_from_line: old.start,
+        old_to_line: old.end,
+        old_text: old_lines[old.clone()].concat(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ropey::Rope;
+    use serial_test::serial;
+
+    use crate::editor::editor_state::{Change, EditorState};
+    use crate::editor::search::apply_replacements;
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+    use crate::git::testutil::{git, init_repo};
+
+    use super::{compute_hunks, revert_change, update_hunks, GitDiff, Hunk, HunkKind};
+
+    #[test]
+    fn test_compute_hunks() {
+        let old = "a\nb\nc\nd\ne\n";
+        let new = "a\nB\nc\nnew\nd\n";
+        let hunks = compute_hunks(old, new);
+
+        let ranges: Vec<_> = hunks
+            .iter()
+            .map(|h| (h.kind, h.from_line, h.to_line))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (HunkKind::Modified, 1, 2),
+                (HunkKind::Added, 3, 4),
+                (HunkKind::Deleted, 5, 5),
+            ]
+        );
+        assert_eq!(hunks[0].old_text, "b\n");
+        assert_eq!(hunks[2].old_text, "e\n");
+        assert!(hunks[2].contains_line(5));
+        assert!(!hunks[1].contains_line(4));
+
+        // Reverting all hunks from the end restores the old text
+        assert_eq!(revert_all(&hunks, new), old);
+
+        assert!(compute_hunks(old, old).is_empty());
+    }
+
+    fn revert_all(hunks: &[Hunk], text: &str) -> String {
+        let mut rope = Rope::from_str(text);
+        let changes: Vec<_> = hunks.iter().map(|h| revert_change(h, &rope)).collect();
+        apply_replacements(&mut rope, &changes);
+        rope.to_string()
+    }
+
+    #[test]
+    fn test_update_hunks() {
+        let old = "a\nb\nc\nd\ne\n";
+        let last = "a\nB\nc\nd\ne\n";
+        let hunks = compute_hunks(old, last);
+
+        // An edit after the hunk keeps it and only diffs the edited line
+        let new = "a\nB\nc\nd\nE\n";
+        assert_eq!(
+            update_hunks(old, last, &hunks, new),
+            compute_hunks(old, new)
+        );
+        // Inserted lines move the hunks after them
+        let new = "x\ny\na\nB\nc\nd\ne\n";
+        let updated = update_hunks(old, last, &hunks, new);
+        assert_eq!(updated, compute_hunks(old, new));
+        assert_eq!((updated[1].from_line, updated[1].to_line), (3, 4));
+        // Reverting the edit removes the hunk again
+        assert!(update_hunks(old, last, &hunks, old).is_empty());
+
+        // Random edits of random lines keep the hunks valid
+        let mut seed = 7u64;
+        let mut next = |n: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % n
+        };
+        let old: String = (0..200).map(|i| format!("line {}\n", i % 13)).collect();
+        let mut text = old.clone();
+        let mut hunks = Vec::new();
+        for _ in 0..300 {
+            let lines: Vec<&str> = text.split_inclusive('\n').collect();
+            let from = next(lines.len() + 1);
+            let to = (from + next(3)).min(lines.len());
+            let inserted: String = (0..next(3))
+                .map(|_| format!("line {}\n", next(15)))
+                .collect();
+            let new = [lines[..from].concat(), inserted, lines[to..].concat()].concat();
+
+            hunks = update_hunks(&old, &text, &hunks, &new);
+            assert_eq!(revert_all(&hunks, &new), old);
+            assert!(hunks.windows(2).all(|w| w[0].to_line < w[1].from_line));
+            text = new;
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_hunks() {
+        create_test_workspace(false);
+        let dir = get_test_dir();
+        let readme = dir.join("README.md");
+        std::fs::write(&readme, "a\nb\n").unwrap();
+        init_repo(&dir);
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", "init"]);
+
+        let editor_state = EditorState::default();
+        let git_diff = GitDiff::new();
+        editor_state.get_document(&readme).await.unwrap();
+        let change = Change {
+            from: 0,
+            to: 2,
+            text: "".to_string(),
+        };
+        let doc = editor_state
+            .apply_changes(&readme, 0, &[change])
+            .unwrap()
+            .doc;
+        let hunks = git_diff.hunks(&doc).await.unwrap();
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].kind, HunkKind::Deleted);
+        // The same text is not diffed again
+        assert!(Arc::ptr_eq(&hunks, &git_diff.hunks(&doc).await.unwrap()));
+
+        // The HEAD version is loaded again after a commit
+        std::fs::write(&readme, "b\n").unwrap();
+        git(&dir, &["commit", "-qam", "delete"]);
+        assert!(git_diff.hunks(&doc).await.unwrap().is_empty());
+
+        // Untracked files have no hunks
+        let new = dir.join("new.md");
+        std::fs::write(&new, "new").unwrap();
+        let doc = editor_state.get_document(&new).await.unwrap();
+        assert!(git_diff.hunks(&doc).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,8 @@
+pub mod blame;
+pub mod cli;
+pub mod command;
+pub mod diff;
+pub mod repo;
+pub mod status;
+#[cfg(test)]
+pub mod testutil;
@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The git dir of a worktree. Linked worktrees and submodules have a `.git`
+// file with `gitdir: <path>` instead of a dir.
+pub fn git_dir(worktree: &Path) -> Option<PathBuf> {
+    let dot_git = worktree.join(".git");
+    if dot_git.is_dir() {
+        return dot_git.join("config").exists().then_some(dot_git);
+    }
+
+    let contents = fs::read_to_string(&dot_git).ok()?;
+    let target = contents.trim().strip_prefix("gitdir:")?.trim();
+    // Relative to the worktree, joining keeps absolute paths
+    let git_dir = worktree.join(target);
+    git_dir.join("HEAD").exists().then_some(git_dir)
+}
+
+// Linked worktrees share refs and objects with the main repo
+fn common_dir(git_dir: &Path) -> PathBuf {
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(dir) => git_dir.join(dir.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+// Resolves HEAD to a commit id by reading the repository files, which is much
+// cheaper than running git. None for repos without commits.
+pub fn head_commit(worktree: &Path) -> Option<String> {
+    let git_dir = git_dir(worktree)?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+
+    let Some(reference) = head.strip_prefix("ref: ") else {
+        // Detached HEAD
+        return Some(head.to_string());
+    };
+
+    let common_dir = common_dir(&git_dir);
+    for dir in [&git_dir, &common_dir] {
+        if let Ok(oid) = fs::read_to_string(dir.join(reference)) {
+            return Some(oid.trim().to_string());
+        }
+    }
+
+    let packed_refs = fs::read_to_string(common_dir.join("packed-refs")).ok()?;
+    packed_refs.lines().find_map(|line| {
+        let (oid, name) = line.split_once(' ')?;
+        (name == reference).then(|| oid.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+    use crate::git::testutil::{git, init_repo};
+
+    use super::{git_dir, head_commit};
+
+    #[test]
+    #[serial]
+    fn test_head_commit() {
+        create_test_workspace(false);
+        let dir = get_test_dir();
+        init_repo(&dir);
+        assert_eq!(head_commit(&dir), None);
+
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", "init"]);
+        let oid = std::process::Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .current_dir(&dir)
+            .output()
+            .unwrap()
+            .stdout;
+        let oid = String::from_utf8(oid).unwrap().trim().to_string();
+        assert_eq!(head_commit(&dir).as_ref(), Some(&oid));
+
+        git(&dir, &["pack-refs", "--all"]);
+        assert_eq!(head_commit(&dir).as_ref(), Some(&oid));
+
+        // Linked worktrees have a .git file and their own HEAD
+        let linked = dir.join("linked");
+        git(&dir, &["worktree", "add", "-q", "-b", "feature", "linked"]);
+        assert!(linked.join(".git").is_file());
+        assert_eq!(
+            git_dir(&linked),
+            Some(dir.join(".git").join("worktrees").join("linked"))
+        );
+        assert_eq!(head_commit(&linked).as_ref(), Some(&oid));
+
+        git(&dir, &["checkout", "-q", "--detach"]);
+        assert_eq!(head_commit(&dir), Some(oid));
+        assert_eq!(git_dir(&dir.join("src")), None);
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde::Serialize;
+
+use super::cli;
+
+pub const GIT_STATUS_CHANGED: &str = "git-status-changed";
+// Refreshes requested within this time share one `git status` run
+pub const REFRESH_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FileStatus {
+    Modified,
+    Added,
+    Deleted,
+    Renamed,
+    Untracked,
+    Ignored,
+    Conflicted,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileStatusEntry {
+    pub path: PathBuf,
+    pub status: FileStatus,
+    // Whether the change is in the index
+    pub staged: bool,
+    // Source of a rename or copy
+    pub orig_path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GitStatus {
+    pub worktree: PathBuf,
+    // None if HEAD is detached
+    pub branch: Option<String>,
+    pub upstream: Option<String>,
+    pub ahead: usize,
+    pub behind: usize,
+    pub files: Vec<FileStatusEntry>,
+}
+
+// Coalesces status refreshes, e.g. on autosave. At most one refresh runs per
+// worktree, requests during a run cause one more run after it.
+#[derive(Default)]
+pub struct StatusRefresh {
+    // Worktrees with a running refresh and whether another one is requested
+    running: Mutex<HashMap<PathBuf, bool>>,
+}
+
+impl StatusRefresh {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns true if the caller has to start the refresh
+    pub fn request(&self, worktree: &Path) -> bool {
+        let mut running = self.running.lock().unwrap();
+        match running.get_mut(worktree) {
+            Some(pending) => {
+                *pending = true;
+                false
+            }
+            None => {
+                running.insert(worktree.to_path_buf(), false);
+                true
+            }
+        }
+    }
+
+    // Called before `git status` runs, it covers all earlier requests
+    pub fn start(&self, worktree: &Path) {
+        if let Some(pending) = self.running.lock().unwrap().get_mut(worktree) {
+            *pending = false;
+        }
+    }
+
+    // Returns true if the refresh has to run again
+    pub fn finish(&self, worktree: &Path) -> bool {
+        let mut running = self.running.lock().unwrap();
+        if running.get(worktree) == Some(&true) {
+            running.insert(worktree.to_path_buf(), false);
+            return true;
+        }
+        running.remove(worktree);
+        false
+    }
+}
+
+pub async fn status(worktree: &Path) -> anyhow::Result<GitStatus> {
+    let output = cli::run(
+        worktree,
+        &[
+            "status",
+            "--porcelain=v2",
+            "--branch",
+            "-z",
+            "--untracked-files=all",
+            // Ignored dirs are listed once instead of every file in them
+            "--ignored=matching",
+        ],
+    )
+    .await?;
+
+    parse_status(worktree, &String::from_utf8_lossy(&output))
+}
+
+// Parses `git status --porcelain=v2 --branch -z`. Paths are relative to the
+// dir git ran in.
+fn parse_status(worktree: &Path, output: &str) -> anyhow::Result<GitStatus> {
+    let mut status = GitStatus {
+        worktree: worktree.to_path_buf(),
+        ..Default::default()
+    };
+
+    let mut records = output.split('\0').filter(|r| !r.is_empty());
+    while let Some(record) = records.next() {
+        let invalid = || anyhow!("Invalid git status line: {}", record);
+        let (kind, rest) = record.split_once(' ').ok_or_else(invalid)?;
+
+        match kind {
+            "#" => {
+                let (key, value) = rest.split_once(' ').ok_or_else(invalid)?;
+                match key {
+                    "branch.head" if value != "(detached)" => {
+                        status.branch = Some(value.to_string())
+                    }
+                    "branch.upstream" => status.upstream = Some(value.to_string()),
+                    "branch.ab" => {
+                        let (ahead, behind) = value.split_once(' ').ok_or_else(invalid)?;
+                        status.ahead = ahead.trim_start_matches('+').parse()?;
+                        status.behind = behind.trim_start_matches('-').parse()?;
+                    }
+                    _ => {}
+                }
+            }
+            // Ordinary and renamed or copied entries
+            "1" | "2" => {
+                let fields = if kind == "1" { 8 } else { 9 };
+                let parts: Vec<&str> = rest.splitn(fields, ' ').collect();
+                let path = parts.get(fields - 1).ok_or_else(invalid)?;
+                let &[x, y] = parts[0].as_bytes() else {
+                    return Err(invalid());
+                };
+
+                let orig_path = if kind == "2" {
+                    let orig = records.next().ok_or_else(invalid)?;
+                    Some(worktree.join(orig))
+                } else {
+                    None
+                };
+
+                let file_status = if x == b'D' || y == b'D' {
+                    FileStatus::Deleted
+                } else if x == b'R' || x == b'C' {
+                    FileStatus::Renamed
+                } else if x == b'A' {
+                    FileStatus::Added
+                } else {
+                    FileStatus::Modified
+                };
+
+                status.files.push(FileStatusEntry {
+                    path: worktree.join(path),
+                    status: file_status,
+                    staged: x != b'.',
+                    orig_path,
+                });
+            }
+            "u" => {
+                let path = rest.splitn(10, ' ').nth(9).ok_or_else(invalid)?;
+                status.files.push(FileStatusEntry {
+                    path: worktree.join(path),
+                    status: FileStatus::Conflicted,
+                    staged: false,
+                    orig_path: None,
+                });
+            }
+            "?" | "!" => {
+                let file_status = if kind == "?" {
+                    FileStatus::Untracked
+                } else {
+                    FileStatus::Ignored
+                };
+                // Ignored dirs end with a slash
+                status.files.push(FileStatusEntry {
+                    path: worktree.join(rest.trim_end_matches('/')),
+                    status: file_status,
+                    staged: false,
+                    orig_path: None,
+                });
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serial_test::serial;
+
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+    use crate::git::testutil::{git, init_repo};
+
+    use super::{parse_status, status, FileStatus, StatusRefresh};
+
+    #[test]
+    fn test_parse_status() {
+        let output = [
+            "# branch.oid 1234",
+            "# branch.head main",
+            "# branch.upstream origin/main",
+            "# branch.ab +2 -1",
+            "1 .M N... 100644 100644 100644 abc abc src/main rs.rs",
+            "2 R. N... 100644 100644 100644 abc abc R100 new.rs",
+            "old.rs",
+            "u UU N... 100644 100644 100644 100644 abc abc abc conflict.rs",
+            "? new file.md",
+            "! dist/",
+            "",
+        ]
+        .join("\0");
+
+        let status = parse_status(Path::new("/repo"), &output).unwrap();
+        assert_eq!(status.branch.as_deref(), Some("main"));
+        assert_eq!(status.upstream.as_deref(), Some("origin/main"));
+        assert_eq!((status.ahead, status.behind), (2, 1));
+
+        let files: Vec<_> = status
+            .files
+            .iter()
+            .map(|f| (f.path.to_string_lossy().to_string(), f.status, f.staged))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                (
+                    "/repo/src/main rs.rs".to_string(),
+                    FileStatus::Modified,
+                    false
+                ),
+                ("/repo/new.rs".to_string(), FileStatus::Renamed, true),
+                (
+                    "/repo/conflict.rs".to_string(),
+                    FileStatus::Conflicted,
+                    false
+                ),
+                (
+                    "/repo/new file.md".to_string(),
+                    FileStatus::Untracked,
+                    false
+                ),
+                ("/repo/dist".to_string(), FileStatus::Ignored, false),
+            ]
+        );
+        assert_eq!(
+            status.files[1].orig_path,
+            Some(Path::new("/repo/old.rs").into())
+        );
+
+        let output = "# branch.oid (initial)\0# branch.head (detached)\0";
+        let status = parse_status(Path::new("/repo"), output).unwrap();
+        assert_eq!(status.branch, None);
+        assert!(parse_status(Path::new("/repo"), "x y").is_err());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_status() {
+        create_test_workspace(false);
+        let dir = get_test_dir();
+        init_repo(&dir);
+        std::fs::write(dir.join(".gitignore"), "dist\n").unwrap();
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", "init"]);
+
+        std::fs::write(dir.join("README.md"), "changed").unwrap();
+        std::fs::write(dir.join("new.md"), "").unwrap();
+        std::fs::write(dir.join("staged.md"), "").unwrap();
+        git(&dir, &["add", "staged.md"]);
+        std::fs::create_dir(dir.join("dist")).unwrap();
+        std::fs::write(dir.join("dist").join("index.js"), "").unwrap();
+        std::fs::remove_file(dir.join("src").join("main.rs")).unwrap();
+
+        let status = status(&dir).await.unwrap();
+        assert_eq!(status.branch.as_deref(), Some("main"));
+        assert_eq!(status.upstream, None);
+
+        let file = |path: &Path| status.files.iter().find(|f| f.path == path).unwrap();
+        assert_eq!(file(&dir.join("README.md")).status, FileStatus::Modified);
+        assert_eq!(file(&dir.join("new.md")).status, FileStatus::Untracked);
+        assert_eq!(file(&dir.join("staged.md")).status, FileStatus::Added);
+        assert!(file(&dir.join("staged.md")).staged);
+        // Only the ignored dir is listed, not the files in it
+        assert_eq!(file(&dir.join("dist")).status, FileStatus::Ignored);
+        assert_eq!(
+            file(&dir.join("src").join("main.rs")).status,
+            FileStatus::Deleted
+        );
+        assert_eq!(status.files.len(), 5);
+    }
+
+    #[test]
+    fn test_status_refresh() {
+        let a = Path::new("/a");
+        let refresh = StatusRefresh::new();
+        assert!(refresh.request(a));
+        assert!(!refresh.request(a));
+        assert!(refresh.request(Path::new("/b")));
+
+        // Requests before the run are covered by it
+        refresh.start(a);
+        assert!(!refresh.finish(a));
+        assert!(refresh.request(a));
+
+        // Requests during the run cause one more
+        refresh.start(a);
+        assert!(!refresh.request(a));
+        assert!(!refresh.request(a));
+        assert!(refresh.finish(a));
+        refresh.start(a);
+        assert!(!refresh.finish(a));
+    }
+}
@@ -0,0 +1,26 @@
+use std::path::Path;
+use std::process::Command;
+
+// Runs git with a fixed identity so that commits work without a global config
+pub fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .args([
+            "-c",
+            "init.defaultBranch=main",
+            "-c",
+            "commit.gpgsign=false",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+// Replaces the fake .git dir of the test workspace with a real repo
+pub fn init_repo(dir: &Path) {
+    let _ = std::fs::remove_dir_all(dir.join(".git"));
+    git(dir, &["init", "-q"]);
+}
@@ -1,20 +1,32 @@
-use tauri::{Builder, Manager, Runtime};
+use tauri::{Builder, Emitter, Manager, Runtime};
 use tauri_plugin_cli::CliExt;
 
 use copilot::chat_service::CopilotChatService;
 use copilot::lsp_service::CopilotLspService;
-use editor::editor_state::EditorState;
+use editor::editor_state::{ChangedDocument, EditorState, DOCUMENT_CHANGED_EXTERNALLY};
+use editor::history::{LocalHistory, HISTORY_INTERVAL};
+use editor::journal::{Journal, JOURNAL_INTERVAL};
+use editor::project_settings::{ProjectSettingsStore, PROJECT_SETTINGS_FILE};
+use editor::watcher::{handle_watch_event, DocumentWatcher};
+use fs::finder::FileIndex;
+use fs::trash::Trash;
+use git::blame::GitBlame;
+use git::diff::GitDiff;
+use git::status::StatusRefresh;
 use lsp::registry::LspRegistry;
 use lsp::service::LspService;
-use tracing::debug;
+use settings::store::{SettingsStore, SETTINGS_FILE};
+use tracing::{debug, error};
 
 mod copilot;
 mod editor;
 mod fs;
+mod git;
 mod install_cli;
 mod logger;
 mod lsp;
 mod menu;
+mod settings;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run<R: Runtime>(builder: Builder<R>) {
@@ -28,13 +40,18 @@ pub fn run<R: Runtime>(builder: Builder<R>) {
         .setup(|app| {
             let handle = app.handle();
 
-            match handle.cli().matches() {
+            let settings_store = SettingsStore::new(app.path().app_config_dir()?.join(SETTINGS_FILE));
+            let settings = settings_store.get();
+            app.manage(settings_store);
+
+            let verbose = match handle.cli().matches() {
                 Ok(matches) => {
                     let verbose = matches
                         .args
                         .get("verbose")
                         .map(|a| a.value.as_bool().unwrap_or(false))
-                        .unwrap_or(false);
+                        .unwrap_or(false)
+                        || settings.verbose;
                     logger::setup::register_logger(handle, verbose)?;
 
                     debug!("Start app with cli args {:?}", &matches);
@@ -64,22 +81,49 @@ pub fn run<R: Runtime>(builder: Builder<R>) {
                         let args = editor::command_args::create_args("".to_string());
                         app.manage(args);
                     }
+
+                    verbose
                 }
                 Err(e) => {
                     println!("{}", e);
                     handle.exit(1);
+                    false
                 }
-            }
+            };
 
             menu::setup_menu(handle)?;
 
             let lsp_registry = LspRegistry::new();
             app.manage(lsp_registry);
 
-            let editor_state = EditorState::new();
+            let editor_state = EditorState::with_large_file_size(settings.large_file_size);
+            *editor_state.root_markers.write().unwrap() = settings.root_markers.clone();
             app.manage(editor_state);
+            app.manage(ProjectSettingsStore::new());
+
+            let document_watcher = DocumentWatcher::new()?;
+            app.manage(document_watcher);
+
+            let file_index = FileIndex::new()?;
+            app.manage(file_index);
+
+            let journal = Journal::new(app.path().app_data_dir()?.join("journal"))?;
+            app.manage(journal);
+
+            let history = LocalHistory::new(
+                app.path().app_data_dir()?.join("history"),
+                settings.history.clone(),
+            )?;
+            app.manage(history);
+
+            let trash = Trash::new(app.path().app_data_dir()?.join("trash"))?;
+            app.manage(trash);
+
+            app.manage(GitDiff::new());
+            app.manage(GitBlame::new());
+            app.manage(StatusRefresh::new());
 
-            let lsp_service = LspService::new(handle.clone());
+            let lsp_service = LspService::new(handle.clone(), verbose);
             app.manage::<LspService<R>>(lsp_service);
 
             let copilot_lsp_service = CopilotLspService::new(handle.clone());
@@ -87,19 +131,75 @@ pub fn run<R: Runtime>(builder: Builder<R>) {
             let copilot_chat_service = CopilotChatService::new().unwrap();
             app.manage(copilot_chat_service);
 
+            if settings.copilot.enabled {
+                let handle = handle.clone();
+                tauri::async_runtime::spawn(async move {
+                    let copilot_service = handle.state::<CopilotLspService<R>>();
+                    if let Err(e) = copilot_service.start().await {
+                        error!("Could not start copilot: {:?}", e);
+                    }
+                });
+            }
+
             let handle2 = handle.clone();
 
             tauri::async_runtime::spawn(async move {
                 let editor_state = handle2.state::<EditorState>();
                 let lsp_service = handle2.state::<LspService<R>>();
                 let copilot_service = handle2.state::<CopilotLspService<R>>();
+                let document_watcher = handle2.state::<DocumentWatcher>();
+                let project_settings = handle2.state::<ProjectSettingsStore>();
+                let mut journal_interval = tokio::time::interval(JOURNAL_INTERVAL);
+                let mut history_interval = tokio::time::interval(HISTORY_INTERVAL);
 
                 loop {
                     tokio::select! {
                         Ok(path) = editor_state.open_doc_rx.recv() => {
+                            let _ = document_watcher.watch(path.as_ref());
+                            // Settings are needed before the language servers start
+                            if let Some(worktree) = editor_state.get_worktree_path(&path) {
+                                if project_settings.open(&worktree) {
+                                    let _ = document_watcher.watch(&worktree.join(PROJECT_SETTINGS_FILE));
+                                }
+                            }
                             let _ = lsp_service.register_language_server(path.as_ref()).await;
                             let _ = copilot_service.register_language_server(path.as_ref()).await;
                         },
+                        Ok(doc) = editor_state.closed_doc_rx.recv() => {
+                            if let Some(language_server_id) = doc.get_language_server_id() {
+                                let _ = lsp_service.close_document(&language_server_id, &doc).await;
+                            }
+                            let _ = copilot_service.close_document(&doc).await;
+                        },
+                        Ok(doc) = editor_state.changed_doc_rx.recv() => {
+                            if let Some(language_server_id) = doc.get_language_server_id() {
+                                let _ = lsp_service.update_document(&language_server_id, &doc).await;
+                            }
+                            let _ = copilot_service.update_document(&doc).await;
+                            let _ = handle2.emit(DOCUMENT_CHANGED_EXTERNALLY, ChangedDocument::from(doc));
+                        },
+                        Ok(event) = document_watcher.rx.recv() => {
+                            let _ = handle_watch_event(&handle2, event).await;
+                        },
+                        _ = journal_interval.tick() => {
+                            // Writes to disk, so it runs outside of the loop
+                            let handle = handle2.clone();
+                            tauri::async_runtime::spawn_blocking(move || {
+                                let editor_state = handle.state::<EditorState>();
+                                if let Err(e) = handle.state::<Journal>().snapshot(&editor_state) {
+                                    error!("Journal snapshot failed: {:?}", e);
+                                }
+                            });
+                        },
+                        _ = history_interval.tick() => {
+                            let handle = handle2.clone();
+                            tauri::async_runtime::spawn_blocking(move || {
+                                let editor_state = handle.state::<EditorState>();
+                                if let Err(e) = handle.state::<LocalHistory>().snapshot_dirty(&editor_state) {
+                                    error!("History snapshot failed: {:?}", e);
+                                }
+                            });
+                        },
                     }
                 }
             });
@@ -111,6 +211,22 @@ pub fn run<R: Runtime>(builder: Builder<R>) {
             fs::metadata::get_mime_type,
             fs::metadata::get_file_last_modified,
             fs::list::list_contents,
+            fs::list::list_directory,
+            fs::operations::create_file,
+            fs::operations::create_dir,
+            fs::operations::rename_path,
+            fs::operations::move_path,
+            fs::operations::copy_path,
+            fs::operations::delete_path,
+            fs::operations::restore_from_trash,
+            fs::operations::list_trash,
+            git::command::git_status,
+            git::command::git_diff_hunks,
+            git::command::git_revert_hunk,
+            git::command::git_blame,
+            fs::finder::find_files,
+            fs::search::search_project,
+            fs::search::replace_project,
             fs::path::resolve_path,
             fs::path::dirname,
             fs::path::basename,
@@ -118,10 +234,31 @@ pub fn run<R: Runtime>(builder: Builder<R>) {
             fs::path::to_absolute_path,
             editor::command_editor_state::get_document,
             editor::command_editor_state::read_text,
+            editor::command_editor_state::read_lines,
             editor::command_editor_state::replace_text,
             editor::command_editor_state::insert_text,
             editor::command_editor_state::delete_text,
+            editor::command_editor_state::apply_changes,
+            editor::command_editor_state::search_document,
+            editor::command_editor_state::replace_all,
+            editor::command_editor_state::undo,
+            editor::command_editor_state::redo,
+            editor::command_editor_state::list_dirty_documents,
+            editor::command_editor_state::list_recoverable_documents,
+            editor::command_editor_state::restore_document,
+            editor::command_editor_state::discard_recoverable_document,
+            editor::command_editor_state::set_file_format,
+            editor::command_editor_state::reopen_with_encoding,
             editor::command_editor_state::write_file,
+            editor::command_history::list_history,
+            editor::command_history::read_history,
+            editor::command_history::diff_history,
+            editor::command_history::restore_history,
+            editor::command_history::get_history_retention,
+            editor::command_history::set_history_retention,
+            editor::command_project_settings::get_project_settings,
+            settings::command::get_settings,
+            settings::command::update_settings,
             lsp::command::lsp_hover,
             lsp::command::lsp_completion,
             lsp::command::lsp_goto,
@@ -146,6 +283,10 @@ pub fn run<R: Runtime>(builder: Builder<R>) {
             if let tauri::RunEvent::Exit = event {
                 let lsp_registry = app_handle.state::<LspRegistry>();
                 tauri::async_runtime::block_on(async { lsp_registry.shutdown().await });
+                // Unsaved changes are only recoverable after a crash
+                if let Err(e) = app_handle.state::<Journal>().clear() {
+                    error!("Could not clear journal: {:?}", e);
+                }
             }
         });
 }
@@ -2,4 +2,6 @@ pub mod command;
 pub mod registry;
 pub mod server;
 pub mod service;
+#[cfg(test)]
+pub mod testutil;
 pub mod util;
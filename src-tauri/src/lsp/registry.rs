@@ -15,13 +15,26 @@ use super::server::LspServer;
 #[derive(Eq, Hash, PartialEq, Debug, Clone)]
 pub struct LanguageServerId(pub PathBuf, pub Language);
 
+// Creates a language server, optionally with a command from the project settings
+type LanguageServerFactory = Box<
+    dyn Fn(&LanguageServerId, Option<&str>) -> anyhow::Result<(LspServer, JoinHandle<()>)>
+        + Send
+        + Sync,
+>;
+
 pub struct LspRegistry {
     pub language_servers: RwLock<HashMap<LanguageServerId, LspServer>>,
     pub language_server_configs: RwLock<HashMap<LanguageServerId, InitializeResult>>,
+    factory: LanguageServerFactory,
 }
 
 impl Document {
     pub fn get_language_server_id(&self) -> Option<LanguageServerId> {
+        // Large files are not sent to language servers
+        if self.large_file {
+            return None;
+        }
+
         let language = self.language.clone()?;
         let path = self.worktree_path.clone().unwrap_or_else(|| {
             if cfg!(target_os = "windows") {
@@ -37,9 +50,21 @@ impl Document {
 
 impl LspRegistry {
     pub fn new() -> Self {
+        Self::with_factory(Self::create_language_server)
+    }
+
+    // Creates a registry that spawns language servers with a custom factory
+    pub fn with_factory<F>(factory: F) -> Self
+    where
+        F: Fn(&LanguageServerId, Option<&str>) -> anyhow::Result<(LspServer, JoinHandle<()>)>
+            + Send
+            + Sync
+            + 'static,
+    {
         Self {
             language_servers: RwLock::new(HashMap::new()),
             language_server_configs: RwLock::new(HashMap::new()),
+            factory: Box::new(factory),
         }
     }
 
@@ -68,6 +93,7 @@ impl LspRegistry {
     pub async fn register_language_server(
         &self,
         language_server_id: &LanguageServerId,
+        command: Option<&str>,
     ) -> anyhow::Result<(LspServer, bool)> {
         let mut language_servers = self.language_servers.write().await;
 
@@ -77,7 +103,7 @@ impl LspRegistry {
                     "register new language server (id={:?})",
                     &language_server_id
                 );
-                let (server, _) = Self::create_language_server(language_server_id)?;
+                let (server, _) = (self.factory)(language_server_id, command)?;
                 let server = entry.insert(server.clone());
                 Ok((server.clone(), true))
             }
@@ -124,11 +150,16 @@ impl LspRegistry {
 
     fn create_language_server(
         language_server_id: &LanguageServerId,
+        command: Option<&str>,
     ) -> anyhow::Result<(LspServer, JoinHandle<()>)> {
         info!(
-            "create language server (language_server_id={:?})",
-            language_server_id
+            "create language server (language_server_id={:?}, command={:?})",
+            language_server_id, command
         );
+        if let Some(command) = command {
+            return Ok(LspServer::new(command));
+        }
+
         match language_server_id.1 .0.as_str() {
             "typescript" => Ok(LspServer::new("typescript-language-server --stdio")),
             "rust" => Ok(LspServer::new("rust-analyzer")),
@@ -142,12 +173,15 @@ impl LspRegistry {
 mod tests {
     use std::time::SystemTime;
 
+    use async_lsp::lsp_types::TextDocumentSyncKind;
     use ropey::Rope;
     use serial_test::serial;
 
     use crate::editor::editor_state::{Document, Language};
     use crate::editor::testutil::{create_test_workspace, get_test_dir};
-    use crate::lsp::registry::{LanguageServerId, LspRegistry};
+    use crate::lsp::registry::LanguageServerId;
+    use crate::lsp::service::OffsetEncoding;
+    use crate::lsp::testutil::{FakeLanguageServer, FakeMessage};
 
     #[tokio::test]
     #[serial]
@@ -161,21 +195,43 @@ mod tests {
             path,
             worktree_path: Some(get_test_dir()),
             language: Some(language.clone()),
+            format: Default::default(),
             text: Rope::new(),
             last_modified: SystemTime::now(),
+            disk_modified: None,
             version: 0,
+            saved_version: 0,
+            content_hash: 0,
+            is_dirty: false,
+            conflicted: false,
+            large_file: false,
+            editor_config: Default::default(),
         };
 
-        let lsp_registry = LspRegistry::new();
-        lsp_registry
-            .register_language_server(&doc.get_language_server_id().unwrap())
+        let (lsp_registry, mut rx) =
+            FakeLanguageServer::new(OffsetEncoding::Utf16, TextDocumentSyncKind::INCREMENTAL)
+                .registry();
+        let (_, created) = lsp_registry
+            .register_language_server(&doc.get_language_server_id().unwrap(), None)
             .await
             .unwrap();
+        assert!(created);
+
+        let (_, created) = lsp_registry
+            .register_language_server(&doc.get_language_server_id().unwrap(), None)
+            .await
+            .unwrap();
+        assert!(!created);
 
         assert!(lsp_registry
             .language_servers
             .read()
             .await
             .contains_key(&LanguageServerId(get_test_dir(), language)));
+
+        lsp_registry.shutdown().await;
+        assert!(matches!(rx.recv().await, Some(FakeMessage::Shutdown)));
+        assert!(matches!(rx.recv().await, Some(FakeMessage::Exit)));
+        assert!(lsp_registry.language_servers.read().await.is_empty());
     }
 }
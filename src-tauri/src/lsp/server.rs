@@ -12,6 +12,7 @@ use async_lsp::{
     tracing::TracingLayer,
     LanguageServer, Result, ServerSocket,
 };
+use futures::{AsyncRead, AsyncWrite};
 use futures_channel::oneshot;
 use tracing::{debug, info};
 use tokio::{sync::RwLock, task::JoinHandle};
@@ -32,6 +33,24 @@ pub struct LspServer {
 
 impl LspServer {
     pub fn new(script: &str) -> (LspServer, JoinHandle<()>) {
+        let child = async_process::Command::new("sh")
+            .args(["-c", script])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.unwrap();
+        let stdin = child.stdin.unwrap();
+
+        Self::from_io(stdout, stdin)
+    }
+
+    // Runs the client main loop over any transport, e.g. an in-memory pipe in tests
+    pub fn from_io(
+        input: impl AsyncRead + Send + 'static,
+        output: impl AsyncWrite + Send + 'static,
+    ) -> (LspServer, JoinHandle<()>) {
         let (mainloop, server) = async_lsp::MainLoop::new_client(|_server| {
             let mut router = Router::new(ClientState { indexed_tx: None });
             router
@@ -67,18 +86,8 @@ impl LspServer {
                 .service(router)
         });
 
-        let child = async_process::Command::new("sh")
-            .args(["-c", script])
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .unwrap();
-        let stdout = child.stdout.unwrap();
-        let stdin = child.stdin.unwrap();
-
         let mainloop_fut = tokio::spawn(async move {
-            mainloop.run_buffered(stdout, stdin).await.unwrap();
+            mainloop.run_buffered(input, output).await.unwrap();
         });
 
         (
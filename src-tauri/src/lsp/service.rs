@@ -1,26 +1,40 @@
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use anyhow::anyhow;
-use async_lsp::lsp_types::notification::{DidChangeTextDocument, DidOpenTextDocument};
-use async_lsp::lsp_types::request::{Completion, GotoDefinition};
+use async_lsp::lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidRenameFiles,
+};
+use async_lsp::lsp_types::request::{Completion, Formatting, GotoDefinition, WillRenameFiles};
 use async_lsp::lsp_types::{
-    request::HoverRequest, HoverParams, Range, TextDocumentIdentifier, TextDocumentPositionParams,
-    Url,
+    request::HoverRequest, HoverParams, TextDocumentIdentifier, TextDocumentPositionParams, Url,
 };
 use async_lsp::lsp_types::{
     CompletionContext, CompletionParams, CompletionResponse, CompletionTriggerKind,
-    DidChangeTextDocumentParams, DidOpenTextDocumentParams, GotoDefinitionParams,
-    GotoDefinitionResponse, Hover, InitializeParams, InitializeResult,
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, InitializeParams, InitializeResult,
     TextDocumentContentChangeEvent, TextDocumentItem, TextDocumentSyncCapability,
     TextDocumentSyncKind, TraceValue, VersionedTextDocumentIdentifier, WorkspaceFolder,
 };
-use tracing::debug;
+use async_lsp::lsp_types::{
+    DocumentChangeOperation, DocumentChanges, DocumentFormattingParams,
+    FileOperationRegistrationOptions, FileRename, FormattingOptions, OneOf, RenameFilesParams,
+    TextEdit, WorkspaceEdit, WorkspaceFileOperationsServerCapabilities,
+};
+use globset::Glob;
+use ropey::Rope;
+use serde_json::Value;
+use tracing::{debug, error, warn};
 use tauri::{AppHandle, Manager, Runtime};
-use tauri_plugin_cli::CliExt;
 
-use crate::editor::editor_state::{Delete, Document, EditorState, Insert};
+use crate::editor::editor_state::{Change, Document, EditorState, Transaction};
+use crate::editor::file_format::FileFormat;
+use crate::editor::project_settings::{ProjectSettings, ProjectSettingsStore};
+use crate::editor::search::apply_replacements;
+use crate::fs::write::write_atomic;
 use crate::lsp::registry::LspRegistry;
-use crate::lsp::util::{get_offset_encoding, pos_to_lsp_pos, url_for_path};
+use crate::lsp::util::{get_offset_encoding, lsp_pos_to_pos, pos_to_lsp_pos, url_for_path};
+use crate::settings::store::{LanguageServerSettings, Settings, SettingsStore};
 
 use super::registry::LanguageServerId;
 use super::server::LspServer;
@@ -38,26 +52,50 @@ pub enum OffsetEncoding {
 
 pub struct LspService<R: Runtime> {
     pub app_handle: AppHandle<R>,
+    pub verbose: bool,
 }
 
 impl<R: Runtime> LspService<R> {
-    pub fn new(app_handle: AppHandle<R>) -> Self {
-        LspService { app_handle }
+    pub fn new(app_handle: AppHandle<R>, verbose: bool) -> Self {
+        LspService {
+            app_handle,
+            verbose,
+        }
     }
 
     pub async fn register_language_server(&self, path: &Path) -> anyhow::Result<()> {
         let editor_state = self.app_handle.state::<EditorState>();
         let lsp_registry = self.app_handle.state::<LspRegistry>();
 
+        let project_settings = self.app_handle.state::<ProjectSettingsStore>();
+        let settings_store = self.app_handle.state::<SettingsStore>();
+
         let doc = editor_state.get_document(path).await?;
         let language_server_id = doc.get_language_server_id().ok_or(anyhow!("No language"))?;
+        let settings = resolve_language_server(
+            &settings_store.get(),
+            &doc.get_language_id(),
+            project_settings.resolve(&doc),
+        );
+        if settings.enabled == Some(false) {
+            return Err(anyhow!(
+                "Language server disabled in settings (id={:?})",
+                language_server_id
+            ));
+        }
 
         match lsp_registry
-            .register_language_server(&language_server_id)
+            .register_language_server(&language_server_id, settings.command.as_deref())
             .await?
         {
             (server, true) => {
-                let result = self.initialize(&server, &language_server_id).await?;
+                let result = self
+                    .initialize(
+                        &server,
+                        &language_server_id,
+                        settings.initialization_options,
+                    )
+                    .await?;
                 lsp_registry
                     .insert_language_server_config(&language_server_id, result)
                     .await;
@@ -76,28 +114,21 @@ impl<R: Runtime> LspService<R> {
         &self,
         server: &LspServer,
         language_server_id: &LanguageServerId,
+        initialization_options: Option<serde_json::Value>,
     ) -> anyhow::Result<InitializeResult> {
         debug!("LSP - send initialize request");
         let root_uri = async_lsp::lsp_types::Url::from_file_path(&language_server_id.0)
             .map_err(|_| anyhow!("invalid root_uri"))?;
-        let trace = self
-            .app_handle
-            .cli()
-            .matches()
-            .ok()
-            .and_then(|m| m.args.get("verbose").and_then(|a| a.value.as_bool()))
-            .map(|verbose| {
-                if verbose {
-                    TraceValue::Verbose
-                } else {
-                    TraceValue::Off
-                }
-            })
-            .or(Some(TraceValue::Off));
+        let trace = if self.verbose {
+            TraceValue::Verbose
+        } else {
+            TraceValue::Off
+        };
 
         let result = server
             .initialize(InitializeParams {
-                trace,
+                trace: Some(trace),
+                initialization_options,
                 workspace_folders: Some(vec![WorkspaceFolder {
                     uri: root_uri,
                     name: Default::default(),
@@ -131,7 +162,7 @@ impl<R: Runtime> LspService<R> {
         Ok(())
     }
 
-    pub async fn update_document(
+    pub async fn close_document(
         &self,
         language_server_id: &LanguageServerId,
         doc: &Document,
@@ -143,78 +174,40 @@ impl<R: Runtime> LspService<R> {
             .await
             .ok_or(anyhow!("No language server"))?;
 
-        let content_changes = vec![TextDocumentContentChangeEvent {
-            range: None,
-            range_length: None,
-            text: doc.text.to_string(),
-        }];
-
-        debug!("LSP - update document (version={})", doc.version);
-
+        let file_uri = url_for_path(doc.path.as_ref());
+        debug!("LSP - close document (file_uri={:?})", file_uri);
         server
-            .notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
-                text_document: VersionedTextDocumentIdentifier {
-                    uri: url_for_path(doc.path.as_ref()),
-                    version: doc.version,
-                },
-                content_changes,
+            .notify::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier { uri: file_uri },
             })
             .await?;
         Ok(())
     }
 
-    pub async fn insert_document(
+    pub async fn update_document(
         &self,
         language_server_id: &LanguageServerId,
         doc: &Document,
-        data: &Insert,
     ) -> anyhow::Result<()> {
         let lsp_registry = self.app_handle.state::<LspRegistry>();
 
-        let config = lsp_registry
-            .get_language_server_config(language_server_id)
-            .await
-            .ok_or(anyhow!("No language server config"))?;
-
         let server = lsp_registry
             .get_language_server(language_server_id)
             .await
             .ok_or(anyhow!("No language server"))?;
 
-        let document_sync_kind = self.document_sync_kind(&config);
-        let offset_encoding = get_offset_encoding(&config);
-        let from = pos_to_lsp_pos(&doc.text, data.from_a, offset_encoding);
-        let to = pos_to_lsp_pos(&doc.text, data.to_b, offset_encoding);
-
-        let content_changes: Vec<_> = match document_sync_kind {
-            Some(TextDocumentSyncKind::FULL) => {
-                debug!("LSP - full document update");
-                vec![TextDocumentContentChangeEvent {
-                    range: None,
-                    range_length: None,
-                    text: doc.text.to_string(),
-                }]
-            }
-            Some(TextDocumentSyncKind::INCREMENTAL) => {
-                debug!("LSP - incremental document update");
-                vec![TextDocumentContentChangeEvent {
-                    range: Some(Range::new(from, to)),
-                    range_length: None,
-                    text: data.text.clone(),
-                }]
-            }
-            _ => Vec::new(),
-        };
+        let content_changes = vec![TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: doc.text.to_string(),
+        }];
 
-        debug!(
-            "LSP - insert document (from=[{}, {}], to=[{}, {}], text={}, version={})",
-            from.line, from.character, to.line, to.character, data.text, doc.version
-        );
+        debug!("LSP - update document (version={})", doc.version);
 
         server
             .notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
                 text_document: VersionedTextDocumentIdentifier {
-                    uri: Url::from_file_path(doc.path.clone()).unwrap(),
+                    uri: url_for_path(doc.path.as_ref()),
                     version: doc.version,
                 },
                 content_changes,
@@ -223,11 +216,10 @@ impl<R: Runtime> LspService<R> {
         Ok(())
     }
 
-    pub async fn delete_document(
+    pub async fn change_document(
         &self,
         language_server_id: &LanguageServerId,
-        doc: &Document,
-        data: &Delete,
+        tx: &Transaction,
     ) -> anyhow::Result<()> {
         let lsp_registry = self.app_handle.state::<LspRegistry>();
 
@@ -241,36 +233,43 @@ impl<R: Runtime> LspService<R> {
             .await
             .ok_or(anyhow!("No language server"))?;
 
+        let doc = &tx.doc;
         let document_sync_kind = self.document_sync_kind(&config);
         let offset_encoding = get_offset_encoding(&config);
-        let from = pos_to_lsp_pos(&doc.text, data.from_a, offset_encoding);
-        let to = pos_to_lsp_pos(&doc.text, data.to_a, offset_encoding);
 
         let content_changes: Vec<_> = match document_sync_kind {
             Some(TextDocumentSyncKind::FULL) => {
+                debug!("LSP - full document update");
                 vec![TextDocumentContentChangeEvent {
                     range: None,
                     range_length: None,
                     text: doc.text.to_string(),
                 }]
             }
-            Some(TextDocumentSyncKind::INCREMENTAL) => vec![TextDocumentContentChangeEvent {
-                range: Some(Range::new(from, to)),
-                range_length: None,
-                text: "".to_string(),
-            }],
+            Some(TextDocumentSyncKind::INCREMENTAL) => {
+                debug!("LSP - incremental document update");
+                tx.changes
+                    .iter()
+                    .map(|change| TextDocumentContentChangeEvent {
+                        range: Some(change.range(offset_encoding)),
+                        range_length: None,
+                        text: change.text.clone(),
+                    })
+                    .collect()
+            }
             _ => Vec::new(),
         };
 
         debug!(
-            "LSP - delete document (from=[{}, {}], to=[{}, {}], version={})",
-            from.line, from.character, to.line, to.character, doc.version
+            "LSP - change document (changes={}, version={})",
+            content_changes.len(),
+            doc.version
         );
 
         server
             .notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
                 text_document: VersionedTextDocumentIdentifier {
-                    uri: Url::from_file_path(doc.path.clone()).unwrap(),
+                    uri: url_for_path(doc.path.as_ref()),
                     version: doc.version,
                 },
                 content_changes,
@@ -417,6 +416,191 @@ impl<R: Runtime> LspService<R> {
         response.ok_or(anyhow!("No response"))
     }
 
+    // Returns the formatting edits of the language server as changes of the
+    // document. Empty if the server does not support formatting.
+    pub async fn format_document(
+        &self,
+        doc: &Document,
+        tab_width: u32,
+        use_tabs: bool,
+    ) -> anyhow::Result<Vec<Change>> {
+        let lsp_registry = self.app_handle.state::<LspRegistry>();
+        let language_server_id = doc.get_language_server_id().ok_or(anyhow!("No language"))?;
+
+        let config = lsp_registry
+            .get_language_server_config(&language_server_id)
+            .await
+            .ok_or(anyhow!("No language server config"))?;
+        let supported = matches!(
+            config.capabilities.document_formatting_provider,
+            Some(OneOf::Left(true) | OneOf::Right(_))
+        );
+        if !supported {
+            return Ok(Vec::new());
+        }
+
+        let server = lsp_registry
+            .get_language_server(&language_server_id)
+            .await
+            .ok_or(anyhow!("No language server"))?;
+
+        debug!("LSP - send formatting request (path={:?})", doc.path);
+        let edits = server
+            .request::<Formatting>(DocumentFormattingParams {
+                text_document: TextDocumentIdentifier::new(url_for_path(&doc.path)),
+                options: FormattingOptions {
+                    tab_size: tab_width,
+                    insert_spaces: !use_tabs,
+                    ..Default::default()
+                },
+                work_done_progress_params: Default::default(),
+            })
+            .await?
+            .unwrap_or_default();
+
+        Ok(to_changes(&doc.text, &edits, get_offset_encoding(&config)))
+    }
+
+    // Asks servers for edits before a file is renamed, e.g. to update imports,
+    // and applies them. Errors of single servers don't stop the rename.
+    pub async fn will_rename_files(&self, from: &Path, to: &Path) {
+        let servers = self
+            .file_operation_servers(from, |ops| ops.will_rename.as_ref())
+            .await;
+
+        for (server, offset_encoding) in servers {
+            debug!("LSP - will rename files (from={:?}, to={:?})", from, to);
+            let result = server
+                .request::<WillRenameFiles>(rename_files_params(from, to))
+                .await;
+
+            match result {
+                Ok(Some(edit)) => {
+                    if let Err(e) = self.apply_workspace_edit(&edit, offset_encoding).await {
+                        error!("Could not apply workspace edit: {:?}", e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => error!("LSP - will rename files failed: {:?}", e),
+            }
+        }
+    }
+
+    pub async fn did_rename_files(&self, from: &Path, to: &Path) {
+        let servers = self
+            .file_operation_servers(to, |ops| ops.did_rename.as_ref())
+            .await;
+
+        for (server, _) in servers {
+            debug!("LSP - did rename files (from={:?}, to={:?})", from, to);
+            if let Err(e) = server
+                .notify::<DidRenameFiles>(rename_files_params(from, to))
+                .await
+            {
+                error!("LSP - did rename files failed: {:?}", e);
+            }
+        }
+    }
+
+    // Applies text edits to open documents as unsaved changes, their editors
+    // reload them with the new version. Closed files are written directly with
+    // their line endings kept. Resource operations are not supported.
+    pub async fn apply_workspace_edit(
+        &self,
+        edit: &WorkspaceEdit,
+        offset_encoding: OffsetEncoding,
+    ) -> anyhow::Result<()> {
+        let mut edits: Vec<(PathBuf, Vec<TextEdit>)> = Vec::new();
+        for (uri, text_edits) in edit.changes.iter().flatten() {
+            edits.push((to_file_path(uri)?, text_edits.clone()));
+        }
+
+        let document_edits = match &edit.document_changes {
+            Some(DocumentChanges::Edits(edits)) => edits.iter().collect(),
+            Some(DocumentChanges::Operations(ops)) => ops
+                .iter()
+                .filter_map(|op| match op {
+                    DocumentChangeOperation::Edit(edit) => Some(edit),
+                    DocumentChangeOperation::Op(_) => None,
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        for edit in document_edits {
+            let text_edits = edit
+                .edits
+                .iter()
+                .map(|e| match e {
+                    OneOf::Left(e) => e.clone(),
+                    OneOf::Right(e) => e.text_edit.clone(),
+                })
+                .collect();
+            edits.push((to_file_path(&edit.text_document.uri)?, text_edits));
+        }
+
+        let editor_state = self.app_handle.state::<EditorState>();
+        for (path, text_edits) in edits {
+            let doc = editor_state.documents.read().unwrap().get(&path).cloned();
+            match doc {
+                Some(doc) => {
+                    let changes = to_changes(&doc.text, &text_edits, offset_encoding);
+                    let tx = editor_state.apply_changes(&path, doc.version, &changes)?;
+                    editor_state.changed_doc_tx.send(tx.doc).await?;
+                }
+                None => {
+                    let (format, text) = FileFormat::decode_raw(&fs::read(&path)?, None)?;
+                    let mut text = Rope::from_str(&text);
+                    let mut changes = to_changes(&text, &text_edits, offset_encoding);
+                    changes.sort_by_key(|c| c.from);
+                    apply_replacements(&mut text, &changes);
+                    write_atomic(&path, |w| format.write(&text, w))?;
+                }
+            }
+            debug!("LSP - applied workspace edit (path={:?})", path);
+        }
+
+        Ok(())
+    }
+
+    // Running servers of worktrees containing `path` that registered for a
+    // file operation with a matching filter
+    async fn file_operation_servers<F>(
+        &self,
+        path: &Path,
+        registration: F,
+    ) -> Vec<(LspServer, OffsetEncoding)>
+    where
+        F: Fn(
+            &WorkspaceFileOperationsServerCapabilities,
+        ) -> Option<&FileOperationRegistrationOptions>,
+    {
+        let lsp_registry = self.app_handle.state::<LspRegistry>();
+        let configs = lsp_registry.language_server_configs.read().await.clone();
+
+        let mut servers = Vec::new();
+        for (language_server_id, config) in configs {
+            if !path.starts_with(&language_server_id.0) {
+                continue;
+            }
+
+            let options = config
+                .capabilities
+                .workspace
+                .as_ref()
+                .and_then(|w| w.file_operations.as_ref())
+                .and_then(&registration);
+            if !options.is_some_and(|o| matches_filters(o, path)) {
+                continue;
+            }
+
+            if let Some(server) = lsp_registry.get_language_server(&language_server_id).await {
+                servers.push((server, get_offset_encoding(&config)));
+            }
+        }
+        servers
+    }
+
     fn document_sync_kind(&self, config: &InitializeResult) -> Option<TextDocumentSyncKind> {
         config
             .capabilities
@@ -428,3 +612,512 @@ impl<R: Runtime> LspService<R> {
             })
     }
 }
+
+// Merges the language server settings of the app with the ones of the project.
+// The project command is ignored unless the worktree is trusted.
+fn resolve_language_server(
+    settings: &Settings,
+    language_id: &str,
+    project: ProjectSettings,
+) -> LanguageServerSettings {
+    let app = settings
+        .language_servers
+        .get(language_id)
+        .cloned()
+        .unwrap_or_default();
+    let trusted = project
+        .worktree_path
+        .as_ref()
+        .is_some_and(|w| settings.trusted_projects.contains(w));
+    let project = project.language_server;
+
+    let command = match project.command {
+        Some(command) if trusted => Some(command),
+        Some(_) => {
+            warn!(
+                "Ignore language server command of untrusted project (language={})",
+                language_id
+            );
+            app.command
+        }
+        None => app.command,
+    };
+    let enabled = match (app.enabled, project.enabled) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (app, project) => project.or(app),
+    };
+
+    LanguageServerSettings {
+        enabled,
+        command,
+        initialization_options: merge_options(
+            app.initialization_options,
+            project.initialization_options,
+        ),
+    }
+}
+
+// Merges objects recursively, other values of `overrides` replace the base
+fn merge_options(base: Option<Value>, overrides: Option<Value>) -> Option<Value> {
+    match (base, overrides) {
+        (Some(Value::Object(mut base)), Some(Value::Object(overrides))) => {
+            for (key, value) in overrides {
+                let merged = merge_options(base.remove(&key), Some(value));
+                base.insert(key, merged.unwrap_or(Value::Null));
+            }
+            Some(Value::Object(base))
+        }
+        (base, overrides) => overrides.or(base),
+    }
+}
+
+fn rename_files_params(from: &Path, to: &Path) -> RenameFilesParams {
+    RenameFilesParams {
+        files: vec![FileRename {
+            old_uri: url_for_path(from).to_string(),
+            new_uri: url_for_path(to).to_string(),
+        }],
+    }
+}
+
+fn matches_filters(options: &FileOperationRegistrationOptions, path: &Path) -> bool {
+    options.filters.iter().any(|filter| {
+        filter.scheme.as_deref().is_none_or(|s| s == "file")
+            && Glob::new(&filter.pattern.glob)
+                .map(|g| g.compile_matcher().is_match(path))
+                .unwrap_or(false)
+    })
+}
+
+fn to_file_path(uri: &Url) -> anyhow::Result<PathBuf> {
+    uri.to_file_path()
+        .map_err(|_| anyhow!("Not a file uri (uri={})", uri))
+}
+
+fn to_changes(text: &Rope, edits: &[TextEdit], offset_encoding: OffsetEncoding) -> Vec<Change> {
+    edits
+        .iter()
+        .map(|edit| Change {
+            from: lsp_pos_to_pos(text, edit.range.start, offset_encoding),
+            to: lsp_pos_to_pos(text, edit.range.end, offset_encoding),
+            text: edit.new_text.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use async_lsp::lsp_types::{
+        CompletionItem, CompletionResponse, CompletionTriggerKind, GotoDefinitionResponse,
+        HoverContents, Location, MarkedString, Position, Range, TextDocumentSyncKind, TextEdit,
+        Url,
+    };
+    use serde_json::json;
+    use serial_test::serial;
+    use tauri::test::{mock_app, MockRuntime};
+    use tauri::{App, Manager};
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use crate::editor::editor_state::{Change, Delete, EditorState};
+    use crate::editor::project_settings::{
+        ProjectLanguageServerSettings, ProjectSettings, ProjectSettingsStore, PROJECT_SETTINGS_FILE,
+    };
+    use crate::editor::testutil::{create_test_workspace, doc_version, get_test_dir};
+    use crate::lsp::registry::LspRegistry;
+    use crate::lsp::testutil::{FakeLanguageServer, FakeMessage};
+    use crate::settings::store::{LanguageServerSettings, Settings, SettingsStore, SETTINGS_FILE};
+
+    use super::{resolve_language_server, LspService, OffsetEncoding};
+
+    fn create_app(fake: FakeLanguageServer) -> (App<MockRuntime>, UnboundedReceiver<FakeMessage>) {
+        let (lsp_registry, rx) = fake.registry();
+        let app = mock_app();
+        app.manage(EditorState::default());
+        app.manage(ProjectSettingsStore::new());
+        app.manage(SettingsStore::new(get_test_dir().join(SETTINGS_FILE)));
+        app.manage(lsp_registry);
+        app.manage(LspService::new(app.handle().clone(), false));
+        (app, rx)
+    }
+
+    async fn expect_initialized(rx: &mut UnboundedReceiver<FakeMessage>) -> String {
+        assert!(matches!(rx.recv().await, Some(FakeMessage::Initialize(_))));
+        assert!(matches!(rx.recv().await, Some(FakeMessage::Initialized)));
+        match rx.recv().await {
+            Some(FakeMessage::DidOpen(params)) => params.text_document.text,
+            msg => panic!("Expected didOpen, got {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_initialize() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("src").join("index.ts");
+        std::fs::write(&path, "const a = 1").unwrap();
+
+        let fake = FakeLanguageServer::new(OffsetEncoding::Utf8, TextDocumentSyncKind::FULL);
+        let (app, mut rx) = create_app(fake);
+        let lsp_service = app.state::<LspService<MockRuntime>>();
+        lsp_service.register_language_server(&path).await.unwrap();
+
+        match rx.recv().await {
+            Some(FakeMessage::Initialize(params)) => {
+                let folders = params.workspace_folders.unwrap();
+                assert_eq!(folders[0].uri, Url::from_file_path(get_test_dir()).unwrap());
+            }
+            msg => panic!("Expected initialize, got {:?}", msg),
+        }
+        assert!(matches!(rx.recv().await, Some(FakeMessage::Initialized)));
+        match rx.recv().await {
+            Some(FakeMessage::DidOpen(params)) => {
+                assert_eq!(params.text_document.text, "const a = 1");
+                assert_eq!(params.text_document.language_id, "typescript");
+            }
+            msg => panic!("Expected didOpen, got {:?}", msg),
+        }
+
+        // Second document with the same language reuses the running server
+        let path = get_test_dir().join("src").join("other.ts");
+        std::fs::write(&path, "").unwrap();
+        lsp_service.register_language_server(&path).await.unwrap();
+        assert!(matches!(rx.recv().await, Some(FakeMessage::DidOpen(_))));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_incremental_delete() {
+        let cases = [
+            (
+                OffsetEncoding::Utf8,
+                Range::new(Position::new(1, 5), Position::new(1, 6)),
+            ),
+            (
+                OffsetEncoding::Utf16,
+                Range::new(Position::new(1, 3), Position::new(1, 4)),
+            ),
+            (
+                OffsetEncoding::Utf32,
+                Range::new(Position::new(1, 2), Position::new(1, 3)),
+            ),
+        ];
+
+        for (offset_encoding, expected) in cases {
+            create_test_workspace(true);
+            let path = get_test_dir().join("src").join("index.ts");
+            std::fs::write(&path, "x\na😀b").unwrap();
+
+            let fake = FakeLanguageServer::new(offset_encoding, TextDocumentSyncKind::INCREMENTAL);
+            let (app, mut rx) = create_app(fake);
+            let editor_state = app.state::<EditorState>();
+            let lsp_service = app.state::<LspService<MockRuntime>>();
+            lsp_service.register_language_server(&path).await.unwrap();
+            expect_initialized(&mut rx).await;
+
+            // Delete "b"
+            let tx = editor_state
+                .delete_text(
+                    &path,
+                    doc_version(&editor_state, &path),
+                    &Delete { from_a: 5, to_a: 6 },
+                )
+                .unwrap();
+            let language_server_id = tx.doc.get_language_server_id().unwrap();
+            lsp_service
+                .change_document(&language_server_id, &tx)
+                .await
+                .unwrap();
+
+            match rx.recv().await {
+                Some(FakeMessage::DidChange(params)) => {
+                    assert_eq!(params.content_changes.len(), 1);
+                    assert_eq!(params.content_changes[0].range, Some(expected));
+                    assert_eq!(params.content_changes[0].text, "");
+                }
+                msg => panic!("Expected didChange, got {:?}", msg),
+            }
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_incremental_batch() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("src").join("index.ts");
+        std::fs::write(&path, "ab\ncd").unwrap();
+
+        let fake =
+            FakeLanguageServer::new(OffsetEncoding::Utf16, TextDocumentSyncKind::INCREMENTAL);
+        let (app, mut rx) = create_app(fake);
+        let editor_state = app.state::<EditorState>();
+        let lsp_service = app.state::<LspService<MockRuntime>>();
+        lsp_service.register_language_server(&path).await.unwrap();
+        expect_initialized(&mut rx).await;
+
+        // Multi-line insert after "a" and replace "d", both in pre-edit offsets
+        let changes = [
+            Change {
+                from: 1,
+                to: 1,
+                text: "x\ny\n".to_string(),
+            },
+            Change {
+                from: 4,
+                to: 5,
+                text: "z".to_string(),
+            },
+        ];
+        let tx = editor_state.apply_changes(&path, 0, &changes).unwrap();
+        assert_eq!(tx.doc.text.to_string(), "ax\ny\nb\ncz");
+        let language_server_id = tx.doc.get_language_server_id().unwrap();
+        lsp_service
+            .change_document(&language_server_id, &tx)
+            .await
+            .unwrap();
+
+        match rx.recv().await {
+            Some(FakeMessage::DidChange(params)) => {
+                assert_eq!(params.text_document.version, 1);
+                let changes: Vec<_> = params
+                    .content_changes
+                    .iter()
+                    .map(|c| (c.range.unwrap(), c.text.as_str()))
+                    .collect();
+                assert_eq!(
+                    changes,
+                    vec![
+                        (Range::new(Position::new(1, 1), Position::new(1, 2)), "z"),
+                        (
+                            Range::new(Position::new(0, 1), Position::new(0, 1)),
+                            "x\ny\n"
+                        ),
+                    ]
+                );
+            }
+            msg => panic!("Expected didChange, got {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_full_sync() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("src").join("index.ts");
+        std::fs::write(&path, "abc").unwrap();
+
+        let fake = FakeLanguageServer::new(OffsetEncoding::Utf16, TextDocumentSyncKind::FULL);
+        let (app, mut rx) = create_app(fake);
+        let editor_state = app.state::<EditorState>();
+        let lsp_service = app.state::<LspService<MockRuntime>>();
+        lsp_service.register_language_server(&path).await.unwrap();
+        expect_initialized(&mut rx).await;
+
+        let tx = editor_state
+            .delete_text(
+                &path,
+                doc_version(&editor_state, &path),
+                &Delete { from_a: 0, to_a: 1 },
+            )
+            .unwrap();
+        let language_server_id = tx.doc.get_language_server_id().unwrap();
+        lsp_service
+            .change_document(&language_server_id, &tx)
+            .await
+            .unwrap();
+
+        match rx.recv().await {
+            Some(FakeMessage::DidChange(params)) => {
+                assert_eq!(params.text_document.version, 1);
+                assert_eq!(params.content_changes[0].range, None);
+                assert_eq!(params.content_changes[0].text, "bc");
+            }
+            msg => panic!("Expected didChange, got {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_hover_completion_goto() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("src").join("index.ts");
+        std::fs::write(&path, "😀\nfoo.").unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+        let location = Location::new(uri.clone(), Range::default());
+
+        let mut fake = FakeLanguageServer::new(OffsetEncoding::Utf32, TextDocumentSyncKind::FULL);
+        fake.hover = Some(async_lsp::lsp_types::Hover {
+            contents: HoverContents::Scalar(MarkedString::String("hover".to_string())),
+            range: None,
+        });
+        fake.completion = Some(CompletionResponse::Array(vec![CompletionItem::new_simple(
+            "bar".to_string(),
+            "detail".to_string(),
+        )]));
+        fake.goto = Some(GotoDefinitionResponse::Scalar(location.clone()));
+
+        let (app, mut rx) = create_app(fake);
+        let lsp_service = app.state::<LspService<MockRuntime>>();
+        lsp_service.register_language_server(&path).await.unwrap();
+        expect_initialized(&mut rx).await;
+
+        let hover = lsp_service.hover(&path, 4).await.unwrap();
+        assert_eq!(
+            hover.contents,
+            HoverContents::Scalar(MarkedString::String("hover".to_string()))
+        );
+        match rx.recv().await {
+            Some(FakeMessage::Hover(params)) => {
+                assert_eq!(
+                    params.text_document_position_params.position,
+                    Position::new(1, 1)
+                );
+            }
+            msg => panic!("Expected hover, got {:?}", msg),
+        }
+
+        let completion = lsp_service
+            .completion(&path, 7, "foo.".to_string())
+            .await
+            .unwrap();
+        match completion {
+            CompletionResponse::Array(items) => assert_eq!(items[0].label, "bar"),
+            _ => panic!("Expected completion items"),
+        }
+        match rx.recv().await {
+            Some(FakeMessage::Completion(params)) => {
+                let context = params.context.unwrap();
+                assert_eq!(
+                    context.trigger_kind,
+                    CompletionTriggerKind::TRIGGER_CHARACTER
+                );
+                assert_eq!(context.trigger_character, Some(".".to_string()));
+                assert_eq!(params.text_document_position.position, Position::new(1, 4));
+            }
+            msg => panic!("Expected completion, got {:?}", msg),
+        }
+
+        let goto = lsp_service.goto(&path, 3).await.unwrap();
+        assert_eq!(goto, GotoDefinitionResponse::Scalar(location));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_project_settings() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("src").join("index.ts");
+        std::fs::write(&path, "a  =  1").unwrap();
+
+        let mut fake = FakeLanguageServer::new(OffsetEncoding::Utf16, TextDocumentSyncKind::FULL);
+        fake.formatting = Some(vec![TextEdit::new(
+            Range::new(Position::new(0, 1), Position::new(0, 6)),
+            " = ".to_string(),
+        )]);
+
+        let (app, mut rx) = create_app(fake);
+        let options = json!({"preferences": {"quoteStyle": "double", "semicolons": "remove"}});
+        let patch = json!({"languageServers": {"typescript": {"initializationOptions": options}}});
+        app.state::<SettingsStore>().update(patch).unwrap();
+
+        // Project options are merged over the ones of the app
+        let settings = [
+            "[language_servers.typescript]",
+            "initialization_options = { preferences = { quoteStyle = \"single\" } }",
+        ]
+        .join("\n");
+        std::fs::write(get_test_dir().join(PROJECT_SETTINGS_FILE), settings).unwrap();
+
+        let lsp_service = app.state::<LspService<MockRuntime>>();
+        lsp_service.register_language_server(&path).await.unwrap();
+        match rx.recv().await {
+            Some(FakeMessage::Initialize(params)) => {
+                let options = params.initialization_options.unwrap();
+                assert_eq!(options["preferences"]["quoteStyle"], "single");
+                assert_eq!(options["preferences"]["semicolons"], "remove");
+            }
+            msg => panic!("Expected initialize, got {:?}", msg),
+        }
+
+        let editor_state = app.state::<EditorState>();
+        let doc = editor_state.get_document(&path).await.unwrap();
+        let changes = lsp_service.format_document(&doc, 2, true).await.unwrap();
+        assert_eq!(
+            changes,
+            vec![Change {
+                from: 1,
+                to: 6,
+                text: " = ".to_string()
+            }]
+        );
+        loop {
+            if let Some(FakeMessage::Formatting(params)) = rx.recv().await {
+                assert_eq!(params.options.tab_size, 2);
+                assert!(!params.options.insert_spaces);
+                break;
+            }
+        }
+
+        // Disabled servers are not started
+        let settings = "[language_servers.typescript]\nenabled = false";
+        std::fs::write(get_test_dir().join(PROJECT_SETTINGS_FILE), settings).unwrap();
+        app.state::<ProjectSettingsStore>().reload(&get_test_dir());
+        let other = get_test_dir().join("other.ts");
+        std::fs::write(&other, "").unwrap();
+        assert!(lsp_service.register_language_server(&other).await.is_err());
+    }
+
+    #[test]
+    fn test_resolve_language_server() {
+        let worktree = Path::new("/repo");
+        let project = ProjectSettings {
+            worktree_path: Some(worktree.to_path_buf()),
+            language_server: ProjectLanguageServerSettings {
+                command: Some("ra".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut settings = Settings::default();
+        settings.language_servers.insert(
+            "rust".to_string(),
+            LanguageServerSettings {
+                command: Some("rust-analyzer".to_string()),
+                ..Default::default()
+            },
+        );
+
+        // Untrusted projects can't replace the command
+        let resolved = resolve_language_server(&settings, "rust", project.clone());
+        assert_eq!(resolved.command.as_deref(), Some("rust-analyzer"));
+        assert_eq!(resolved.enabled, None);
+
+        settings.trusted_projects.push(worktree.to_path_buf());
+        let resolved = resolve_language_server(&settings, "rust", project.clone());
+        assert_eq!(resolved.command.as_deref(), Some("ra"));
+
+        // Either side can turn the server off
+        let mut disabled = project;
+        disabled.language_server.enabled = Some(false);
+        settings.language_servers.get_mut("rust").unwrap().enabled = Some(true);
+        let resolved = resolve_language_server(&settings, "rust", disabled);
+        assert_eq!(resolved.enabled, Some(false));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_shutdown() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("src").join("index.ts");
+
+        let fake = FakeLanguageServer::new(OffsetEncoding::Utf16, TextDocumentSyncKind::FULL);
+        let (app, mut rx) = create_app(fake);
+        let lsp_service = app.state::<LspService<MockRuntime>>();
+        lsp_service.register_language_server(&path).await.unwrap();
+        expect_initialized(&mut rx).await;
+
+        app.state::<LspRegistry>().shutdown().await;
+        assert!(matches!(rx.recv().await, Some(FakeMessage::Shutdown)));
+        assert!(matches!(rx.recv().await, Some(FakeMessage::Exit)));
+    }
+}
@@ -0,0 +1,219 @@
+use std::ops::ControlFlow;
+
+use async_lsp::lsp_types::notification::{
+    DidChangeTextDocument, DidOpenTextDocument, DidRenameFiles, Exit, Initialized,
+};
+use async_lsp::lsp_types::request::{
+    Completion, Formatting, GotoDefinition, HoverRequest, Initialize, Shutdown, WillRenameFiles,
+};
+use async_lsp::lsp_types::{
+    CompletionOptions, CompletionParams, CompletionResponse, DidChangeTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentFormattingParams, FileOperationFilter, FileOperationPattern,
+    FileOperationRegistrationOptions, GotoDefinitionResponse, Hover, HoverParams,
+    HoverProviderCapability, InitializeParams, InitializeResult, OneOf, PositionEncodingKind,
+    RenameFilesParams, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
+    TextEdit, WorkspaceEdit, WorkspaceFileOperationsServerCapabilities,
+    WorkspaceServerCapabilities,
+};
+use async_lsp::router::Router;
+use async_lsp::ClientSocket;
+use futures::AsyncReadExt;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tower::ServiceBuilder;
+
+use super::registry::LspRegistry;
+use super::server::LspServer;
+use super::service::OffsetEncoding;
+
+const MEMORY_CHANNEL_SIZE: usize = 64 << 10;
+
+// Everything the fake server received from the client, in order
+#[derive(Debug)]
+pub enum FakeMessage {
+    Initialize(Box<InitializeParams>),
+    Initialized,
+    DidOpen(DidOpenTextDocumentParams),
+    DidChange(DidChangeTextDocumentParams),
+    Hover(HoverParams),
+    Completion(CompletionParams),
+    WillRenameFiles(RenameFilesParams),
+    DidRenameFiles(RenameFilesParams),
+    Formatting(DocumentFormattingParams),
+    Shutdown,
+    Exit,
+}
+
+struct FakeState {
+    tx: UnboundedSender<FakeMessage>,
+    // The main loop stops once all client sockets are dropped
+    _client: ClientSocket,
+}
+
+// In-process language server that answers with scripted responses
+#[derive(Clone)]
+pub struct FakeLanguageServer {
+    pub offset_encoding: OffsetEncoding,
+    pub sync_kind: TextDocumentSyncKind,
+    pub hover: Option<Hover>,
+    pub completion: Option<CompletionResponse>,
+    pub goto: Option<GotoDefinitionResponse>,
+    pub will_rename: Option<WorkspaceEdit>,
+    pub formatting: Option<Vec<TextEdit>>,
+}
+
+impl FakeLanguageServer {
+    pub fn new(offset_encoding: OffsetEncoding, sync_kind: TextDocumentSyncKind) -> Self {
+        Self {
+            offset_encoding,
+            sync_kind,
+            hover: None,
+            completion: None,
+            goto: None,
+            will_rename: None,
+            formatting: None,
+        }
+    }
+
+    pub fn initialize_result(&self) -> InitializeResult {
+        let position_encoding = match self.offset_encoding {
+            OffsetEncoding::Utf8 => PositionEncodingKind::UTF8,
+            OffsetEncoding::Utf16 => PositionEncodingKind::UTF16,
+            OffsetEncoding::Utf32 => PositionEncodingKind::UTF32,
+        };
+
+        let rename_options = FileOperationRegistrationOptions {
+            filters: vec![FileOperationFilter {
+                scheme: Some("file".to_string()),
+                pattern: FileOperationPattern {
+                    glob: "**/*.ts".to_string(),
+                    ..Default::default()
+                },
+            }],
+        };
+
+        InitializeResult {
+            capabilities: ServerCapabilities {
+                position_encoding: Some(position_encoding),
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(self.sync_kind)),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(vec![".".to_string()]),
+                    ..CompletionOptions::default()
+                }),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: None,
+                    file_operations: Some(WorkspaceFileOperationsServerCapabilities {
+                        will_rename: Some(rename_options.clone()),
+                        did_rename: Some(rename_options),
+                        ..Default::default()
+                    }),
+                }),
+                ..ServerCapabilities::default()
+            },
+            server_info: None,
+        }
+    }
+
+    // Starts the fake server on an in-memory pipe and returns the connected client
+    pub fn spawn(&self, tx: UnboundedSender<FakeMessage>) -> (LspServer, JoinHandle<()>) {
+        let fake = self.clone();
+        let (server_main, _) = async_lsp::MainLoop::new_server(|client| {
+            let mut router = Router::new(FakeState {
+                tx,
+                _client: client,
+            });
+            router
+                .request::<Initialize, _>(move |st, params| {
+                    let _ = st.tx.send(FakeMessage::Initialize(Box::new(params)));
+                    let result = fake.initialize_result();
+                    async move { Ok(result) }
+                })
+                .request::<HoverRequest, _>({
+                    let hover = self.hover.clone();
+                    move |st, params| {
+                        let _ = st.tx.send(FakeMessage::Hover(params));
+                        let hover = hover.clone();
+                        async move { Ok(hover) }
+                    }
+                })
+                .request::<Completion, _>({
+                    let completion = self.completion.clone();
+                    move |st, params| {
+                        let _ = st.tx.send(FakeMessage::Completion(params));
+                        let completion = completion.clone();
+                        async move { Ok(completion) }
+                    }
+                })
+                .request::<GotoDefinition, _>({
+                    let goto = self.goto.clone();
+                    move |_, _| {
+                        let goto = goto.clone();
+                        async move { Ok(goto) }
+                    }
+                })
+                .request::<WillRenameFiles, _>({
+                    let will_rename = self.will_rename.clone();
+                    move |st, params| {
+                        let _ = st.tx.send(FakeMessage::WillRenameFiles(params));
+                        let will_rename = will_rename.clone();
+                        async move { Ok(will_rename) }
+                    }
+                })
+                .request::<Formatting, _>({
+                    let formatting = self.formatting.clone();
+                    move |st, params| {
+                        let _ = st.tx.send(FakeMessage::Formatting(params));
+                        let formatting = formatting.clone();
+                        async move { Ok(formatting) }
+                    }
+                })
+                .request::<Shutdown, _>(|st, _| {
+                    let _ = st.tx.send(FakeMessage::Shutdown);
+                    async move { Ok(()) }
+                })
+                .notification::<Initialized>(|st, _| {
+                    let _ = st.tx.send(FakeMessage::Initialized);
+                    ControlFlow::Continue(())
+                })
+                .notification::<DidOpenTextDocument>(|st, params| {
+                    let _ = st.tx.send(FakeMessage::DidOpen(params));
+                    ControlFlow::Continue(())
+                })
+                .notification::<DidChangeTextDocument>(|st, params| {
+                    let _ = st.tx.send(FakeMessage::DidChange(params));
+                    ControlFlow::Continue(())
+                })
+                .notification::<DidRenameFiles>(|st, params| {
+                    let _ = st.tx.send(FakeMessage::DidRenameFiles(params));
+                    ControlFlow::Continue(())
+                })
+                .notification::<Exit>(|st, _| {
+                    let _ = st.tx.send(FakeMessage::Exit);
+                    ControlFlow::Break(Ok(()))
+                })
+                .unhandled_notification(|_, _| ControlFlow::Continue(()));
+
+            ServiceBuilder::new().service(router)
+        });
+
+        let (client_stream, server_stream) = tokio::io::duplex(MEMORY_CHANNEL_SIZE);
+        let (server_rx, server_tx) = server_stream.compat().split();
+        tokio::spawn(async move {
+            let _ = server_main.run_buffered(server_rx, server_tx).await;
+        });
+
+        let (client_rx, client_tx) = client_stream.compat().split();
+        LspServer::from_io(client_rx, client_tx)
+    }
+
+    // Creates a registry that starts this fake for every language server id
+    pub fn registry(self) -> (LspRegistry, UnboundedReceiver<FakeMessage>) {
+        let (tx, rx) = unbounded_channel();
+        let registry = LspRegistry::with_factory(move |_, _| Ok(self.spawn(tx.clone())));
+        (registry, rx)
+    }
+}
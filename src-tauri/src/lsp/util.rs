@@ -35,6 +35,32 @@ pub fn pos_to_lsp_pos(doc: &Rope, pos: usize, offset_encoding: OffsetEncoding) -
     }
 }
 
+// Converts an LSP position to a UTF-16 offset. Positions past the end of a
+// line or the document are clamped.
+pub fn lsp_pos_to_pos(doc: &Rope, pos: Position, offset_encoding: OffsetEncoding) -> usize {
+    let line = pos.line as usize;
+    if line >= doc.len_lines() {
+        return doc.len_utf16_cu();
+    }
+
+    // Without the line break
+    let line_text = doc.line(line);
+    let mut len = line_text.len_chars();
+    while len > 0 && matches!(line_text.char(len - 1), '\n' | '\r') {
+        len -= 1;
+    }
+    let line_text = line_text.slice(..len);
+
+    let col = pos.character as usize;
+    let chars = match offset_encoding {
+        OffsetEncoding::Utf8 => line_text.byte_to_char(col.min(line_text.len_bytes())),
+        OffsetEncoding::Utf16 => line_text.utf16_cu_to_char(col.min(line_text.len_utf16_cu())),
+        OffsetEncoding::Utf32 => col.min(line_text.len_chars()),
+    };
+
+    doc.char_to_utf16_cu(doc.line_to_char(line) + chars)
+}
+
 pub fn get_offset_encoding(config: &InitializeResult) -> OffsetEncoding {
     config
         .capabilities
@@ -67,7 +93,7 @@ mod tests {
     use async_lsp::lsp_types::Position;
     use ropey::Rope;
 
-    use super::pos_to_lsp_pos;
+    use super::{lsp_pos_to_pos, pos_to_lsp_pos};
 
     use crate::lsp::service::OffsetEncoding;
 
@@ -93,4 +119,20 @@ mod tests {
         let lsp_pos = pos_to_lsp_pos(&doc, 1, OffsetEncoding::Utf16);
         assert_eq!(lsp_pos, Position::new(0, 1));
     }
+
+    #[test]
+    fn test_lsp_pos_to_pos() {
+        let doc = Rope::from_str("x\na😀b");
+        let cases = [
+            (OffsetEncoding::Utf8, Position::new(1, 5), 5),
+            (OffsetEncoding::Utf16, Position::new(1, 3), 5),
+            (OffsetEncoding::Utf32, Position::new(1, 2), 5),
+            (OffsetEncoding::Utf16, Position::new(0, 10), 1),
+            (OffsetEncoding::Utf16, Position::new(5, 0), 6),
+        ];
+
+        for (offset_encoding, pos, expected) in cases {
+            assert_eq!(lsp_pos_to_pos(&doc, pos, offset_encoding), expected);
+        }
+    }
 }
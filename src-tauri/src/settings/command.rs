@@ -0,0 +1,91 @@
+use serde::Serialize;
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use tracing::error;
+
+use crate::copilot::lsp_service::CopilotLspService;
+use crate::editor::editor_state::EditorState;
+use crate::editor::history::LocalHistory;
+
+use super::store::{Settings, SettingsStore, SETTINGS_CHANGED};
+
+// The revision is set by the frontend on its own updates, so that it can
+// ignore their echo
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangedSettings {
+    #[serde(flatten)]
+    pub settings: Settings,
+    pub revision: Option<u64>,
+}
+
+#[tauri::command]
+pub async fn get_settings<R: Runtime>(app_handle: AppHandle<R>) -> tauri::Result<Settings> {
+    let store = app_handle.state::<SettingsStore>();
+    Ok(store.get())
+}
+
+#[tauri::command]
+pub async fn update_settings<R: Runtime>(
+    settings: Value,
+    revision: Option<u64>,
+    app_handle: AppHandle<R>,
+) -> tauri::Result<Settings> {
+    let settings = save_settings(&app_handle, settings, revision).await?;
+    Ok(settings)
+}
+
+// Saves the patch, applies the changes to the services and notifies the
+// frontend
+pub async fn save_settings<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    patch: Value,
+    revision: Option<u64>,
+) -> anyhow::Result<Settings> {
+    let store = app_handle.state::<SettingsStore>();
+    let previous = store.get();
+    let settings = store.update(patch)?;
+
+    apply_settings(app_handle, &previous, &settings).await;
+    app_handle.emit(
+        SETTINGS_CHANGED,
+        ChangedSettings {
+            settings: settings.clone(),
+            revision,
+        },
+    )?;
+    Ok(settings)
+}
+
+// Settings that are only read on startup are not applied
+pub async fn apply_settings<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    previous: &Settings,
+    settings: &Settings,
+) {
+    if previous.root_markers != settings.root_markers {
+        let editor_state = app_handle.state::<EditorState>();
+        *editor_state.root_markers.write().unwrap() = settings.root_markers.clone();
+    }
+
+    if previous.history != settings.history {
+        let history = app_handle.state::<LocalHistory>();
+        if let Err(e) = history.set_retention(settings.history.clone()) {
+            error!("Could not apply history retention: {:?}", e);
+        }
+    }
+
+    // The frontend may have started or stopped copilot already
+    if previous.copilot.enabled != settings.copilot.enabled {
+        let copilot_service = app_handle.state::<CopilotLspService<R>>();
+        let running = *copilot_service.enabled.lock().await;
+        let result = match (settings.copilot.enabled, running) {
+            (true, false) => copilot_service.start().await,
+            (false, true) => copilot_service.disconnect().await,
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            error!("Could not apply copilot settings: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, error};
+
+use crate::editor::editor_state::{LARGE_FILE_SIZE, ROOT_MARKERS};
+use crate::editor::history::Retention;
+use crate::fs::write::write_atomic;
+
+pub const SETTINGS_FILE: &str = "settings.json";
+pub const SETTINGS_CHANGED: &str = "settings-changed";
+pub const SETTINGS_VERSION: u64 = 1;
+
+// Upgrades the settings of one version to the next, the first one from
+// version 1 to 2
+type Migration = fn(&mut Value);
+
+const MIGRATIONS: &[Migration] = &[];
+
+// Every version but the first needs a migration
+const _: () = assert!(SETTINGS_VERSION as usize == MIGRATIONS.len() + 1);
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CopilotSettings {
+    // Starts the language server on app start
+    pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LanguageServerSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    // Shell command that replaces the built-in one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initialization_options: Option<Value>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ThemeSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub main: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub main_dark: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub main_light: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_dark: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_light: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PrettierSettings {
+    pub print_width: u32,
+    pub tab_width: u32,
+    pub use_tabs: bool,
+    pub semi: bool,
+    pub single_quote: bool,
+    pub bracket_spacing: bool,
+}
+
+impl Default for PrettierSettings {
+    fn default() -> Self {
+        Self {
+            print_width: 80,
+            tab_width: 2,
+            use_tabs: false,
+            semi: false,
+            single_quote: true,
+            bracket_spacing: false,
+        }
+    }
+}
+
+// The config of the frontend, same defaults as in `types.ts`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct UiSettings {
+    pub theme: ThemeSettings,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font: Option<String>,
+    pub font_size: f64,
+    pub content_width: f64,
+    pub always_on_top: bool,
+    pub typewriter_mode: bool,
+    pub spellcheck: bool,
+    pub prettier: PrettierSettings,
+}
+
+impl Default for UiSettings {
+    fn default() -> Self {
+        Self {
+            theme: ThemeSettings::default(),
+            font: None,
+            font_size: 14.0,
+            content_width: 600.0,
+            always_on_top: false,
+            typewriter_mode: false,
+            spellcheck: true,
+            prettier: PrettierSettings::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Settings {
+    pub version: u64,
+    // Applied on restart, like the `--verbose` flag
+    pub verbose: bool,
+    // Applied on restart
+    pub large_file_size: u64,
+    pub root_markers: Vec<String>,
+    pub copilot: CopilotSettings,
+    pub history: Retention,
+    // By language id, e.g. `typescript`
+    pub language_servers: HashMap<String, LanguageServerSettings>,
+    // Worktrees whose project settings may replace language server commands
+    pub trusted_projects: Vec<PathBuf>,
+    // None until the frontend stored its config for the first time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ui: Option<UiSettings>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            verbose: false,
+            large_file_size: LARGE_FILE_SIZE,
+            root_markers: ROOT_MARKERS.iter().map(|m| m.to_string()).collect(),
+            copilot: CopilotSettings::default(),
+            history: Retention::default(),
+            language_servers: HashMap::new(),
+            trusted_projects: Vec::new(),
+            ui: None,
+        }
+    }
+}
+
+// Keeps the app settings in a JSON file in the app config dir. Invalid files
+// are logged and replaced on the next update. Files of a newer app version
+// are never overwritten.
+pub struct SettingsStore {
+    path: PathBuf,
+    settings: RwLock<Settings>,
+    read_only: bool,
+}
+
+impl SettingsStore {
+    pub fn new(path: PathBuf) -> Self {
+        let mut read_only = false;
+        let settings = match read(&path) {
+            Ok(Some(value)) if version(&value) > SETTINGS_VERSION => {
+                error!(
+                    "Settings are from a newer version (path={:?}, version={})",
+                    path,
+                    version(&value)
+                );
+                read_only = true;
+                Settings::default()
+            }
+            Ok(Some(value)) => from_value(migrate(value, MIGRATIONS)).unwrap_or_else(|e| {
+                error!("Invalid settings (path={:?}): {:?}", path, e);
+                Settings::default()
+            }),
+            Ok(None) => Settings::default(),
+            Err(e) => {
+                error!("Could not read settings (path={:?}): {:?}", path, e);
+                Settings::default()
+            }
+        };
+
+        debug!("Loaded settings (path={:?}, settings={:?})", path, settings);
+        Self {
+            path,
+            settings: RwLock::new(settings),
+            read_only,
+        }
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.read().unwrap().clone()
+    }
+
+    // Merges the patch into the settings, e.g. `{"copilot": {"enabled": true}}`,
+    // and saves them. Nothing is saved if the result is invalid. The `ui`
+    // config is replaced as a whole, so that the frontend can clear keys.
+    pub fn update(&self, patch: Value) -> anyhow::Result<Settings> {
+        if self.read_only {
+            return Err(anyhow!(
+                "Settings are read-only because they are from a newer version"
+            ));
+        }
+
+        let mut settings = self.settings.write().unwrap();
+        let mut value = serde_json::to_value(&*settings)?;
+        if patch.get("ui").is_some() {
+            value["ui"] = Value::Null;
+        }
+        merge(&mut value, patch);
+        let updated = from_value(value)?;
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(&self.path, |w| {
+            serde_json::to_writer_pretty(w, &updated).map_err(std::io::Error::other)
+        })?;
+
+        *settings = updated.clone();
+        Ok(updated)
+    }
+}
+
+fn read(path: &Path) -> anyhow::Result<Option<Value>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Files without a version are from the first one
+fn version(value: &Value) -> u64 {
+    value["version"].as_u64().unwrap_or(1)
+}
+
+fn migrate(mut value: Value, migrations: &[Migration]) -> Value {
+    let from = version(&value) as usize;
+    for (i, migration) in migrations.iter().enumerate().skip(from.saturating_sub(1)) {
+        debug!("Migrate settings to version {}", i + 2);
+        migration(&mut value);
+    }
+    value
+}
+
+fn from_value(value: Value) -> anyhow::Result<Settings> {
+    let mut settings: Settings = serde_json::from_value(value)?;
+    settings.version = SETTINGS_VERSION;
+    Ok(settings)
+}
+
+// Objects are merged recursively, all other values are replaced
+fn merge(target: &mut Value, patch: Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                merge(target.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use serial_test::serial;
+
+    use crate::editor::testutil::{create_test_workspace, get_test_dir};
+
+    use super::{migrate, Migration, Settings, SettingsStore, SETTINGS_FILE};
+
+    #[test]
+    #[serial]
+    fn test_settings_store() {
+        create_test_workspace(true);
+        let path = get_test_dir().join("config").join(SETTINGS_FILE);
+
+        let store = SettingsStore::new(path.clone());
+        assert_eq!(store.get(), Settings::default());
+
+        let settings = store
+            .update(json!({"copilot": {"enabled": true}, "history": {"maxCount": 10}}))
+            .unwrap();
+        assert!(settings.copilot.enabled);
+        assert_eq!(settings.history.max_count, 10);
+        assert_eq!(settings.history.max_age_days, 30);
+
+        // Invalid updates are not saved
+        assert!(store.update(json!({"verbose": "yes"})).is_err());
+        assert!(!store.get().verbose);
+
+        // Keys missing in the ui config are cleared
+        let settings = store.update(json!({"ui": {"font": "mono"}})).unwrap();
+        assert_eq!(settings.ui.unwrap().font.as_deref(), Some("mono"));
+        let settings = store.update(json!({"ui": {"fontSize": 16.0}})).unwrap();
+        let ui = settings.ui.clone().unwrap();
+        assert_eq!(ui.font, None);
+        assert_eq!(ui.font_size, 16.0);
+
+        let store = SettingsStore::new(path.clone());
+        assert_eq!(store.get(), settings);
+
+        // Unknown fields are dropped and invalid files replaced on update
+        std::fs::write(&path, r#"{"version": 1, "verbose": true, "old": 1}"#).unwrap();
+        assert!(SettingsStore::new(path.clone()).get().verbose);
+        std::fs::write(&path, "{").unwrap();
+        let store = SettingsStore::new(path.clone());
+        assert_eq!(store.get(), Settings::default());
+        assert!(store.update(json!({"verbose": true})).is_ok());
+
+        // Files of newer versions are kept
+        std::fs::write(&path, r#"{"version": 99}"#).unwrap();
+        let store = SettingsStore::new(path.clone());
+        assert!(store.update(json!({"verbose": true})).is_err());
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            r#"{"version": 99}"#
+        );
+    }
+
+    #[test]
+    fn test_migrate() {
+        let migrations: &[Migration] = &[
+            |v| v["copilot"] = json!({"enabled": v["copilot"]}),
+            |v| v["verbose"] = Value::Bool(true),
+        ];
+
+        let value = migrate(json!({"copilot": true}), migrations);
+        assert_eq!(value["copilot"]["enabled"], true);
+        assert_eq!(value["verbose"], true);
+
+        let value = migrate(json!({"version": 2, "copilot": true}), migrations);
+        assert_eq!(value["copilot"], true);
+        assert_eq!(value["verbose"], true);
+    }
+}
@@ -12,6 +12,7 @@ export const Keymap = () => {
     store,
     appService,
     editorService,
+    codeService,
     fileService,
     collabService,
     canvasService,
@@ -71,7 +72,13 @@ export const Keymap = () => {
 
   const onSave = async () => {
     const currentFile = fileService.currentFile
-    if (!isTauri() || !currentFile || currentFile?.path) return false
+    if (!isTauri() || !currentFile) return false
+    if (currentFile.path) {
+      if (!currentFile.code) return false
+      await codeService.save(currentFile)
+      return true
+    }
+
     const path = await saveFile(currentFile)
     if (path) await fileService.updatePath(currentFile.id, path)
   }
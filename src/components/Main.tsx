@@ -1,10 +1,11 @@
 import {Route, Router, type RouteSectionProps} from '@solidjs/router'
-import {createEffect, ErrorBoundary, on, onMount, Show, untrack} from 'solid-js'
+import {createEffect, ErrorBoundary, on, onCleanup, onMount, Show, untrack} from 'solid-js'
 import {DarkMode} from '@/components/DarkMode'
 import {DropFile} from '@/components/DropFile'
 import {Keymap} from '@/components/Keymap'
 import {DragArea, Layout, PageContent} from '@/components/Layout'
 import {MouseCursor} from '@/components/MouseCursor'
+import {RecoverDocuments} from '@/components/RecoverDocuments'
 import {Menu} from '@/components/menu/Menu'
 import {InfoNavbar} from '@/components/navbar/InfoNavbar'
 import {CanvasPage, NewCanvasPage} from '@/components/pages/CanvasPage'
@@ -18,12 +19,19 @@ import {Variables} from '@/components/Variables'
 import {DB} from '@/db'
 import {isTauri} from '@/env'
 import {useBeforeLeave} from '@/hooks/use-before-leave'
-import {setAlwaysOnTop} from '@/remote/app'
+import {onSettingsChanged, setAlwaysOnTop} from '@/remote/app'
 import {startLanguageServer} from '@/remote/copilot'
+import {
+  onDocumentChangedExternally,
+  onDocumentConflict,
+  onDocumentDeleted,
+  onDocumentRenamed,
+  toRelativePath,
+} from '@/remote/editor'
 import {info} from '@/remote/log'
 import {show, updateWindow} from '@/remote/window'
 import {createCtrl} from '@/services'
-import {StateContext} from '@/state'
+import {createConfig, StateContext} from '@/state'
 import type {State} from '@/types'
 import {ChatSidebar} from './assistant/ChatSidebar'
 import {Dialogs} from './dialog/Dialogs'
@@ -48,6 +56,40 @@ export const Main = (props: Props) => {
     onMount(async () => {
       ctrl.appService.layoutRef = layoutRef
 
+      if (isTauri()) {
+        const unlisten = onSettingsChanged((settings) => {
+          if (settings.ui) {
+            ctrl.configService.setConfig(createConfig(settings.ui), settings.revision)
+          }
+        })
+        onCleanup(() => void unlisten.then((fn) => fn()))
+
+        const unlistenDocuments = Promise.all([
+          onDocumentChangedExternally(async (doc) => {
+            const file = await ctrl.fileService.findFileByPath(doc.path)
+            if (!file) return
+            if (file.code) await ctrl.codeService.reload(file, doc)
+            else await ctrl.editorService.reload(file)
+          }),
+          onDocumentConflict(async (doc) => {
+            const path = await toRelativePath(doc.path)
+            ctrl.dialogService.toast({
+              message: `${path} changed on disk, saving overwrites the changes`,
+            })
+          }),
+          onDocumentDeleted(async (doc) => {
+            const path = await toRelativePath(doc.path)
+            ctrl.dialogService.toast({message: `${path} was deleted on disk`})
+          }),
+          // Files renamed in the app already have their new path
+          onDocumentRenamed(async ({from, document}) => {
+            const file = await ctrl.fileService.findFileByPath(from)
+            if (file) await ctrl.fileService.updatePath(file.id, document.path)
+          }),
+        ])
+        onCleanup(() => void unlistenDocuments.then((fns) => fns.forEach((fn) => fn())))
+      }
+
       if (isTauri() && ctrl.store.window) {
         await updateWindow(ctrl.store.window)
       }
@@ -122,6 +164,7 @@ export const Main = (props: Props) => {
             <ResizeWindow />
             <DarkMode />
             <Dialogs />
+            <RecoverDocuments />
             <Title />
           </Layout>
         </ErrorBoundary>
@@ -0,0 +1,42 @@
+import {onMount} from 'solid-js'
+import {isTauri} from '@/env'
+import {useConfirmDialog} from '@/hooks/use-confirm-dialog'
+import {
+  discardRecoverableDocument,
+  type JournalEntry,
+  listRecoverableDocuments,
+  restoreDocument,
+  toRelativePath,
+} from '@/remote/editor'
+import {error} from '@/remote/log'
+
+// Offers the unsaved changes of the last session one document at a time.
+// Restored documents reach open editors as external changes.
+export const RecoverDocuments = () => {
+  const showConfirmDialog = useConfirmDialog()
+
+  const offer = async (entries: JournalEntry[]) => {
+    const [entry, ...rest] = entries
+    if (!entry) return
+    const path = await toRelativePath(entry.path)
+    showConfirmDialog({
+      title: 'Recover unsaved changes',
+      content: `${path} has unsaved changes from the last session. Restore them?`,
+      onConfirm: async () => {
+        await restoreDocument(entry.path).catch((e) => error('Could not restore document', e))
+        await offer(rest)
+      },
+      onCancel: async () => {
+        await discardRecoverableDocument(entry.path)
+        await offer(rest)
+      },
+    })
+  }
+
+  onMount(async () => {
+    if (!isTauri()) return
+    await offer(await listRecoverableDocuments())
+  })
+
+  return null
+}
@@ -1,10 +1,12 @@
-import {Match, Show, Suspense, Switch} from 'solid-js'
+import {For, Match, Show, Suspense, Switch} from 'solid-js'
 import {getLanguageNames} from '@/codemirror/highlight'
 import {useConfirmDialog} from '@/hooks/use-confirm-dialog'
 import {useDialog} from '@/hooks/use-dialog'
 import {useInputLine} from '@/hooks/use-input-line'
 import {useTitle} from '@/hooks/use-title'
+import {listHistory, restoreHistory, type Snapshot} from '@/remote/editor'
 import {CanvasService} from '@/services/CanvasService'
+import type {Dialog} from '@/services/DialogService'
 import {isCodeFile, isLocalFile, useState} from '@/state'
 import {Button} from '../Button'
 import {DialogList, TooltipButton, TooltipDivider} from '../dialog/Style'
@@ -14,7 +16,8 @@ import {IconPrettier} from '../icons/Logo'
 import {IconClose, IconDelete, IconEdit, IconHistory, IconLanguage} from '../icons/Ui'
 
 export const CurrentFileButton = () => {
-  const {codeService, canvasService, deleteService, fileService, locationService} = useState()
+  const {codeService, canvasService, deleteService, dialogService, fileService, locationService} =
+    useState()
   const showInputLine = useInputLine()
   const showConfirmDialog = useConfirmDialog()
 
@@ -37,6 +40,12 @@ export const CurrentFileButton = () => {
         <IconEdit />
         Rename
       </TooltipButton>
+      <Show when={fileService.currentFile?.path}>
+        <TooltipButton onClick={onShowHistory}>
+          <IconHistory />
+          Local History
+        </TooltipButton>
+      </Show>
       <Switch>
         <Match when={fileService.currentFile?.deleted}>
           <TooltipButton onClick={onRestore}>
@@ -64,13 +73,53 @@ export const CurrentFileButton = () => {
     component: Tooltip,
   })
 
+  // Snapshot ids are their creation time in milliseconds
+  const HistoryTooltip = (p: {dialog: Dialog<Snapshot[]>}) => (
+    <DialogList>
+      <For each={p.dialog.state}>
+        {(snapshot) => (
+          <TooltipButton onClick={() => onRestoreHistory(snapshot)}>
+            <IconHistory />
+            {new Date(snapshot.id).toLocaleString()}
+          </TooltipButton>
+        )}
+      </For>
+    </DialogList>
+  )
+
+  const [showHistory, closeHistory] = useDialog<Snapshot[]>({
+    component: HistoryTooltip,
+  })
+
+  let anchor: HTMLElement | undefined
+
   const focus = () => {
     fileService.currentFile?.editorView?.focus()
     fileService.currentFile?.codeEditorView?.focus()
   }
 
   const onOpen = (e: MouseEvent) => {
-    showTooltip({anchor: e.currentTarget as HTMLElement})
+    anchor = e.currentTarget as HTMLElement
+    showTooltip({anchor})
+  }
+
+  const onShowHistory = async () => {
+    const path = fileService.currentFile?.path
+    if (!path) return
+    closeTooltip()
+    const snapshots = await listHistory(path)
+    if (snapshots.length === 0) {
+      dialogService.toast({message: 'No local history for this file'})
+      return
+    }
+    showHistory({anchor, state: snapshots})
+  }
+
+  // The restored text reaches the editor as an external change
+  const onRestoreHistory = async (snapshot: Snapshot) => {
+    const path = fileService.currentFile?.path
+    closeHistory()
+    if (path) await restoreHistory(path, snapshot.id)
   }
 
   const onRename = async () => {
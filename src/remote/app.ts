@@ -1,7 +1,9 @@
 import {invoke} from '@tauri-apps/api/core'
+import {listen, type UnlistenFn} from '@tauri-apps/api/event'
 import {getCurrentWindow} from '@tauri-apps/api/window'
 import {open as shellOpen} from '@tauri-apps/plugin-shell'
-import type {Args} from '@/types'
+import type {Args, Config} from '@/types'
+import type {Retention} from './editor'
 
 export const getArgs = async (): Promise<Args> => {
   return invoke('get_args')
@@ -31,3 +33,50 @@ export const setFullscreen = async (status: boolean): Promise<void> => {
 export const open = async (href: string) => {
   await shellOpen(href)
 }
+
+export interface LanguageServerSettings {
+  enabled?: boolean
+  command?: string
+  initializationOptions?: unknown
+}
+
+export interface Settings {
+  version: number
+  verbose: boolean
+  largeFileSize: number
+  rootMarkers: string[]
+  copilot: {enabled: boolean}
+  history: Retention
+  // By language id, e.g. `typescript`
+  languageServers: Record<string, LanguageServerSettings>
+  // Worktrees whose project settings may replace language server commands
+  trustedProjects: string[]
+  ui?: Config
+}
+
+export type SettingsPatch = {
+  [K in keyof Settings]?: Settings[K] extends object ? Partial<Settings[K]> : Settings[K]
+}
+
+export const getSettings = async (): Promise<Settings> => {
+  return invoke('get_settings')
+}
+
+// `revision` is sent back with the change event of this update. The `ui`
+// config replaces the stored one as a whole.
+export const updateSettings = async (
+  settings: SettingsPatch,
+  revision?: number,
+): Promise<Settings> => {
+  return invoke('update_settings', {settings, revision})
+}
+
+export interface ChangedSettings extends Settings {
+  revision?: number
+}
+
+export const onSettingsChanged = async (
+  fn: (settings: ChangedSettings) => void,
+): Promise<UnlistenFn> => {
+  return listen<ChangedSettings>('settings-changed', (event) => fn(event.payload))
+}
@@ -33,8 +33,8 @@ type CopilotCompletion = {completions: {text: string; displayText: string}[]}
 export const copilotCompletion = async (
   path: string | undefined,
   pos: number,
-  tabWidth: number,
-  useTabs: boolean,
+  tabWidth?: number,
+  useTabs?: boolean,
 ): Promise<CopilotCompletion> => {
   return await invoke('copilot_completion', {path, pos, tabWidth, useTabs})
 }
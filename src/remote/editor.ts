@@ -1,4 +1,5 @@
-import {invoke} from '@tauri-apps/api/core'
+import {type Channel, invoke} from '@tauri-apps/api/core'
+import {listen, type UnlistenFn} from '@tauri-apps/api/event'
 import * as dialog from '@tauri-apps/plugin-dialog'
 import * as fs from '@tauri-apps/plugin-fs'
 import {isTauri} from '@/env'
@@ -6,12 +7,150 @@ import {serialize} from '@/prosemirror/markdown-serialize'
 import type {File} from '@/types'
 import {debug} from './log'
 
+export interface FileFormat {
+  encoding: string
+  lineEnding: 'lf' | 'crlf'
+  bom: boolean
+  finalNewline: boolean
+}
+
+export interface EditorConfig {
+  indentStyle?: 'tab' | 'space'
+  indentSize?: number
+  tabWidth?: number
+  endOfLine?: 'lf' | 'crlf'
+  charset?: 'latin1' | 'utf8' | 'utf8Bom' | 'utf16be' | 'utf16le'
+  trimTrailingWhitespace?: boolean
+  insertFinalNewline?: boolean
+}
+
 interface Document {
   path: string
   worktreePath?: string
   language?: string
+  format: FileFormat
   lastModified: Date
+  diskModified?: Date
   version: number
+  savedVersion: number
+  isDirty: boolean
+  conflicted: boolean
+  largeFile: boolean
+  editorConfig: EditorConfig
+}
+
+export interface Lines {
+  start: number
+  lines: string[]
+  total: number
+}
+
+export interface Change {
+  from: number
+  to: number
+  text: string
+}
+
+export type EditError =
+  | {kind: 'versionConflict'; path: string; version: number; currentVersion: number; text: string}
+  | {kind: 'other'; message: string}
+
+// The text is missing for large files
+export interface ChangedDocument extends Document {
+  text?: string
+}
+
+export const onDocumentChangedExternally = async (
+  fn: (doc: ChangedDocument) => void,
+): Promise<UnlistenFn> => {
+  return listen<ChangedDocument>('document-changed-externally', (event) => fn(event.payload))
+}
+
+export const onDocumentConflict = async (fn: (doc: Document) => void): Promise<UnlistenFn> => {
+  return listen<Document>('document-conflict', (event) => fn(event.payload))
+}
+
+export const onDocumentDeleted = async (fn: (doc: Document) => void): Promise<UnlistenFn> => {
+  return listen<Document>('document-deleted', (event) => fn(event.payload))
+}
+
+export interface RenamedDocument {
+  from: string
+  document: Document
+}
+
+export const onDocumentRenamed = async (
+  fn: (renamed: RenamedDocument) => void,
+): Promise<UnlistenFn> => {
+  return listen<RenamedDocument>('document-renamed', (event) => fn(event.payload))
+}
+
+export type GitFileStatus =
+  | 'modified'
+  | 'added'
+  | 'deleted'
+  | 'renamed'
+  | 'untracked'
+  | 'ignored'
+  | 'conflicted'
+
+export interface GitFileStatusEntry {
+  path: string
+  status: GitFileStatus
+  staged: boolean
+  origPath?: string
+}
+
+export interface GitStatus {
+  worktree: string
+  branch?: string
+  upstream?: string
+  ahead: number
+  behind: number
+  files: GitFileStatusEntry[]
+}
+
+export const gitStatus = async (worktree: string): Promise<GitStatus> => {
+  return invoke('git_status', {worktree})
+}
+
+export interface GitHunk {
+  kind: 'added' | 'modified' | 'deleted'
+  fromLine: number
+  toLine: number
+  oldFromLine: number
+  oldToLine: number
+  oldText: string
+}
+
+export const gitDiffHunks = async (path: string): Promise<GitHunk[]> => {
+  return invoke('git_diff_hunks', {path})
+}
+
+// The change is against the text before the revert
+export interface RevertedHunk {
+  document: Document
+  change: Change
+}
+
+export const gitRevertHunk = async (path: string, line: number): Promise<RevertedHunk> => {
+  return invoke('git_revert_hunk', {path, line})
+}
+
+export interface GitBlameLine {
+  commitId: string
+  author: string
+  date?: Date
+  summary: string
+  committed: boolean
+}
+
+export const gitBlame = async (path: string): Promise<GitBlameLine[]> => {
+  return invoke('git_blame', {path})
+}
+
+export const onGitStatusChanged = async (fn: (status: GitStatus) => void): Promise<UnlistenFn> => {
+  return listen<GitStatus>('git-status-changed', (event) => fn(event.payload))
 }
 
 export const getMimeType = async (path: string): Promise<string> => {
@@ -26,6 +165,10 @@ export const readText = async (path: string): Promise<string> => {
   return invoke('read_text', {path})
 }
 
+export const readLines = async (path: string, start: number, count: number): Promise<Lines> => {
+  return invoke('read_lines', {path, start, count})
+}
+
 export const replaceText = async (
   path: string,
   data: {text: string; language?: string},
@@ -33,25 +176,316 @@ export const replaceText = async (
   return await invoke('replace_text', {path, data})
 }
 
-export const writeFile = async (path: string): Promise<void> => {
-  return await invoke('write_file', {path})
+export interface WriteResult {
+  // Each batch is against the text after the previous one
+  edits: Change[][]
+  version: number
+}
+
+// Explicit saves by the user also run the save-time edits, e.g. formatting
+export const writeFile = async (path: string, explicit = false): Promise<WriteResult> => {
+  return await invoke('write_file', {path, explicit})
 }
 
+// `version` is the document version the edit was made against
 export const insertText = async (
   path: string,
-  data: {fromA: number; toB: number; text: string},
+  version: number,
+  data: {fromA: number; text: string},
+) => {
+  return await invoke('insert_text', {path, version, data})
+}
+
+export const deleteText = async (
+  path: string,
+  version: number,
+  data: {fromA: number; toA: number},
 ) => {
-  return await invoke('insert_text', {path, data})
+  return await invoke('delete_text', {path, version, data})
 }
 
-export const deleteText = async (path: string, data: {fromA: number; toA: number}) => {
-  return await invoke('delete_text', {path, data})
+// Offsets of changes in a page of a large file start at the first `line` of
+// the page
+export const applyChanges = async (
+  path: string,
+  version: number,
+  changes: Change[],
+  line?: number,
+): Promise<Document> => {
+  return await invoke('apply_changes', {path, version, changes, line})
 }
 
 export const getDocument = async (path: string): Promise<Document> => {
   return invoke('get_document', {path})
 }
 
+export interface SearchOptions {
+  ignoreCase?: boolean
+  wholeWord?: boolean
+  regex?: boolean
+}
+
+export interface SearchMatch {
+  from: number
+  to: number
+}
+
+export const searchDocument = async (
+  path: string,
+  query: string,
+  options: SearchOptions = {},
+): Promise<SearchMatch[]> => {
+  return invoke('search_document', {path, query, options})
+}
+
+export const replaceAll = async (
+  path: string,
+  version: number,
+  query: string,
+  replacement: string,
+  options: SearchOptions = {},
+): Promise<Document> => {
+  return invoke('replace_all', {path, version, query, replacement, options})
+}
+
+export interface ListDirectoryOptions {
+  hideIgnored?: boolean
+  showHidden?: boolean
+  depth?: number
+}
+
+export interface DirEntry {
+  name: string
+  path: string
+  kind: 'file' | 'dir' | 'symlink'
+  size: number
+  modified?: Date
+  symlinkTarget?: string
+  isIgnored: boolean
+  isHidden: boolean
+  children?: DirEntry[]
+}
+
+export const listDirectory = async (
+  path: string,
+  options?: ListDirectoryOptions,
+): Promise<DirEntry[]> => {
+  return invoke('list_directory', {path, options})
+}
+
+export interface TrashEntry {
+  id: number
+  path: string
+  deleted: Date
+}
+
+export const createFile = async (path: string): Promise<string> => {
+  return invoke('create_file', {path})
+}
+
+export const createDir = async (path: string): Promise<string> => {
+  return invoke('create_dir', {path})
+}
+
+export const renamePath = async (from: string, to: string): Promise<string> => {
+  return invoke('rename_path', {from, to})
+}
+
+export const movePath = async (from: string, dir: string): Promise<string> => {
+  return invoke('move_path', {from, dir})
+}
+
+export const copyPath = async (from: string, to: string): Promise<string> => {
+  return invoke('copy_path', {from, to})
+}
+
+export const deletePath = async (path: string): Promise<TrashEntry> => {
+  return invoke('delete_path', {path})
+}
+
+export const restoreFromTrash = async (id: number): Promise<string> => {
+  return invoke('restore_from_trash', {id})
+}
+
+export const listTrash = async (): Promise<TrashEntry[]> => {
+  return invoke('list_trash')
+}
+
+export interface FileMatch {
+  path: string
+  relativePath: string
+  score: number
+  positions: number[]
+}
+
+export const findFiles = async (
+  path: string,
+  query: string,
+  limit?: number,
+): Promise<FileMatch[]> => {
+  return invoke('find_files', {path, query, limit})
+}
+
+export interface ProjectSearchOptions extends SearchOptions {
+  include?: string[]
+  exclude?: string[]
+}
+
+export interface LineMatch {
+  line: number
+  preview: string
+  ranges: SearchMatch[]
+}
+
+export interface FileMatches {
+  path: string
+  lines: LineMatch[]
+}
+
+export interface ProjectSearchSummary {
+  files: number
+  matches: number
+}
+
+export const searchProject = async (
+  path: string,
+  query: string,
+  options: ProjectSearchOptions,
+  onEvent: Channel<FileMatches>,
+): Promise<ProjectSearchSummary> => {
+  return invoke('search_project', {path, query, options, onEvent})
+}
+
+// Files that could not be replaced have an error and keep their text
+export interface FileReplacement {
+  path: string
+  matches: number
+  error?: string
+}
+
+export const replaceProject = async (
+  path: string,
+  query: string,
+  replacement: string,
+  options: ProjectSearchOptions = {},
+): Promise<FileReplacement[]> => {
+  return invoke('replace_project', {path, query, replacement, options})
+}
+
+export interface UndoResult {
+  document: Document
+  steps: Change[][]
+}
+
+export const undo = async (path: string): Promise<UndoResult | null> => {
+  return invoke('undo', {path})
+}
+
+export const redo = async (path: string): Promise<UndoResult | null> => {
+  return invoke('redo', {path})
+}
+
+export const setFileFormat = async (path: string, format: FileFormat): Promise<Document> => {
+  return invoke('set_file_format', {path, format})
+}
+
+export const reopenWithEncoding = async (path: string, encoding: string): Promise<Document> => {
+  return invoke('reopen_with_encoding', {path, encoding})
+}
+
+export interface JournalEntry {
+  path: string
+  text: string
+  format: FileFormat
+  version: number
+  journaledAt: Date
+}
+
+export const listRecoverableDocuments = async (): Promise<JournalEntry[]> => {
+  return invoke('list_recoverable_documents')
+}
+
+// Replaces the text of the document with the recovered one. Open editors
+// get it with onDocumentChangedExternally.
+export const restoreDocument = async (path: string): Promise<Document> => {
+  return invoke('restore_document', {path})
+}
+
+export const discardRecoverableDocument = async (path: string): Promise<void> => {
+  return invoke('discard_recoverable_document', {path})
+}
+
+export interface Snapshot {
+  id: number
+  path: string
+  created: Date
+  size: number
+}
+
+export const listHistory = async (path: string): Promise<Snapshot[]> => {
+  return invoke('list_history', {path})
+}
+
+export const readHistory = async (path: string, id: number): Promise<string> => {
+  return invoke('read_history', {path, id})
+}
+
+export const diffHistory = async (path: string, id: number): Promise<string> => {
+  return invoke('diff_history', {path, id})
+}
+
+// Replaces the text with the snapshot as an unsaved edit. Open editors get it
+// with onDocumentChangedExternally.
+export const restoreHistory = async (path: string, id: number): Promise<Document> => {
+  return invoke('restore_history', {path, id})
+}
+
+export interface Retention {
+  maxCount: number
+  maxAgeDays: number
+  maxSize: number
+  snapshotIntervalMinutes?: number
+}
+
+export const getHistoryRetention = async (): Promise<Retention> => {
+  return invoke('get_history_retention')
+}
+
+export const setHistoryRetention = async (retention: Retention): Promise<void> => {
+  return invoke('set_history_retention', {retention})
+}
+
+// The command is only used in worktrees trusted in the app settings
+export interface ProjectLanguageServerSettings {
+  enabled?: boolean
+  command?: string
+  initializationOptions?: unknown
+}
+
+export interface ProjectSettings {
+  worktreePath?: string
+  tabWidth?: number
+  useTabs?: boolean
+  formatOnSave: boolean
+  copilot: boolean
+  exclude: string[]
+  languageServer: ProjectLanguageServerSettings
+}
+
+export const getProjectSettings = async (path: string): Promise<ProjectSettings> => {
+  return invoke('get_project_settings', {path})
+}
+
+export const onProjectSettingsChanged = async (
+  fn: (worktreePath: string) => void,
+): Promise<UnlistenFn> => {
+  return listen<string>('project-settings-changed', (event) => fn(event.payload))
+}
+
+export const listDirtyDocuments = async (): Promise<Document[]> => {
+  return invoke('list_dirty_documents')
+}
+
 export const resolvePath = async (
   path: string,
   basePath: string | undefined = undefined,
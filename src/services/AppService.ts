@@ -1,6 +1,7 @@
 import type {SetStoreFunction, Store} from 'solid-js/store'
 import {DB} from '@/db'
-import {getArgs, setFullscreen} from '@/remote/app'
+import {isTauri} from '@/env'
+import {getArgs, getSettings, setFullscreen} from '@/remote/app'
 import {getDocument} from '@/remote/editor'
 import {info} from '@/remote/log'
 import {createConfig} from '@/state'
@@ -14,7 +15,11 @@ export class AppService {
     const args = (await getArgs().catch(() => undefined)) ?? {}
 
     const fetchedWindow = await DB.getWindow()
-    const fetchedConfig = (await DB.getConfig()) ?? createConfig()
+    // Settings in the backend survive a cleared webview storage
+    const settings = isTauri() ? await getSettings().catch(() => undefined) : undefined
+    const fetchedConfig = settings?.ui
+      ? createConfig(settings.ui)
+      : ((await DB.getConfig()) ?? createConfig())
     const sidebar = await DB.getSidebar()
     const ai = await DB.getAi()
     const lastLocation = await DB.getLastLocation()
@@ -1,9 +1,12 @@
 import {getChunks, unifiedMergeView} from '@codemirror/merge'
+import {Annotation} from '@codemirror/state'
 import {
   EditorView,
   highlightActiveLine,
   highlightActiveLineGutter,
   lineNumbers,
+  type Panel,
+  showPanel,
   type ViewUpdate,
 } from '@codemirror/view'
 import {indentationMarkers} from '@replit/codemirror-indentation-markers'
@@ -11,15 +14,28 @@ import {debounce} from '@solid-primitives/scheduled'
 import {type SetStoreFunction, type Store, unwrap} from 'solid-js/store'
 import {yCollab, ySyncFacet} from 'y-codemirror.next'
 import * as Y from 'yjs'
-import {deleteText, insertText, writeFile} from '@/remote/editor'
-import {info} from '@/remote/log'
+import {
+  applyChanges,
+  type Change,
+  type ChangedDocument,
+  type EditError,
+  getDocument,
+  type Lines,
+  readText,
+  type WriteResult,
+  writeFile,
+} from '@/remote/editor'
+import {error, info, warn} from '@/remote/log'
 import {type File, Page, type SelectionRange, type State, type VisualPositionRange} from '@/types'
 import {CodeMirrorService} from './CodeMirrorService'
 import type {CollabService} from './CollabService'
-import {FileService} from './FileService'
+import {FileService, LARGE_FILE_LINES} from './FileService'
 import type {LocationService} from './LocationService'
 import type {PrettierService} from './PrettierService'
 
+// Marks edits that were already made in the backend
+const backendEdit = Annotation.define<boolean>()
+
 export class CodeService {
   constructor(
     private fileService: FileService,
@@ -33,6 +49,15 @@ export class CodeService {
 
   private writeFileThrottled = debounce(this.writeFile.bind(this), 1000)
 
+  // Last known document version per path, changes are sent in order against it
+  private versions = new Map<string, number>()
+  private pendingChanges = Promise.resolve()
+
+  // Shown page of large files by file id
+  private pages = new Map<string, Lines>()
+  // Files whose editor text is replaced by another page
+  private loadingPages = new Set<string>()
+
   async init(id: string, existingYdoc?: Y.Doc) {
     const file = this.fileService.findFileById(id)
     const share = this.locationService.state?.share
@@ -50,7 +75,9 @@ export class CodeService {
     }
 
     if (path) {
-      text = (await FileService.loadTextFile(path)).text
+      const loaded = await FileService.loadTextFile(path)
+      text = loaded.text
+      if (loaded.lines) this.pages.set(file.id, loaded.lines)
     }
 
     if (!existingYdoc) {
@@ -115,6 +142,31 @@ export class CodeService {
     info(`Updated code text from file`)
   }
 
+  // Shows the lines of a large file from `start` on. Edits of the shown page
+  // are kept by the backend until they are written.
+  async showPage(file: File, start: number) {
+    const path = file.path
+    if (!path) return
+
+    await this.pendingChanges
+    await this.loadPage(file, path, Math.max(0, start))
+  }
+
+  private async loadPage(file: File, path: string, start: number) {
+    const {text, lines} = await FileService.loadTextFile(path, start)
+    if (!lines) return
+
+    this.pages.set(file.id, lines)
+    // The new page is not an edit of the shown one
+    this.loadingPages.add(file.id)
+    try {
+      this.updateText(file, this.collabService.getSubdoc(file.id), text)
+    } finally {
+      this.loadingPages.delete(file.id)
+    }
+    this.updateEditorState(file)
+  }
+
   updateConfig(file: File) {
     this.updateEditorState(file)
   }
@@ -170,13 +222,18 @@ export class CodeService {
       doc = merge.range ? CodeMirrorService.replaceSlice(doc, merge.doc, merge.range) : merge.doc
     }
 
+    const page = this.pages.get(file.id)
     const extensions = [
       ...indentationMarkers({markerType: 'fullScope'}),
       highlightActiveLine(),
       highlightActiveLineGutter(),
-      lineNumbers(),
+      lineNumbers(page ? {formatNumber: (n) => String(page.start + n)} : {}),
     ]
 
+    if (page) {
+      extensions.push(showPanel.of(() => this.createPagePanel(file, page)))
+    }
+
     if (merge) {
       extensions.push(
         unifiedMergeView({original: type.toString()}),
@@ -233,25 +290,166 @@ export class CodeService {
     this.fileService.updateFile(file.id, {codeEditorView: editor.editorView})
   }
 
+  // Saves the file with the save-time edits of the backend and applies them
+  // to the editor
+  async save(file: File) {
+    const path = file.path
+    if (!path) return
+
+    this.writeFileThrottled.clear()
+    await this.pendingChanges
+    const result = await writeFile(path, true)
+    this.applyBackendEdits(file, path, result)
+  }
+
+  // Replaces the editor text with the text of the backend after the file
+  // was changed outside of the editor
+  async reload(file: File, doc: ChangedDocument) {
+    const path = file.path
+    if (!path) return
+
+    await this.pendingChanges
+    if ((this.versions.get(path) ?? -1) >= doc.version) return
+
+    const page = this.pages.get(file.id)
+    if (page) {
+      await this.showPage(file, page.start)
+      this.versions.set(path, doc.version)
+      return
+    }
+
+    const view = file.codeEditorView
+    if (view) {
+      const text = doc.text ?? (await FileService.loadTextFile(path)).text
+      view.dispatch({
+        changes: CodeService.replaceChange(view.state.doc.toString(), text),
+        annotations: backendEdit.of(true),
+      })
+    }
+
+    this.versions.set(path, doc.version)
+  }
+
+  private createPagePanel(file: File, page: Lines): Panel {
+    const end = page.start + page.lines.length
+    const dom = document.createElement('div')
+    const label = document.createElement('span')
+    label.textContent = `Large file. Lines ${page.start + 1}–${end} of ${page.total}`
+    dom.append(label)
+
+    // Edits can change the number of lines in the page
+    const pageEnd = () => page.start + (file.codeEditorView?.state.doc.lines ?? page.lines.length)
+
+    const button = (text: string, start: () => number, disabled: boolean) => {
+      const el = document.createElement('button')
+      el.textContent = text
+      el.disabled = disabled
+      el.onclick = () => this.showPage(file, start())
+      dom.append(el)
+    }
+
+    button('Previous', () => page.start - LARGE_FILE_LINES, page.start === 0)
+    button('Next', pageEnd, end >= page.total)
+    return {top: true, dom}
+  }
+
+  // Replaces only the part that differs, so that the selection is kept
+  private static replaceChange(current: string, text: string) {
+    let start = 0
+    const max = Math.min(current.length, text.length)
+    while (start < max && current[start] === text[start]) start++
+    let end = 0
+    while (
+      end < max - start &&
+      current[current.length - end - 1] === text[text.length - end - 1]
+    ) {
+      end++
+    }
+
+    return {from: start, to: current.length - end, insert: text.slice(start, text.length - end)}
+  }
+
+  private applyBackendEdits(file: File, path: string, result: WriteResult) {
+    for (const changes of result.edits) {
+      file.codeEditorView?.dispatch({
+        changes: changes.map((c) => ({from: c.from, to: c.to, insert: c.text})),
+        annotations: backendEdit.of(true),
+      })
+    }
+
+    this.versions.set(path, result.version)
+  }
+
   private async saveEditor(file: File, update: ViewUpdate) {
+    const loadingPage = this.loadingPages.has(file.id)
+    const page = this.pages.get(file.id)
     await FileService.saveFile(file)
 
     const path = file.path
-    if (path) {
-      update.changes.iterChanges(async (fromA, toA, _fromB, toB, insert) => {
+    const fromBackend = update.transactions.some((tr) => tr.annotation(backendEdit))
+    if (path && !fromBackend && !loadingPage) {
+      const changes: Change[] = []
+      update.changes.iterChanges((fromA, toA, _fromB, _toB, insert) => {
         const text = insert.sliceString(0, insert.length, '\n')
-        if (fromA !== toA) {
-          await deleteText(path, {fromA, toA})
-        }
-        if (text.length > 0) {
-          await insertText(path, {fromA, toB, text})
-        }
+        changes.push({from: fromA, to: toA, text})
       })
 
+      // Pages of large files only send their changes. Writing rewrites the
+      // whole file, so they are only written on explicit saves.
+      if (page) {
+        this.pendingChanges = this.pendingChanges.then(() =>
+          this.applyPageChanges(file, path, page, changes),
+        )
+        await this.pendingChanges
+        return
+      }
+
+      const text = update.state.doc.toString()
+      this.pendingChanges = this.pendingChanges.then(() => this.applyChanges(path, changes, text))
+      await this.pendingChanges
+
       this.writeFileThrottled(file)
     }
   }
 
+  // `text` is the editor text after the changes. It replaces the backend text
+  // if the changes were rejected, so that no edit is lost.
+  private async applyChanges(path: string, changes: Change[], text: string) {
+    try {
+      const version = this.versions.get(path) ?? (await getDocument(path)).version
+      const doc = await applyChanges(path, version, changes)
+      this.versions.set(path, doc.version)
+    } catch (e) {
+      warn(`Could not apply changes, replace backend text (path=${path})`, e)
+      try {
+        const conflict = e as EditError
+        const [version, current] =
+          conflict.kind === 'versionConflict'
+            ? [conflict.currentVersion, conflict.text]
+            : [(await getDocument(path)).version, await readText(path)]
+        const doc = await applyChanges(path, version, [{from: 0, to: current.length, text}])
+        this.versions.set(path, doc.version)
+      } catch (e) {
+        this.versions.delete(path)
+        error(`Could not replace backend text (path=${path})`, e)
+      }
+    }
+  }
+
+  // The whole text of large files is never sent, so rejected changes are
+  // dropped and the page is loaded again
+  private async applyPageChanges(file: File, path: string, page: Lines, changes: Change[]) {
+    try {
+      const version = this.versions.get(path) ?? (await getDocument(path)).version
+      const doc = await applyChanges(path, version, changes, page.start)
+      this.versions.set(path, doc.version)
+    } catch (e) {
+      error(`Could not apply changes to page, reload page (path=${path})`, e)
+      this.versions.delete(path)
+      await this.loadPage(file, path, page.start)
+    }
+  }
+
   private async writeFile(file: File) {
     if (file.path) {
       await writeFile(file.path)
@@ -2,8 +2,8 @@ import {debounce} from '@solid-primitives/scheduled'
 import {batch} from 'solid-js'
 import type {SetStoreFunction, Store} from 'solid-js/store'
 import {DB} from '@/db'
-import {isDark} from '@/env'
-import {setAlwaysOnTop} from '@/remote/app'
+import {isDark, isTauri} from '@/env'
+import {setAlwaysOnTop, updateSettings} from '@/remote/app'
 import {info} from '@/remote/log'
 import type {Config, State, ThemeConfig} from '@/types'
 import type {CollabService} from './CollabService'
@@ -277,6 +277,8 @@ export class ConfigService {
   static readonly BORDER_RADIUS = '10px'
 
   private saveConfigDebounced = debounce((state: State) => this.saveConfig(state), 100)
+  // Revision of the last config sent to the backend
+  private revision = 0
 
   constructor(
     private collabService: CollabService,
@@ -414,8 +416,19 @@ export class ConfigService {
     void this.saveConfigDebounced(this.store)
   }
 
+  // Applies a config that was saved elsewhere, e.g. in the backend settings.
+  // Echoes of own updates are ignored, the config may have changed since.
+  setConfig(config: Config, revision?: number) {
+    if (revision !== undefined && revision <= this.revision) return
+    this.setState('config', config)
+  }
+
   private async saveConfig(state: State) {
     await DB.setConfig(state.config)
+    if (isTauri()) {
+      this.revision++
+      await updateSettings({ui: state.config}, this.revision)
+    }
     info('Config saved')
   }
 }
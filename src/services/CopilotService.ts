@@ -3,7 +3,7 @@ import {createSignal} from 'solid-js'
 import type {SetStoreFunction, Store} from 'solid-js/store'
 import {DB} from '@/db'
 import {isTauri} from '@/env'
-import {open} from '@/remote/app'
+import {open, updateSettings} from '@/remote/app'
 import {
   type CopilotSignIn,
   type CopilotStatus,
@@ -115,6 +115,7 @@ export class CopilotService {
     await DB.setAi(ai)
     if (isTauri()) {
       await disconnectCopilot()
+      await updateSettings({copilot: {enabled: false}})
     }
   }
 
@@ -127,6 +128,7 @@ export class CopilotService {
 
     if (isTauri()) {
       await startLanguageServer()
+      await updateSettings({copilot: {enabled: true}})
       const currentFile = this.fileService.currentFile
       // Register file to LSP server
       if (currentFile?.path) {
@@ -31,6 +31,9 @@ export class EditorService {
 
   private writeFileThrottled = debounce(this.writeFile.bind(this), 1000)
 
+  // Set while the text of the backend is applied, it must not be written back
+  private reloading = false
+
   updateConfig(file: File) {
     this.updateEditorState(file)
   }
@@ -86,6 +89,7 @@ export class EditorService {
 
         this.setState('lastTr', tr.time)
         if (!tr.docChanged) return
+        const reloading = this.reloading
 
         this.fileService.updateFile(file.id, {
           lastModified: new Date(),
@@ -96,7 +100,7 @@ export class EditorService {
         if (!updatedFile) return
 
         await FileService.saveFile(file)
-        this.writeFileThrottled(file)
+        if (!reloading) this.writeFileThrottled(file)
       }
 
       if (!node) return
@@ -200,6 +204,21 @@ export class EditorService {
     Y.applyUpdate(subdoc, update)
   }
 
+  // Replaces the editor content after the file was changed outside of the
+  // editor
+  async reload(file: File) {
+    if (!file.path || !file.editorView) return
+
+    const text = (await FileService.loadMarkdownFile(file.path)).text
+    const subdoc = this.collabService.getSubdoc(file.id)
+    this.reloading = true
+    try {
+      this.updateText(file, subdoc, text)
+    } finally {
+      this.reloading = false
+    }
+  }
+
   selectBox(box: Box, first: boolean, last: boolean) {
     const currentFile = this.fileService.currentFile
     const editorView = currentFile?.editorView
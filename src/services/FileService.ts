@@ -16,6 +16,8 @@ import {
   dirname,
   getDocument,
   getMimeType,
+  type Lines,
+  readLines,
   readText,
   resolvePath,
   toAbsolutePath,
@@ -28,10 +30,15 @@ import type {CollabService} from './CollabService'
 import type {LocationService} from './LocationService'
 import type {TreeService} from './TreeService'
 
+// Number of lines that are loaded at once from files in large file mode
+export const LARGE_FILE_LINES = 10_000
+
 export interface LoadedTextFile {
   text: string
   lastModified: Date
   path: string
+  // The loaded page of a large file
+  lines?: Lines
 }
 
 export interface LoadedMarkdownFile {
@@ -81,7 +88,7 @@ export class FileService {
     return this.files?.findIndex((f) => f.id === fileId) ?? -1
   }
 
-  static async loadTextFile(path: string): Promise<LoadedTextFile> {
+  static async loadTextFile(path: string, start = 0): Promise<LoadedTextFile> {
     debug(`Load text file (path=${path})`)
     let resolvedPath: string
     try {
@@ -91,9 +98,15 @@ export class FileService {
     }
 
     try {
-      const text = await readText(resolvedPath)
       const doc = await getDocument(resolvedPath)
       const lastModified = doc.lastModified
+      // Large files are loaded one page at a time
+      if (doc.largeFile) {
+        const lines = await readLines(resolvedPath, start, LARGE_FILE_LINES)
+        return {text: lines.lines.join('\n'), lastModified, path: resolvedPath, lines}
+      }
+
+      const text = await readText(resolvedPath)
       return {text, lastModified, path: resolvedPath}
     } catch (e: any) {
       throw new Error('Permission denied', e)
@@ -245,7 +258,10 @@ export class FileService {
     let file = await this.findFileByPath(p)
     if (!file) {
       const mime = await getMimeType(p)
-      const code = !mime.startsWith('text/markdown')
+      // Large markdown files are shown as text
+      const code =
+        !mime.startsWith('text/markdown') ||
+        (!!path && isTauri() && (await getDocument(await toAbsolutePath(path))).largeFile)
       file = await this.newFile({newFile, path, code})
     }
 